@@ -14,6 +14,18 @@
 //! # With persistence
 //! usld --db /var/lib/ussl/data.db
 //!
+//! # With persistence, appending each write to an update log
+//! usld --db /var/lib/ussl/data.db --update-log
+//!
+//! # With persistence, keeping at most ~512MB of documents in memory
+//! usld --db /var/lib/ussl/data.db --memory-budget 512 --eviction-policy lfu
+//!
+//! # Allow BACKUP TO / RESTORE FROM on files in a directory
+//! usld --backup-dir /var/lib/ussl/backups
+//!
+//! # Snapshot every 5 minutes, keeping the last 12
+//! usld --snapshot-interval 300 --snapshot-dir /var/lib/ussl/snapshots --snapshot-keep 12
+//!
 //! # With authentication
 //! usld --password mysecret
 //!
@@ -30,15 +42,17 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use clap::Parser;
 use tracing::{info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use ussl_core::DocumentManager;
+use ussl_core::backup::{self, Retention};
+use ussl_core::{CacheConfig, DocumentManager, EvictionPolicy};
 use ussl_storage::SqliteStorage;
-use ussl_transport::{Metrics, MetricsServer, RateLimitConfig, TcpServer, TlsConfig, WebSocketServer};
+use ussl_transport::{Metrics, MetricsServer, Persister, RateLimitConfig, TcpServer, TlsConfig, WebSocketServer};
 
 /// USSL Daemon - Universal State Synchronization Layer
 #[derive(Parser, Debug)]
@@ -100,6 +114,48 @@ struct Args {
     /// Prometheus metrics port (0 = disabled)
     #[arg(long, env = "USSL_METRICS_PORT", default_value = "0")]
     metrics_port: u16,
+
+    /// Append each write's changes to a per-document update log in --db
+    /// instead of rewriting the whole document
+    #[arg(long, env = "USSL_UPDATE_LOG", requires = "db")]
+    update_log: bool,
+
+    /// Seconds between folding update logs into document snapshots (0 = never)
+    #[arg(long, env = "USSL_FOLD_INTERVAL", default_value = "30")]
+    fold_interval: u64,
+
+    /// Only fold update logs with at least this many entries
+    #[arg(long, env = "USSL_FOLD_MIN_ENTRIES", default_value = "1")]
+    fold_min_entries: usize,
+
+    /// Memory budget in MB for resident documents; cold documents are
+    /// evicted to --db and loaded back on demand (0 = keep everything)
+    #[arg(long, env = "USSL_MEMORY_BUDGET", default_value = "0", requires = "db")]
+    memory_budget: usize,
+
+    /// Eviction policy when over the memory budget (lru, lfu)
+    #[arg(long, env = "USSL_EVICTION_POLICY", default_value = "lru")]
+    eviction_policy: EvictionPolicy,
+
+    /// Directory for BACKUP TO and RESTORE FROM files (default: disabled)
+    #[arg(long, env = "USSL_BACKUP_DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Seconds between snapshots written to --snapshot-dir (0 = disabled)
+    #[arg(long, env = "USSL_SNAPSHOT_INTERVAL", default_value = "0", requires = "snapshot_dir")]
+    snapshot_interval: u64,
+
+    /// Directory for scheduled snapshots
+    #[arg(long, env = "USSL_SNAPSHOT_DIR")]
+    snapshot_dir: Option<PathBuf>,
+
+    /// Number of snapshots to keep (0 = keep all)
+    #[arg(long, env = "USSL_SNAPSHOT_KEEP", default_value = "0")]
+    snapshot_keep: usize,
+
+    /// Remove snapshots older than this many seconds (0 = never)
+    #[arg(long, env = "USSL_SNAPSHOT_MAX_AGE", default_value = "0")]
+    snapshot_max_age: u64,
 }
 
 #[tokio::main]
@@ -116,7 +172,7 @@ async fn main() -> Result<()> {
         _ => Level::INFO,
     };
 
-    let subscriber = FmtSubscriber::builder()
+    FmtSubscriber::builder()
         .with_max_level(level)
         .with_target(false)
         .with_thread_ids(false)
@@ -127,13 +183,14 @@ async fn main() -> Result<()> {
     // Print banner
     print_banner();
 
-    // Create shared document manager
-    let manager = Arc::new(DocumentManager::new());
-
     // Initialize SQLite storage if path provided
     let storage = if let Some(db_path) = &args.db {
         info!(path = %db_path.display(), "Initializing SQLite persistence");
         match SqliteStorage::new(db_path) {
+            Ok(storage) if args.update_log => {
+                info!("SQLite persistence enabled with update log");
+                Some(Arc::new(storage.with_update_log()))
+            }
             Ok(storage) => {
                 let storage = Arc::new(storage);
                 info!("SQLite persistence enabled");
@@ -149,6 +206,36 @@ async fn main() -> Result<()> {
         None
     };
 
+    // Both servers queue their writes through one persister, keeping them in
+    // order; in lazy mode evictions are written through it as well
+    let persister = storage.clone().map(|s| Arc::new(Persister::new(s)));
+
+    // Create shared document manager
+    let lazy = storage.is_some() && args.memory_budget > 0;
+    let manager = match persister {
+        Some(ref p) if lazy => {
+            let budget = args.memory_budget * 1024 * 1024;
+            info!(
+                budget_mb = args.memory_budget,
+                policy = %args.eviction_policy,
+                "Lazy loading enabled"
+            );
+            Arc::new(
+                DocumentManager::new()
+                    .with_backing(p.clone(), CacheConfig::new(budget, args.eviction_policy)),
+            )
+        }
+        _ => Arc::new(DocumentManager::new()),
+    };
+
+    // Rehydrate documents before any listener is bound, so clients never
+    // observe a partially recovered keyspace. Lazy mode loads on demand.
+    if let Some(ref s) = storage {
+        if !lazy {
+            ussl_storage::recover(&manager, s.as_ref()).await?;
+        }
+    }
+
     // Initialize TLS if certificate and key provided
     let tls_config = match (&args.tls_cert, &args.tls_key) {
         (Some(cert_path), Some(key_path)) => {
@@ -175,6 +262,13 @@ async fn main() -> Result<()> {
         None
     };
 
+    // Create the backup directory so BACKUP TO works on a fresh install
+    if let Some(ref dir) = args.backup_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create backup directory {}: {}", dir.display(), e))?;
+        info!(dir = %dir.display(), "File backups enabled");
+    }
+
     // Initialize metrics if port specified
     let metrics = if args.metrics_port > 0 {
         let metrics = Arc::new(Metrics::new());
@@ -219,8 +313,8 @@ async fn main() -> Result<()> {
             Some(pwd) => TcpServer::with_password(manager.clone(), tcp_addr, pwd.clone()),
             None => TcpServer::new(manager.clone(), tcp_addr),
         };
-        if let Some(ref s) = storage {
-            tcp_server = tcp_server.with_storage(s.clone());
+        if let Some(ref p) = persister {
+            tcp_server = tcp_server.with_persister(p.clone());
         }
         if let Some(ref tls) = tls_config {
             tcp_server = tcp_server.with_tls(tls.clone());
@@ -228,6 +322,9 @@ async fn main() -> Result<()> {
         if let Some(ref rl) = rate_limit_config {
             tcp_server = tcp_server.with_rate_limit(rl.clone());
         }
+        if let Some(ref dir) = args.backup_dir {
+            tcp_server = tcp_server.with_backup_dir(dir.clone());
+        }
         handles.push(tokio::spawn(async move {
             if let Err(e) = tcp_server.run().await {
                 tracing::error!(error = %e, "TCP server error");
@@ -241,8 +338,8 @@ async fn main() -> Result<()> {
             Some(pwd) => WebSocketServer::with_password(manager.clone(), ws_addr, pwd.clone()),
             None => WebSocketServer::new(manager.clone(), ws_addr),
         };
-        if let Some(ref s) = storage {
-            ws_server = ws_server.with_storage(s.clone());
+        if let Some(ref p) = persister {
+            ws_server = ws_server.with_persister(p.clone());
         }
         if let Some(ref tls) = tls_config {
             ws_server = ws_server.with_tls(tls.clone());
@@ -250,6 +347,9 @@ async fn main() -> Result<()> {
         if let Some(ref rl) = rate_limit_config {
             ws_server = ws_server.with_rate_limit(rl.clone());
         }
+        if let Some(ref dir) = args.backup_dir {
+            ws_server = ws_server.with_backup_dir(dir.clone());
+        }
         handles.push(tokio::spawn(async move {
             if let Err(e) = ws_server.run().await {
                 tracing::error!(error = %e, "WebSocket server error");
@@ -274,6 +374,116 @@ async fn main() -> Result<()> {
         }
     }));
 
+    // Start background eviction task
+    if lazy {
+        let evict_manager = manager.clone();
+        handles.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                if let Err(e) = evict_manager.evict() {
+                    tracing::warn!(error = %e, "Eviction failed");
+                }
+            }
+        }));
+    }
+
+    // Start background update log folding
+    if let (Some(s), true) = (storage.clone(), args.update_log && args.fold_interval > 0) {
+        let period = Duration::from_secs(args.fold_interval);
+        let min_entries = args.fold_min_entries;
+        info!(interval_secs = args.fold_interval, min_entries, "Update log folding enabled");
+        handles.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match ussl_storage::fold_logs(s.as_ref(), min_entries).await {
+                    Ok(stats) if stats.documents > 0 || stats.failed > 0 => {
+                        tracing::info!(
+                            documents = stats.documents,
+                            entries = stats.entries,
+                            failed = stats.failed,
+                            "Folded update logs"
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!(error = %e, "Failed to fold update logs"),
+                }
+            }
+        }));
+    }
+
+    // Start background snapshot task
+    if let (Some(dir), true) = (args.snapshot_dir.clone(), args.snapshot_interval > 0) {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create snapshot directory {}: {}", dir.display(), e))?;
+        let retention = Retention {
+            keep: (args.snapshot_keep > 0).then_some(args.snapshot_keep),
+            max_age: (args.snapshot_max_age > 0).then(|| Duration::from_secs(args.snapshot_max_age)),
+        };
+        info!(
+            dir = %dir.display(),
+            interval_secs = args.snapshot_interval,
+            keep = args.snapshot_keep,
+            max_age_secs = args.snapshot_max_age,
+            "Scheduled snapshots enabled"
+        );
+
+        let snapshot_manager = manager.clone();
+        let snapshot_metrics = metrics.clone();
+        handles.push(tokio::spawn(async move {
+            let period = Duration::from_secs(args.snapshot_interval);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let manager = snapshot_manager.clone();
+                let dir = dir.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    // A failed prune does not undo the snapshot, so it is reported apart
+                    backup::write_snapshot(&manager, &dir)
+                        .map(|info| (info, backup::prune_snapshots(&dir, retention)))
+                })
+                .await;
+
+                match result {
+                    Ok(Ok((info, pruned))) => {
+                        if let Some(ref m) = snapshot_metrics {
+                            m.record_snapshot(&info);
+                        }
+                        tracing::info!(
+                            path = %info.path.display(),
+                            documents = info.documents,
+                            bytes = info.bytes,
+                            duration_ms = info.duration_ms,
+                            "Snapshot saved"
+                        );
+                        match pruned {
+                            Ok(removed) if !removed.is_empty() => {
+                                tracing::info!(removed = removed.len(), "Pruned old snapshots");
+                            }
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!(error = %e, "Failed to prune old snapshots"),
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        if let Some(ref m) = snapshot_metrics {
+                            m.record_snapshot_error();
+                        }
+                        tracing::warn!(error = %e, "Snapshot failed");
+                    }
+                    Err(e) => {
+                        if let Some(ref m) = snapshot_metrics {
+                            m.record_snapshot_error();
+                        }
+                        tracing::warn!(error = %e, "Snapshot task failed");
+                    }
+                }
+            }
+        }));
+    }
+
     // Wait for shutdown signal
     tokio::signal::ctrl_c().await?;
     info!("Shutting down...");
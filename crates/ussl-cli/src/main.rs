@@ -180,9 +180,9 @@ fn execute_command(stream: &mut TcpStream, cmd: &str) -> Result<()> {
         println!("{}", "PONG".green());
     } else if response.starts_with("-ERR") {
         println!("{}", response.red());
-    } else if response.starts_with(':') {
+    } else if let Some(n) = response.strip_prefix(':') {
         // Integer
-        println!("{}", response[1..].yellow());
+        println!("{}", n.yellow());
     } else if response.starts_with('$') {
         // Bulk string
         if response == "$-1" {
@@ -193,9 +193,9 @@ fn execute_command(stream: &mut TcpStream, cmd: &str) -> Result<()> {
             reader.read_line(&mut data)?;
             println!("{}", data.trim());
         }
-    } else if response.starts_with('*') {
+    } else if let Some(n) = response.strip_prefix('*') {
         // Array
-        let count: usize = response[1..].parse().unwrap_or(0);
+        let count: usize = n.parse().unwrap_or(0);
         for i in 0..count {
             let mut line = String::new();
             reader.read_line(&mut line)?;
@@ -221,18 +221,35 @@ fn print_help() {
 
 {}
   CREATE <id> [STRATEGY <s>] [TTL <ms>]  Create a new document
-  GET <id> [PATH <path>]                 Get document or path value
+  GET <id> [PATH <path>] [FORMAT delta]  Get document, path value or text delta
   SET <id> <path> <value>                Set value at path
   DEL <id> [PATH <path>]                 Delete document or path
   KEYS [pattern]                         List document IDs
+  SCAN <cursor> [MATCH p] [COUNT n]      Iterate document IDs (STRATEGY s filters)
 
 {}
-  SUB <pattern>                          Subscribe to document changes
+  SUB <pattern> [PATH <path>] [FROM <v>] Subscribe to document changes
   UNSUB <pattern>                        Unsubscribe from changes
 
 {}
-  PUSH <id> <path> <value>               Append value to array
+  PUSH <id> <path> <value>               Append value to list
+  LINSERT <id> <path> <index> <value>    Insert value into list
+  LREMOVE <id> <path> <index> [count]    Remove items from list
+  LMOVE <id> <path> <from> <to>          Move list item
+  LRANGE <id> <path> [start stop]        Get list items (default: all)
   INC <id> <path> [delta]                Increment counter (default: 1)
+  SADD <id> <path> <value>               Add member to set
+  SREM <id> <path> <value>               Remove member from set
+  SISMEMBER <id> <path> <value>          Check set membership (1/0)
+  SMEMBERS <id> <path>                   List set members
+  TEXT.INSERT <id> <index> <string>      Insert text at character index
+  TEXT.DELETE <id> <index> <len>         Delete characters from text
+  TEXT.FORMAT <id> <index> <len> <json>  Format text range (e.g. {{"bold":true}})
+  TEXT.LEN <id>                          Text length in characters
+  SYNC1 <id> <b64-state-vector>          Get updates missing from a state vector
+  SYNC2 <id> <b64-update>                Send updates missing on the server
+  APPLY <id> <b64-update>                Apply a raw Y.js update
+  STATE <id>                             Get the full Y.js state (base64)
 
 {}
   PRESENCE <id> [DATA <json>]            Get/set presence info
@@ -240,6 +257,9 @@ fn print_help() {
 {}
   PING                                   Check connection
   INFO                                   Server information
+  BACKUP [TO <file> | STREAM]            Export all documents
+  RESTORE <json> | FROM <file> | STREAM  Import documents from a backup
+  LASTSAVE                               Time of the last snapshot
   QUIT                                   Close connection
 
 {}
@@ -250,7 +270,7 @@ fn print_help() {
 {}
   lww          Last-Writer-Wins (default)
   crdt-counter Convergent counter
-  crdt-set     Add/Remove set
+  crdt-set     Observed-remove set (add wins)
   crdt-map     Nested map with LWW per key
   crdt-text    Collaborative text editing
 "#,
@@ -0,0 +1,403 @@
+//! Streaming backup format
+//!
+//! Backups that go to files or are streamed over the wire are written as
+//! newline-delimited JSON: a `BackupHeader` record, then one
+//! `DocumentBackup` per line. Readers never hold more than one record, and
+//! no single line is larger than its biggest document.
+//!
+//! Readers also accept a whole `Backup` object on the first line, which is
+//! what `BACKUP` replies with, so saved replies can be restored the same way.
+//! Scheduled snapshots are written in that form, as `snapshot-<ms>.json`.
+
+use crate::document::DocumentId;
+use crate::error::{Error, Result};
+use crate::manager::{check_backup_version, Backup, DocumentBackup, DocumentManager};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// First record of a streamed backup
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupHeader {
+    pub version: u32,
+    /// Seconds since the epoch when the backup was taken
+    pub timestamp: u64,
+    /// Milliseconds since the epoch of the instant the documents reflect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<u64>,
+    /// Number of document records that follow
+    pub count: usize,
+}
+
+impl BackupHeader {
+    /// Header announcing the documents of `backup`
+    pub fn new(backup: &Backup) -> Self {
+        Self {
+            version: backup.version,
+            timestamp: backup.timestamp,
+            taken_at: backup.taken_at,
+            count: backup.documents.len(),
+        }
+    }
+
+    /// The header as one NDJSON record, without the newline
+    pub fn to_record(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// File name prefix and extension of scheduled snapshots
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+const SNAPSHOT_EXTENSION: &str = ".json";
+
+/// A snapshot written by [`write_snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SnapshotInfo {
+    pub path: PathBuf,
+    /// Milliseconds since the epoch of the instant the snapshot reflects
+    pub timestamp: u64,
+    pub documents: usize,
+    pub bytes: u64,
+    /// Time spent taking and writing the snapshot
+    pub duration_ms: u64,
+}
+
+/// Which snapshots to keep when pruning; unset limits keep everything
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Keep at most this many of the newest snapshots
+    pub keep: Option<usize>,
+    /// Remove snapshots older than this
+    pub max_age: Option<Duration>,
+}
+
+/// Write a full backup of `manager` to a new snapshot file in `dir`
+///
+/// The file appears atomically, and the snapshot is recorded as the
+/// manager's last one (see [`DocumentManager::last_snapshot`]).
+pub fn write_snapshot(manager: &DocumentManager, dir: &Path) -> Result<SnapshotInfo> {
+    let started = Instant::now();
+    let backup = manager.backup()?;
+    let mut timestamp = backup.taken_at.unwrap_or_else(now_ms);
+
+    // Two snapshots within one millisecond get distinct names
+    let mut path = dir.join(snapshot_name(timestamp));
+    while path.exists() {
+        timestamp += 1;
+        path = dir.join(snapshot_name(timestamp));
+    }
+
+    write_atomic(&path, |out| {
+        serde_json::to_writer(&mut *out, &backup).map_err(|e| Error::Serialization(e.to_string()))?;
+        writeln!(out).map_err(io_error)
+    })?;
+
+    let info = SnapshotInfo {
+        bytes: fs::metadata(&path).map_err(io_error)?.len(),
+        path,
+        timestamp,
+        documents: backup.documents.len(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    manager.record_snapshot(info.clone());
+    Ok(info)
+}
+
+/// Remove the snapshots in `dir` that `retention` no longer keeps
+///
+/// The newest snapshot is always kept. Returns the removed files.
+pub fn prune_snapshots(dir: &Path, retention: Retention) -> Result<Vec<PathBuf>> {
+    let mut snapshots: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .map_err(io_error)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let timestamp = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(SNAPSHOT_PREFIX)?
+                .strip_suffix(SNAPSHOT_EXTENSION)?
+                .parse()
+                .ok()?;
+            Some((timestamp, path))
+        })
+        .collect();
+    snapshots.sort_unstable_by(|a, b| b.cmp(a));
+
+    let cutoff = retention
+        .max_age
+        .map(|age| now_ms().saturating_sub(age.as_millis() as u64));
+    let keep = retention.keep.unwrap_or(usize::MAX).max(1);
+
+    let mut removed = Vec::new();
+    for (i, (timestamp, path)) in snapshots.into_iter().enumerate() {
+        let expired = cutoff.is_some_and(|cutoff| timestamp < cutoff);
+        if i > 0 && (i >= keep || expired) {
+            fs::remove_file(&path).map_err(io_error)?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+fn snapshot_name(timestamp: u64) -> String {
+    format!("{}{:013}{}", SNAPSHOT_PREFIX, timestamp, SNAPSHOT_EXTENSION)
+}
+
+/// One document of a backup as an NDJSON record, without the newline
+pub fn document_record(doc_backup: &DocumentBackup) -> Result<String> {
+    serde_json::to_string(doc_backup).map_err(|e| Error::Serialization(e.to_string()))
+}
+
+/// Write `backup` as a streamed backup; returns how many documents were written
+pub fn write_ndjson<W: Write>(backup: &Backup, mut out: W) -> Result<usize> {
+    writeln!(out, "{}", BackupHeader::new(backup).to_record()).map_err(io_error)?;
+    for doc_backup in &backup.documents {
+        writeln!(out, "{}", document_record(doc_backup)?).map_err(io_error)?;
+    }
+    out.flush().map_err(io_error)?;
+    Ok(backup.documents.len())
+}
+
+/// Write `backup` to the file at `path`; returns how many documents were written
+///
+/// The backup goes to `<path>.tmp` first and is renamed into place once
+/// complete, so a failed backup never replaces a good one.
+pub fn write_file(path: &Path, backup: &Backup) -> Result<usize> {
+    write_atomic(path, |out| write_ndjson(backup, out))
+}
+
+/// Write a file through `<path>.tmp`, renamed to `path` once `write` succeeded
+fn write_atomic<T>(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> Result<T>) -> Result<T> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let result = File::create(&tmp)
+        .map_err(io_error)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            let value = write(&mut out)?;
+            out.into_inner().map_err(|e| io_error(e.into_error()))?.sync_all().map_err(io_error)?;
+            Ok(value)
+        })
+        .and_then(|value| fs::rename(&tmp, path).map(|_| value).map_err(io_error));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Restore the backup file at `path`; returns the restored ids
+pub fn read_file(manager: &DocumentManager, path: &Path) -> Result<Vec<DocumentId>> {
+    let file = File::open(path).map_err(io_error)?;
+    read_ndjson(manager, BufReader::new(file))
+}
+
+/// Restore a streamed backup read from `input`; returns the restored ids
+pub fn read_ndjson<R: BufRead>(manager: &DocumentManager, input: R) -> Result<Vec<DocumentId>> {
+    let mut restore = RestoreStream::new();
+    let mut restored = Vec::new();
+    for line in input.lines() {
+        restored.extend(restore.push_line(manager, &line.map_err(io_error)?)?);
+    }
+    restore.finish()?;
+    Ok(restored)
+}
+
+/// Restores a streamed backup fed to it one line at a time
+#[derive(Debug, Default)]
+pub struct RestoreStream {
+    header: Option<BackupHeader>,
+    /// Set once a whole `Backup` object was read instead of a header
+    complete: bool,
+    records: usize,
+    restored: usize,
+}
+
+impl RestoreStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restore the next record into `manager`; returns the ids it restored
+    ///
+    /// Blank lines are ignored.
+    pub fn push_line(&mut self, manager: &DocumentManager, line: &str) -> Result<Vec<DocumentId>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Vec::new());
+        }
+        if self.complete {
+            return Err(Error::RestoreError("Unexpected record after a complete backup".into()));
+        }
+
+        let Some(ref header) = self.header else {
+            return self.start(manager, line);
+        };
+        let version = header.version;
+        let doc_backup: DocumentBackup = serde_json::from_str(line)
+            .map_err(|e| Error::RestoreError(format!("Record {}: {}", self.records + 1, e)))?;
+        self.records += 1;
+        self.restore(manager, version, std::slice::from_ref(&doc_backup))
+    }
+
+    /// Finish the restore and return how many documents it restored
+    ///
+    /// Fails if records announced by the header are missing.
+    pub fn finish(self) -> Result<usize> {
+        match self.header {
+            Some(ref header) if self.records < header.count => Err(Error::RestoreError(format!(
+                "Backup truncated: expected {} documents, got {}",
+                header.count, self.records
+            ))),
+            None if !self.complete => Err(Error::RestoreError("Empty backup".into())),
+            _ => Ok(self.restored),
+        }
+    }
+
+    fn start(&mut self, manager: &DocumentManager, line: &str) -> Result<Vec<DocumentId>> {
+        if let Ok(backup) = serde_json::from_str::<Backup>(line) {
+            check_backup_version(backup.version)?;
+            self.complete = true;
+            return self.restore(manager, backup.version, &backup.documents);
+        }
+
+        let header: BackupHeader = serde_json::from_str(line)
+            .map_err(|e| Error::RestoreError(format!("Invalid backup header: {}", e)))?;
+        check_backup_version(header.version)?;
+        self.header = Some(header);
+        Ok(Vec::new())
+    }
+
+    fn restore(
+        &mut self,
+        manager: &DocumentManager,
+        version: u32,
+        documents: &[DocumentBackup],
+    ) -> Result<Vec<DocumentId>> {
+        let mut restored = Vec::new();
+        for doc_backup in documents {
+            if manager.restore_document(version, doc_backup)? {
+                restored.push(DocumentId::new(&doc_backup.id)?);
+            }
+        }
+        self.restored += restored.len();
+        Ok(restored)
+    }
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    Error::Io(e.to_string())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::{Strategy, Value};
+
+    #[test]
+    fn test_ndjson_round_trip() {
+        let manager = DocumentManager::new();
+        for i in 0..3 {
+            let doc = manager.create(DocumentId::new(format!("nd:{}", i)).unwrap(), Strategy::CrdtMap, None).unwrap();
+            doc.set("n", Value::from(i as i64)).unwrap();
+        }
+
+        let mut out = Vec::new();
+        assert_eq!(write_ndjson(&manager.backup().unwrap(), &mut out).unwrap(), 3);
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert_eq!(text.lines().count(), 4);
+
+        let restored = DocumentManager::new();
+        let ids = read_ndjson(&restored, out.as_slice()).unwrap();
+        assert_eq!(ids.len(), 3);
+        let doc = restored.get(&DocumentId::new("nd:2").unwrap()).unwrap();
+        assert_eq!(doc.get(Some("n")).unwrap(), Value::from(2i64));
+
+        // A truncated stream is reported, not silently accepted
+        let truncated: String = text.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        assert!(read_ndjson(&DocumentManager::new(), truncated.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_backup_file() {
+        let dir = std::env::temp_dir().join(format!("ussl-backup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("docs.ndjson");
+
+        let manager = DocumentManager::new();
+        manager.create(DocumentId::new("file:1").unwrap(), Strategy::CrdtText, None).unwrap();
+        assert_eq!(write_file(&path, &manager.backup().unwrap()).unwrap(), 1);
+        assert!(!dir.join("docs.ndjson.tmp").exists());
+
+        let restored = DocumentManager::new();
+        assert_eq!(read_file(&restored, &path).unwrap().len(), 1);
+        assert!(write_file(&dir.join("missing").join("docs.ndjson"), &manager.backup().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots_and_retention() {
+        let dir = std::env::temp_dir().join(format!("ussl-snapshots-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.txt"), b"not a snapshot").unwrap();
+
+        let manager = DocumentManager::new();
+        manager.create(DocumentId::new("snap:1").unwrap(), Strategy::CrdtCounter, None).unwrap();
+        assert_eq!(manager.last_snapshot(), None);
+        let infos: Vec<SnapshotInfo> = (0..4).map(|_| write_snapshot(&manager, &dir).unwrap()).collect();
+        assert!(infos.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+        assert_eq!(manager.last_snapshot().as_ref(), infos.last());
+        assert_eq!(infos[0].documents, 1);
+
+        // A snapshot restores like a BACKUP reply
+        let restored = DocumentManager::new();
+        assert_eq!(read_file(&restored, &infos[0].path).unwrap().len(), 1);
+
+        let removed = prune_snapshots(&dir, Retention { keep: Some(2), max_age: None }).unwrap();
+        assert_eq!(removed, [infos[1].path.clone(), infos[0].path.clone()]);
+        // Wait out names bumped past the current millisecond
+        std::thread::sleep(Duration::from_millis(10));
+        let removed = prune_snapshots(&dir, Retention { keep: None, max_age: Some(Duration::ZERO) }).unwrap();
+        assert_eq!(removed, [infos[2].path.clone()]);
+        assert!(infos[3].path.exists() && dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reads_whole_backup_object() {
+        let manager = DocumentManager::new();
+        manager.create(DocumentId::new("whole:1").unwrap(), Strategy::Lww, None).unwrap();
+        let json = serde_json::to_string(&manager.backup().unwrap()).unwrap();
+
+        let restored = DocumentManager::new();
+        assert_eq!(read_ndjson(&restored, json.as_bytes()).unwrap().len(), 1);
+        assert!(read_ndjson(&restored, "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_state_is_base64() {
+        let manager = DocumentManager::new();
+        let doc = manager.create(DocumentId::new("b64:1").unwrap(), Strategy::Lww, None).unwrap();
+        doc.set("name", Value::from("Ada")).unwrap();
+        let doc_backup = DocumentBackup::new(&doc);
+
+        let record: serde_json::Value = serde_json::from_str(&document_record(&doc_backup).unwrap()).unwrap();
+        assert_eq!(record["state"], crate::base64::encode(&doc_backup.state));
+
+        // Records written with the state as an array of numbers still load
+        let mut legacy = record.clone();
+        legacy["state"] = serde_json::json!(doc_backup.state);
+        let read: DocumentBackup = serde_json::from_value(legacy).unwrap();
+        assert_eq!(read.state, doc_backup.state);
+    }
+}
@@ -0,0 +1,76 @@
+//! Memory-bounded document caching over a persistent backing store
+
+use crate::document::{DocumentId, DocumentMeta};
+use crate::error::{Error, Result};
+
+/// Order in which resident documents are evicted when over budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Least recently used first
+    #[default]
+    Lru,
+    /// Least frequently used first (ties broken by recency)
+    Lfu,
+}
+
+impl std::fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvictionPolicy::Lru => write!(f, "lru"),
+            EvictionPolicy::Lfu => write!(f, "lfu"),
+        }
+    }
+}
+
+impl std::str::FromStr for EvictionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(EvictionPolicy::Lru),
+            "lfu" => Ok(EvictionPolicy::Lfu),
+            _ => Err(Error::InvalidStrategy(format!("Unknown eviction policy: {}", s))),
+        }
+    }
+}
+
+/// Cache configuration for a backed `DocumentManager`
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Approximate upper bound for resident document snapshots, in bytes
+    pub memory_budget: usize,
+    /// Which documents to evict first
+    pub policy: EvictionPolicy,
+}
+
+impl CacheConfig {
+    pub fn new(memory_budget: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            memory_budget,
+            policy,
+        }
+    }
+}
+
+/// Persistent store that a `DocumentManager` falls through to on a miss
+///
+/// Calls are synchronous because the manager API is; implementations that
+/// wrap async storage are expected to bridge into their runtime.
+/// Snapshots are the bytes produced by `Document::encode_snapshot`.
+pub trait Backing: Send + Sync {
+    /// Load a document snapshot
+    fn load(&self, id: &DocumentId) -> Result<Option<(DocumentMeta, Vec<u8>)>>;
+
+    /// Write a document snapshot
+    fn flush(&self, meta: &DocumentMeta, snapshot: &[u8]) -> Result<()>;
+
+    /// Remove a document
+    fn delete(&self, id: &DocumentId) -> Result<()>;
+
+    /// List stored document IDs matching a pattern
+    fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>>;
+
+    /// Metadata of up to `count` stored documents with ids greater than
+    /// `after` that match a pattern, in id order
+    fn scan(&self, after: Option<&str>, pattern: Option<&str>, count: usize) -> Result<Vec<DocumentMeta>>;
+}
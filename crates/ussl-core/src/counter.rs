@@ -0,0 +1,149 @@
+//! PN-Counter CRDT stored inside a document's Y.Doc
+//!
+//! Every replica (Y.Doc client) owns two monotonically growing entries per
+//! counter path: `<client>:p:<path>` for increments and `<client>:n:<path>`
+//! for decrements. A replica only ever writes its own entries, so concurrent
+//! updates from different replicas never overwrite each other and merge
+//! through the regular Y.js update exchange. The value of a counter is the
+//! sum of all `p` entries minus the sum of all `n` entries for its path.
+//!
+//! Entries live in a single flat map (rather than a nested map per path) so
+//! that two replicas touching a new path concurrently cannot race on creating
+//! the container.
+
+use crate::crdt::is_within;
+use std::collections::BTreeMap;
+use yrs::types::Value as YValue;
+use yrs::{Doc, Map, MapRef, ReadTxn, Transact, TransactionMut};
+
+/// Name of the root Y.Map holding counter entries
+const COUNTERS: &str = "counters";
+
+/// Add `delta` to the counter at `path` on behalf of this replica
+///
+/// Returns the merged counter value after the update.
+pub(crate) fn increment(doc: &Doc, path: &str, delta: i64) -> i64 {
+    let counters = doc.get_or_insert_map(COUNTERS);
+    let mut txn = doc.transact_mut();
+
+    let kind = if delta >= 0 { 'p' } else { 'n' };
+    let key = format!("{}:{}:{}", doc.client_id(), kind, path);
+    let current = read_entry(&counters, &txn, &key);
+    let amount = i64::try_from(delta.unsigned_abs()).unwrap_or(i64::MAX);
+    counters.insert(&mut txn, key, current.saturating_add(amount));
+
+    totals_in(&counters, &txn)
+        .get(path)
+        .copied()
+        .unwrap_or(0)
+}
+
+/// Merged value of every counter path in the document
+pub(crate) fn totals(doc: &Doc) -> BTreeMap<String, i64> {
+    // Looked up in a read-only transaction so concurrent readers never
+    // contend for the exclusive one that get_or_insert_map opens
+    let txn = doc.transact();
+    txn.get_map(COUNTERS)
+        .map(|counters| totals_in(&counters, &txn))
+        .unwrap_or_default()
+}
+
+/// Drop counters at `path` (and below it), or all counters if `path` is None
+///
+/// Returns true if anything was removed.
+pub(crate) fn remove(doc: &Doc, path: Option<&str>) -> bool {
+    let counters = doc.get_or_insert_map(COUNTERS);
+    let mut txn = doc.transact_mut();
+
+    let doomed: Vec<String> = counters
+        .iter(&txn)
+        .filter_map(|(key, _)| {
+            let (_, _, entry_path) = split_key(key)?;
+            path.is_none_or(|p| is_within(entry_path, p))
+                .then(|| key.to_string())
+        })
+        .collect();
+
+    for key in &doomed {
+        counters.remove(&mut txn, key);
+    }
+    !doomed.is_empty()
+}
+
+fn totals_in<T: ReadTxn>(counters: &MapRef, txn: &T) -> BTreeMap<String, i64> {
+    let mut totals = BTreeMap::new();
+    for (key, value) in counters.iter(txn) {
+        let Some((_, kind, path)) = split_key(key) else {
+            continue;
+        };
+        let amount = as_i64(value);
+        let total = totals.entry(path.to_string()).or_insert(0i64);
+        *total = match kind {
+            "p" => total.saturating_add(amount),
+            _ => total.saturating_sub(amount),
+        };
+    }
+    totals
+}
+
+fn read_entry(counters: &MapRef, txn: &TransactionMut, key: &str) -> i64 {
+    counters.get(txn, key).map(as_i64).unwrap_or(0)
+}
+
+fn as_i64(value: YValue) -> i64 {
+    match value {
+        YValue::Any(any) => i64::try_from(any).unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Split `<client>:<p|n>:<path>`; the path itself may contain colons
+fn split_key(key: &str) -> Option<(&str, &str, &str)> {
+    let mut parts = key.splitn(3, ':');
+    let client = parts.next()?;
+    let kind = parts.next()?;
+    let path = parts.next()?;
+    matches!(kind, "p" | "n").then_some((client, kind, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::updates::decoder::Decode;
+    use yrs::{StateVector, Update};
+
+    fn sync(from: &Doc, to: &Doc) {
+        let update = from.transact().encode_state_as_update_v1(&StateVector::default());
+        to.transact_mut().apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_increments_converge() {
+        let a = Doc::new();
+        let b = Doc::new();
+
+        increment(&a, "views", 5);
+        increment(&b, "views", 3);
+        increment(&b, "views", -1);
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        assert_eq!(totals(&a).get("views"), Some(&7));
+        assert_eq!(totals(&b).get("views"), Some(&7));
+    }
+
+    #[test]
+    fn test_remove_nested_paths() {
+        let doc = Doc::new();
+        increment(&doc, "stats.views", 2);
+        increment(&doc, "stats.likes", 1);
+        increment(&doc, "statsx", 4);
+
+        assert!(remove(&doc, Some("stats")));
+
+        let totals = totals(&doc);
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals.get("statsx"), Some(&4));
+    }
+}
@@ -4,14 +4,15 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Conflict resolution strategy for a document
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Strategy {
     /// Last-Writer-Wins based on timestamp
+    #[default]
     Lww,
-    /// Convergent counter operations
+    /// Convergent counter operations (per-replica PN-counter)
     CrdtCounter,
-    /// Add/Remove set operations
+    /// Observed-remove set (concurrent add wins)
     CrdtSet,
     /// Nested map with LWW per key
     CrdtMap,
@@ -19,12 +20,6 @@ pub enum Strategy {
     CrdtText,
 }
 
-impl Default for Strategy {
-    fn default() -> Self {
-        Self::Lww
-    }
-}
-
 impl std::fmt::Display for Strategy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -53,9 +48,10 @@ impl std::str::FromStr for Strategy {
 }
 
 /// A value that can be stored in a document
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Value {
+    #[default]
     Null,
     Bool(bool),
     Number(Number),
@@ -185,12 +181,6 @@ impl Value {
     }
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::Null
-    }
-}
-
 impl From<bool> for Value {
     fn from(v: bool) -> Self {
         Value::Bool(v)
@@ -235,6 +225,15 @@ pub enum Number {
     Float(f64),
 }
 
+/// Whether `path` is `parent` itself or nested below it
+/// (e.g. `stats.views` and `stats[0]` are within `stats`, `statsx` is not)
+pub fn is_within(path: &str, parent: &str) -> bool {
+    match path.strip_prefix(parent) {
+        Some(rest) => rest.is_empty() || rest.starts_with('.') || rest.starts_with('['),
+        None => false,
+    }
+}
+
 /// Path segment for navigating document structure
 #[derive(Debug, Clone)]
 pub enum PathSegment<'a> {
@@ -285,7 +284,7 @@ impl<'a> Iterator for PathParser<'a> {
 
         // Find next delimiter
         let end = remaining
-            .find(|c| c == '.' || c == '[')
+            .find(['.', '['])
             .unwrap_or(remaining.len());
 
         let key = &remaining[..end];
@@ -1,13 +1,21 @@
 //! Document types and operations
 
-use crate::crdt::{Strategy, Value};
+use crate::counter;
+use crate::crdt::{Number, Strategy, Value};
+use crate::list;
+use crate::map;
+use crate::orset;
+use crate::text;
 use crate::error::{Error, Result};
+use crate::manager::{Delta, EventKind};
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use parking_lot::RwLock;
-use yrs::{Doc, Text, Transact, ReadTxn, GetString};
+use parking_lot::{Mutex, RwLock};
+use yrs::{Doc, Subscription, Transact, ReadTxn};
 use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
 
 /// Maximum document size in bytes (16MB default)
 pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
@@ -21,6 +29,19 @@ pub const COMPACTION_THRESHOLD: u64 = 1000;
 /// Size threshold in bytes for compaction (1MB)
 pub const COMPACTION_SIZE_THRESHOLD: usize = 1024 * 1024;
 
+/// Pending updates above this size are replaced by a full snapshot (1MB)
+const MAX_PENDING_SIZE: usize = 1024 * 1024;
+
+/// Number of recent deltas kept per document for `deltas_since`
+const UPDATE_LOG_LEN: usize = 128;
+
+/// Bytes of recent deltas kept per document for `deltas_since` (1MB)
+const UPDATE_LOG_SIZE: usize = 1024 * 1024;
+
+/// Prefix of blobs produced by `Document::encode_snapshot`.
+/// Blobs without it are treated as bare Y.js updates (pre-snapshot format).
+const SNAPSHOT_MAGIC: &[u8; 4] = b"USN1";
+
 /// Document identifier - UTF-8 string, max 512 bytes
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct DocumentId(String);
@@ -117,6 +138,11 @@ impl DocumentMeta {
         }
     }
 
+    /// When the document expires (in ms since the epoch), or None if no TTL
+    pub fn expires_at(&self) -> Option<u64> {
+        self.ttl.map(|ttl_ms| self.created_at + ttl_ms)
+    }
+
     /// Get time remaining before expiration (in ms), or None if no TTL
     pub fn ttl_remaining(&self) -> Option<i64> {
         self.ttl.map(|ttl_ms| {
@@ -130,6 +156,27 @@ impl DocumentMeta {
     }
 }
 
+/// One change to a document, as appended to a storage update log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Change {
+    /// A Y.js update
+    Update(Vec<u8>),
+    /// The plain (LWW) value written at `path`
+    Lww { path: String, value: Value },
+}
+
+impl Change {
+    /// Encode for storage
+    pub fn encode(&self) -> Vec<u8> {
+        rmp_serde::to_vec(self).unwrap_or_default()
+    }
+
+    /// Decode a change produced by `encode`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        rmp_serde::from_slice(bytes).map_err(|e| Error::RestoreError(e.to_string()))
+    }
+}
+
 /// A synchronized document with CRDT support
 pub struct Document {
     meta: RwLock<DocumentMeta>,
@@ -137,35 +184,173 @@ pub struct Document {
     ydoc: RwLock<Doc>,
     /// LWW fallback for simple key-value
     lww_data: RwLock<Value>,
+    /// Whether `ydoc` holds any list, so documents without one skip it
+    has_lists: AtomicBool,
     /// Number of updates applied (for compaction heuristics)
     update_count: AtomicU64,
     /// Number of compactions performed
     compaction_count: AtomicU64,
+    /// Manager tick of the most recent access (for LRU eviction)
+    last_access: AtomicU64,
+    /// Number of accesses since load (for LFU eviction)
+    access_count: AtomicU64,
+    /// Size of the last snapshot encoded by `snapshot_size`, with the
+    /// version and compaction count it was measured at
+    snapshot_size: Mutex<Option<((u64, u64), usize)>>,
+    /// Y.js updates produced since the last `take_delta`
+    pending: Arc<Mutex<PendingUpdates>>,
+    /// Keeps the update observer on `ydoc` registered
+    observer: Mutex<Option<Subscription>>,
+    /// Recently published deltas, for subscribers catching up
+    log: Mutex<UpdateLog>,
+}
+
+/// Y.js updates collected between two deltas
+#[derive(Default)]
+struct PendingUpdates {
+    updates: Vec<Vec<u8>>,
+    size: usize,
+    /// Subscribers need the full state (history was rewritten or too much piled up)
+    snapshot: bool,
+}
+
+/// Bounded log of the deltas published for a document, oldest first
+struct UpdateLog {
+    entries: std::collections::VecDeque<Delta>,
+    size: usize,
+    /// Version the document had before the oldest entry's changes
+    floor: u64,
+}
+
+impl UpdateLog {
+    fn new(floor: u64) -> Self {
+        Self {
+            entries: std::collections::VecDeque::new(),
+            size: 0,
+            floor,
+        }
+    }
+
+    fn push(&mut self, delta: Delta) {
+        // A full state supersedes everything before it
+        if delta.snapshot {
+            self.entries.clear();
+            self.size = 0;
+            self.floor = 0;
+        }
+        self.size += delta.data.len();
+        self.entries.push_back(delta);
+
+        while self.entries.len() > UPDATE_LOG_LEN || (self.size > UPDATE_LOG_SIZE && self.entries.len() > 1) {
+            let Some(oldest) = self.entries.pop_front() else {
+                break;
+            };
+            self.size -= oldest.data.len();
+            self.floor = oldest.version;
+        }
+    }
 }
 
 impl Document {
     /// Create a new document with the given ID and strategy
     pub fn new(id: DocumentId, strategy: Strategy) -> Self {
-        Self {
-            meta: RwLock::new(DocumentMeta::new(id, strategy)),
-            ydoc: RwLock::new(Doc::new()),
-            lww_data: RwLock::new(Value::Object(std::collections::HashMap::new())),
-            update_count: AtomicU64::new(0),
-            compaction_count: AtomicU64::new(0),
-        }
+        Self::build(DocumentMeta::new(id, strategy), Doc::new(), Value::Object(std::collections::HashMap::new()))
     }
 
     /// Create a new document with TTL (time-to-live in milliseconds)
     pub fn with_ttl(id: DocumentId, strategy: Strategy, ttl_ms: u64) -> Self {
+        Self::build(
+            DocumentMeta::with_ttl(id, strategy, ttl_ms),
+            Doc::new(),
+            Value::Object(std::collections::HashMap::new()),
+        )
+    }
+
+    fn build(meta: DocumentMeta, ydoc: Doc, lww_data: Value) -> Self {
+        let pending = Arc::new(Mutex::new(PendingUpdates::default()));
+        let observer = observe(&ydoc, &pending);
+        let log = UpdateLog::new(meta.version);
+        let has_lists = list::exists(&ydoc);
         Self {
-            meta: RwLock::new(DocumentMeta::with_ttl(id, strategy, ttl_ms)),
-            ydoc: RwLock::new(Doc::new()),
-            lww_data: RwLock::new(Value::Object(std::collections::HashMap::new())),
+            meta: RwLock::new(meta),
+            ydoc: RwLock::new(ydoc),
+            lww_data: RwLock::new(lww_data),
+            has_lists: AtomicBool::new(has_lists),
             update_count: AtomicU64::new(0),
             compaction_count: AtomicU64::new(0),
+            last_access: AtomicU64::new(0),
+            access_count: AtomicU64::new(0),
+            snapshot_size: Mutex::new(None),
+            pending,
+            observer: Mutex::new(observer),
+            log: Mutex::new(log),
         }
     }
 
+    /// Rebuild a document from persisted metadata and a snapshot
+    ///
+    /// `snapshot` is either the output of `encode_snapshot` or a bare Y.js
+    /// update as written by older versions. Unlike `apply_state`, this keeps
+    /// the stored version and timestamps untouched so a reloaded document
+    /// looks exactly as it did before.
+    pub fn from_snapshot(meta: DocumentMeta, snapshot: &[u8]) -> Result<Self> {
+        let (state, data) = decode_snapshot(snapshot)?;
+
+        let ydoc = Doc::new();
+        if !state.is_empty() {
+            let mut txn = ydoc.transact_mut();
+            let decoded = yrs::Update::decode_v1(state)
+                .map_err(|e: yrs::encoding::read::Error| Error::RestoreError(e.to_string()))?;
+            txn.apply_update(decoded);
+        }
+
+        // Map documents written before crdt-map moved into the Y.Doc kept
+        // their content in the LWW data
+        let data = if meta.strategy == Strategy::CrdtMap && data.as_object().is_some_and(|o| !o.is_empty()) {
+            map::set(&ydoc, "", data)?;
+            Value::Object(std::collections::HashMap::new())
+        } else {
+            data
+        };
+
+        Ok(Self::build(meta, ydoc, data))
+    }
+
+    /// Rebuild a document from a snapshot and the changes logged after it
+    ///
+    /// Like `from_snapshot`, the version and timestamps come from `meta`.
+    pub fn replay(meta: DocumentMeta, snapshot: &[u8], changes: &[Change]) -> Result<Self> {
+        let doc = Self::from_snapshot(meta, snapshot)?;
+
+        let updates: Vec<&[u8]> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Update(update) => Some(update.as_slice()),
+                Change::Lww { .. } => None,
+            })
+            .collect();
+        if !updates.is_empty() {
+            let merged = yrs::merge_updates_v1(&updates).map_err(|e| Error::RestoreError(e.to_string()))?;
+            let decoded = yrs::Update::decode_v1(&merged)
+                .map_err(|e: yrs::encoding::read::Error| Error::RestoreError(e.to_string()))?;
+            let ydoc = doc.ydoc.write();
+            ydoc.transact_mut().apply_update(decoded);
+            doc.note_lists(&ydoc);
+        }
+
+        let mut data = doc.lww_data.write();
+        for change in changes {
+            if let Change::Lww { path, value } = change {
+                data.set_path(path, value.clone())?;
+            }
+        }
+        drop(data);
+
+        // Replayed updates are history, not news for subscribers
+        *doc.pending.lock() = PendingUpdates::default();
+        Ok(doc)
+    }
+
     /// Get the document ID
     pub fn id(&self) -> DocumentId {
         self.meta.read().id.clone()
@@ -204,32 +389,41 @@ impl Document {
 
     /// Get a value at the given path
     pub fn get(&self, path: Option<&str>) -> Result<Value> {
-        let strategy = self.strategy();
+        let ydoc = self.ydoc.read();
 
-        match strategy {
-            Strategy::Lww | Strategy::CrdtMap => {
-                let data = self.lww_data.read();
-                match path {
-                    Some(p) => data.get_path(p)
-                        .cloned()
-                        .ok_or_else(|| Error::InvalidPath(p.to_string())),
-                    None => Ok(data.clone()),
-                }
-            }
-            Strategy::CrdtText => {
-                let ydoc = self.ydoc.read();
-                let text = ydoc.get_or_insert_text("content");
-                let txn = ydoc.transact();
-                Ok(Value::String(text.get_string(&txn)))
-            }
+        let mut data = match self.strategy() {
+            Strategy::CrdtText => return Ok(Value::String(text::content(&ydoc))),
+            Strategy::Lww => self.lww_data.read().clone(),
+            Strategy::CrdtMap => map::get(&ydoc, None)?,
             Strategy::CrdtCounter => {
-                let data = self.lww_data.read();
-                Ok(data.clone())
+                // Plain values set on the document, overlaid with counters
+                let mut data = self.lww_data.read().clone();
+                for (counter_path, total) in counter::totals(&ydoc) {
+                    data.set_path(&counter_path, Value::Number(Number::Integer(total)))?;
+                }
+                data
             }
             Strategy::CrdtSet => {
-                let data = self.lww_data.read();
-                Ok(data.clone())
+                // Plain values set on the document, overlaid with sets as arrays
+                let mut data = self.lww_data.read().clone();
+                for (set_path, members) in orset::all(&ydoc) {
+                    data.set_path(&set_path, Value::Array(members))?;
+                }
+                data
             }
+        };
+
+        if self.has_lists() {
+            for (list_path, items) in list::all(&ydoc) {
+                data.set_path(&list_path, Value::Array(items))?;
+            }
+        }
+
+        match path {
+            Some(p) => data.get_path(p)
+                .cloned()
+                .ok_or_else(|| Error::InvalidPath(p.to_string())),
+            None => Ok(data),
         }
     }
 
@@ -237,41 +431,54 @@ impl Document {
     pub fn set(&self, path: &str, value: Value) -> Result<()> {
         let strategy = self.strategy();
 
-        match strategy {
-            Strategy::Lww | Strategy::CrdtMap => {
-                let mut data = self.lww_data.write();
-                data.set_path(path, value)?;
-                self.update_version();
-                Ok(())
-            }
-            Strategy::CrdtText => {
-                if let Value::String(text_value) = value {
-                    let ydoc = self.ydoc.write();
-                    let ytext = ydoc.get_or_insert_text("content");
-                    let mut txn = ydoc.transact_mut();
-                    let current_len = ytext.get_string(&txn).len() as u32;
-                    ytext.remove_range(&mut txn, 0, current_len);
-                    ytext.insert(&mut txn, 0, &text_value);
-                    drop(txn);
-                    drop(ydoc);
-                    self.increment_update_count();
-                    self.update_version();
-                    Ok(())
-                } else {
-                    Err(Error::Crdt("CrdtText strategy requires string values".into()))
-                }
-            }
-            _ => {
-                let mut data = self.lww_data.write();
-                data.set_path(path, value)?;
-                self.update_version();
-                Ok(())
-            }
+        if strategy == Strategy::CrdtText {
+            let Value::String(text_value) = value else {
+                return Err(Error::Crdt("CrdtText strategy requires string values".into()));
+            };
+            text::replace(&self.ydoc.write(), &text_value);
+            self.increment_update_count();
+            self.update_version();
+            return Ok(());
+        }
+
+        // A plain value replaces any list at or below the path
+        if self.has_lists() && list::clear(&self.ydoc.write(), Some(path)) {
+            self.increment_update_count();
+        }
+
+        if strategy == Strategy::CrdtMap {
+            map::set(&self.ydoc.write(), path, value)?;
+            self.increment_update_count();
+        } else {
+            self.lww_data.write().set_path(path, value)?;
         }
+        self.update_version();
+        Ok(())
     }
 
     /// Delete a value at the given path (or the entire document content)
     pub fn delete(&self, path: Option<&str>) -> Result<()> {
+        let ydoc = self.ydoc.write();
+        let mut removed = self.has_lists() && list::clear(&ydoc, path);
+
+        if self.strategy() == Strategy::CrdtMap {
+            removed |= map::delete(&ydoc, path)?;
+            if removed {
+                self.increment_update_count();
+            }
+            self.update_version();
+            return Ok(());
+        }
+
+        removed |= match self.strategy() {
+            Strategy::CrdtCounter => counter::remove(&ydoc, path),
+            Strategy::CrdtSet => orset::clear(&ydoc, path),
+            _ => false,
+        };
+        if removed {
+            self.increment_update_count();
+        }
+
         match path {
             Some(p) => {
                 let mut data = self.lww_data.write();
@@ -288,32 +495,103 @@ impl Document {
         }
     }
 
-    /// Push a value to an array at the given path
+    /// Push a value to the list at the given path
     pub fn push(&self, path: &str, value: Value) -> Result<()> {
-        let mut data = self.lww_data.write();
+        self.require_lists()?;
+        let ydoc = self.ydoc.write();
+        self.has_lists.store(true, Ordering::Relaxed);
+        self.adopt_array(&ydoc, path)?;
+        list::push(&ydoc, path, value);
+        drop(ydoc);
+        self.increment_update_count();
+        self.update_version();
+        Ok(())
+    }
 
-        // Get or create array at path
-        let arr = match data.get_path(path) {
-            Some(Value::Array(_)) => {}
-            Some(_) => return Err(Error::InvalidPath(format!("{} is not an array", path))),
-            None => {
-                data.set_path(path, Value::Array(vec![]))?;
-            }
-        };
+    /// Insert a value into the list at the given path before `index`
+    ///
+    /// Returns the new length of the list.
+    pub fn list_insert(&self, path: &str, index: u32, value: Value) -> Result<u32> {
+        self.require_lists()?;
+        let ydoc = self.ydoc.write();
+        self.has_lists.store(true, Ordering::Relaxed);
+        self.adopt_array(&ydoc, path)?;
+        let len = list::insert(&ydoc, path, index, value)?;
+        drop(ydoc);
+        self.increment_update_count();
+        self.update_version();
+        Ok(len)
+    }
 
-        // Navigate to the array and push
-        if let Some(Value::Array(arr)) = data.get_path(path).cloned() {
-            let mut new_arr = arr;
-            new_arr.push(value);
-            data.set_path(path, Value::Array(new_arr))?;
-        }
+    /// Remove up to `count` items starting at `index` from the list at the given path
+    ///
+    /// Returns the number of items removed.
+    pub fn list_remove(&self, path: &str, index: u32, count: u32) -> Result<u32> {
+        self.require_lists()?;
+        let ydoc = self.ydoc.write();
+        self.has_lists.store(true, Ordering::Relaxed);
+        self.adopt_array(&ydoc, path)?;
+        let removed = list::remove(&ydoc, path, index, count)?;
+        drop(ydoc);
+        self.increment_update_count();
+        self.update_version();
+        Ok(removed)
+    }
 
+    /// Move the list item at `from` to index `to`
+    pub fn list_move(&self, path: &str, from: u32, to: u32) -> Result<()> {
+        self.require_lists()?;
+        let ydoc = self.ydoc.write();
+        self.has_lists.store(true, Ordering::Relaxed);
+        self.adopt_array(&ydoc, path)?;
+        list::move_item(&ydoc, path, from, to)?;
+        drop(ydoc);
+        self.increment_update_count();
         self.update_version();
         Ok(())
     }
 
+    /// List items between `start` and `stop` inclusive (negative counts from the end)
+    pub fn list_range(&self, path: &str, start: i64, stop: i64) -> Result<Vec<Value>> {
+        self.require_lists()?;
+        let ydoc = self.ydoc.read();
+        if list::items(&ydoc, path).is_empty() {
+            // Arrays written as plain values before they were used as a list
+            drop(ydoc);
+            return match self.get(Some(path)) {
+                Ok(Value::Array(items)) => Ok(list::slice(&items, start, stop)),
+                _ => Ok(Vec::new()),
+            };
+        }
+        Ok(list::range(&ydoc, path, start, stop))
+    }
+
     /// Increment a counter at the given path
+    ///
+    /// On `CrdtCounter` documents this updates a PN-counter that merges
+    /// across replicas; other strategies do a plain read-modify-write.
     pub fn increment(&self, path: &str, delta: i64) -> Result<i64> {
+        if self.strategy() == Strategy::CrdtCounter {
+            let new_value = counter::increment(&self.ydoc.write(), path, delta);
+            self.increment_update_count();
+            self.update_version();
+            return Ok(new_value);
+        }
+
+        if self.strategy() == Strategy::CrdtMap {
+            let ydoc = self.ydoc.write();
+            let current = map::get(&ydoc, Some(path))
+                .ok()
+                .and_then(|v| v.as_i64())
+                .unwrap_or(0);
+            let new_value = current + delta;
+            map::set(&ydoc, path, Value::Number(Number::Integer(new_value)))?;
+            drop(ydoc);
+            self.increment_update_count();
+            self.update_version();
+            return Ok(new_value);
+        }
+
         let mut data = self.lww_data.write();
 
         let current = data.get_path(path)
@@ -321,12 +599,94 @@ impl Document {
             .unwrap_or(0);
 
         let new_value = current + delta;
-        data.set_path(path, Value::Number(crate::crdt::Number::Integer(new_value)))?;
+        data.set_path(path, Value::Number(Number::Integer(new_value)))?;
 
         self.update_version();
         Ok(new_value)
     }
 
+    /// Add a member to the set at the given path (add-wins OR-Set)
+    ///
+    /// Returns true if the member was not already present.
+    pub fn set_add(&self, path: &str, member: Value) -> Result<bool> {
+        self.require_strategy(Strategy::CrdtSet)?;
+        let added = orset::add(&self.ydoc.write(), path, &member);
+        self.increment_update_count();
+        self.update_version();
+        Ok(added)
+    }
+
+    /// Remove a member from the set at the given path
+    ///
+    /// Returns true if the member was present.
+    pub fn set_remove(&self, path: &str, member: &Value) -> Result<bool> {
+        self.require_strategy(Strategy::CrdtSet)?;
+        let removed = orset::remove(&self.ydoc.write(), path, member);
+        if removed {
+            self.increment_update_count();
+            self.update_version();
+        }
+        Ok(removed)
+    }
+
+    /// Check whether a member is in the set at the given path
+    pub fn set_contains(&self, path: &str, member: &Value) -> Result<bool> {
+        self.require_strategy(Strategy::CrdtSet)?;
+        Ok(orset::contains(&self.ydoc.read(), path, member))
+    }
+
+    /// List the members of the set at the given path
+    pub fn set_members(&self, path: &str) -> Result<Vec<Value>> {
+        self.require_strategy(Strategy::CrdtSet)?;
+        Ok(orset::all(&self.ydoc.read()).remove(path).unwrap_or_default())
+    }
+
+    /// Insert text before the character at `index`
+    pub fn text_insert(&self, index: u32, chunk: &str) -> Result<()> {
+        self.require_strategy(Strategy::CrdtText)?;
+        text::insert(&self.ydoc.write(), index, chunk)?;
+        self.increment_update_count();
+        self.update_version();
+        Ok(())
+    }
+
+    /// Delete `len` characters starting at `index`
+    pub fn text_delete(&self, index: u32, len: u32) -> Result<()> {
+        self.require_strategy(Strategy::CrdtText)?;
+        text::delete(&self.ydoc.write(), index, len)?;
+        self.increment_update_count();
+        self.update_version();
+        Ok(())
+    }
+
+    /// Apply formatting attributes (bold, link, ...) to a range of characters
+    ///
+    /// A `Null` attribute value removes that attribute from the range.
+    pub fn text_format(
+        &self,
+        index: u32,
+        len: u32,
+        attributes: std::collections::HashMap<String, Value>,
+    ) -> Result<()> {
+        self.require_strategy(Strategy::CrdtText)?;
+        text::format(&self.ydoc.write(), index, len, attributes)?;
+        self.increment_update_count();
+        self.update_version();
+        Ok(())
+    }
+
+    /// Text content and formatting as a Quill-style delta
+    pub fn text_delta(&self) -> Result<Value> {
+        self.require_strategy(Strategy::CrdtText)?;
+        Ok(text::delta(&self.ydoc.read()))
+    }
+
+    /// Length of the text in characters
+    pub fn text_len(&self) -> Result<u32> {
+        self.require_strategy(Strategy::CrdtText)?;
+        Ok(text::len(&self.ydoc.read()))
+    }
+
     /// Get the Y.js document state as bytes (for sync)
     pub fn encode_state(&self) -> Vec<u8> {
         let ydoc = self.ydoc.read();
@@ -334,6 +694,159 @@ impl Document {
         txn.encode_state_as_update_v1(&yrs::StateVector::default())
     }
 
+    /// Get the Y.js state vector, summarising which updates this document has
+    pub fn state_vector(&self) -> Vec<u8> {
+        let ydoc = self.ydoc.read();
+        let txn = ydoc.transact();
+        txn.state_vector().encode_v1()
+    }
+
+    /// Encode the updates a peer with state vector `sv` is missing
+    pub fn encode_diff(&self, sv: &[u8]) -> Result<Vec<u8>> {
+        let sv = yrs::StateVector::decode_v1(sv)
+            .map_err(|e: yrs::encoding::read::Error| Error::Crdt(e.to_string()))?;
+        let ydoc = self.ydoc.read();
+        let txn = ydoc.transact();
+        Ok(txn.encode_state_as_update_v1(&sv))
+    }
+
+    /// Collect the Y.js changes made since the previous call as a `Delta`
+    ///
+    /// `data` is normally the merged update of just those changes. After a
+    /// compaction or restore, or if too many changes piled up, it is the
+    /// full state instead and `snapshot` is set. Changes that do not touch
+    /// the Y.Doc (plain LWW values) yield an empty update.
+    pub fn take_delta(&self, path: Option<String>) -> Delta {
+        let pending = std::mem::take(&mut *self.pending.lock());
+
+        let merged = if pending.snapshot {
+            None
+        } else if pending.updates.is_empty() {
+            Some(Self::empty_update())
+        } else {
+            let updates: Vec<&[u8]> = pending.updates.iter().map(Vec::as_slice).collect();
+            yrs::merge_updates_v1(&updates).ok()
+        };
+
+        let (data, snapshot) = match merged {
+            Some(data) => (data, false),
+            None => (self.encode_state(), true),
+        };
+
+        let delta = Delta {
+            event: EventKind::Updated,
+            document_id: self.id(),
+            version: self.version(),
+            path,
+            strategy: self.strategy(),
+            data,
+            snapshot,
+        };
+        self.log.lock().push(delta.clone());
+        delta
+    }
+
+    /// Deltas published after `version`, oldest first
+    ///
+    /// If the update log no longer reaches back to `version` (or `version`
+    /// is from the future, e.g. before a restart), a single `Resync` delta
+    /// with the full state is returned instead.
+    pub fn deltas_since(&self, version: u64) -> Vec<Delta> {
+        let current = self.version();
+        if version == current {
+            return Vec::new();
+        }
+
+        let log = self.log.lock();
+        if version < log.floor || version > current {
+            drop(log);
+            return vec![self.full_delta(EventKind::Resync)];
+        }
+        log.entries
+            .iter()
+            .filter(|delta| delta.version > version)
+            .cloned()
+            .collect()
+    }
+
+    /// The changes `delta` (from `take_delta`) made, for an update log
+    ///
+    /// The plain value at the delta's path is recorded as it is now, so
+    /// replaying changes in version order ends with the latest value. Returns
+    /// `None` if the delta carries the full state, in which case the document
+    /// should be stored whole.
+    pub fn changes(&self, delta: &Delta) -> Option<Vec<Change>> {
+        if delta.snapshot {
+            return None;
+        }
+
+        let mut changes = Vec::new();
+        if delta.data != Self::empty_update() {
+            changes.push(Change::Update(delta.data.clone()));
+        }
+        // Map documents keep their values in the Y.Doc
+        if self.strategy() != Strategy::CrdtMap {
+            if let Some(path) = &delta.path {
+                if let Some(value) = self.lww_data.read().get_path(path) {
+                    changes.push(Change::Lww {
+                        path: path.clone(),
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+        Some(changes)
+    }
+
+    /// The full state as a `Delta`, leaving pending changes untouched
+    ///
+    /// For subscribers that lost track of the document and need to catch up.
+    pub fn full_delta(&self, event: EventKind) -> Delta {
+        Delta {
+            event,
+            document_id: self.id(),
+            version: self.version(),
+            path: None,
+            strategy: self.strategy(),
+            data: self.encode_state(),
+            snapshot: true,
+        }
+    }
+
+    /// A Y.js update that changes nothing
+    pub(crate) fn empty_update() -> Vec<u8> {
+        yrs::Update::new().encode_v1()
+    }
+
+    /// Encode the full document content (Y.js state plus LWW data)
+    ///
+    /// This is what gets persisted; `from_snapshot` reverses it.
+    pub fn encode_snapshot(&self) -> Vec<u8> {
+        let state = self.encode_state();
+        let data = rmp_serde::to_vec(&*self.lww_data.read()).unwrap_or_default();
+
+        let mut buf = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 4 + state.len() + data.len());
+        buf.extend_from_slice(SNAPSHOT_MAGIC);
+        buf.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&state);
+        buf.extend_from_slice(&data);
+        buf
+    }
+
+    /// Length of `encode_snapshot`, encoded again only once the document changed
+    pub fn snapshot_size(&self) -> usize {
+        let key = (self.version(), self.compaction_count());
+        let mut cached = self.snapshot_size.lock();
+        match *cached {
+            Some((at, size)) if at == key => size,
+            _ => {
+                let size = self.encode_snapshot().len();
+                *cached = Some((key, size));
+                size
+            }
+        }
+    }
+
     /// Apply a Y.js update from another peer
     pub fn apply_update(&self, update: &[u8]) -> Result<()> {
         let ydoc = self.ydoc.write();
@@ -342,6 +855,7 @@ impl Document {
             .map_err(|e: yrs::encoding::read::Error| Error::Crdt(e.to_string()))?;
         txn.apply_update(decoded);
         drop(txn);
+        self.note_lists(&ydoc);
         drop(ydoc);
         self.increment_update_count();
         self.update_version();
@@ -361,7 +875,9 @@ impl Document {
             .map_err(|e: yrs::encoding::read::Error| Error::RestoreError(e.to_string()))?;
         txn.apply_update(decoded);
         drop(txn);
+        self.note_lists(&ydoc);
         drop(ydoc);
+        self.pending.lock().snapshot = true;
         self.update_version();
         Ok(())
     }
@@ -412,25 +928,12 @@ impl Document {
         let old_state = self.encode_state();
         let old_size = old_state.len();
 
-        // For CrdtText, we need to preserve the text content
-        let strategy = self.strategy();
-        let text_content = if strategy == Strategy::CrdtText {
-            let ydoc = self.ydoc.read();
-            let text = ydoc.get_or_insert_text("content");
-            let txn = ydoc.transact();
-            Some(text.get_string(&txn))
-        } else {
-            None
-        };
-
         // Create a fresh Doc and apply the snapshot
         let new_doc = Doc::new();
 
-        if let Some(content) = text_content {
-            // For text documents, just set the content directly
-            let text = new_doc.get_or_insert_text("content");
-            let mut txn = new_doc.transact_mut();
-            text.insert(&mut txn, 0, &content);
+        if self.strategy() == Strategy::CrdtText {
+            // For text documents, copy content and formatting only
+            text::copy(&self.ydoc.read(), &new_doc);
         } else {
             // For other types, apply the state update
             let mut txn = new_doc.transact_mut();
@@ -439,11 +942,17 @@ impl Document {
             txn.apply_update(decoded);
         }
 
-        // Replace the old doc with the new one
+        // Replace the old doc with the new one; peers must resync from a snapshot
         {
             let mut ydoc = self.ydoc.write();
+            *self.observer.lock() = observe(&new_doc, &self.pending);
             *ydoc = new_doc;
         }
+        {
+            let mut pending = self.pending.lock();
+            *pending = PendingUpdates::default();
+            pending.snapshot = true;
+        }
 
         // Reset update counter and increment compaction counter
         self.update_count.store(0, Ordering::Relaxed);
@@ -451,15 +960,85 @@ impl Document {
 
         // Calculate bytes saved
         let new_size = self.encode_state().len();
-        let saved = if old_size > new_size {
-            old_size - new_size
-        } else {
-            0
-        };
+        let saved = old_size.saturating_sub(new_size);
 
         Ok(saved)
     }
 
+    /// Record an access at the given manager tick
+    pub fn touch(&self, tick: u64) {
+        self.last_access.store(tick, Ordering::Relaxed);
+        self.access_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Manager tick of the most recent access
+    pub fn last_access(&self) -> u64 {
+        self.last_access.load(Ordering::Relaxed)
+    }
+
+    /// Number of accesses since the document was loaded
+    pub fn access_count(&self) -> u64 {
+        self.access_count.load(Ordering::Relaxed)
+    }
+
+    fn require_strategy(&self, expected: Strategy) -> Result<()> {
+        let got = self.strategy();
+        if got == expected {
+            Ok(())
+        } else {
+            Err(Error::StrategyMismatch {
+                expected: expected.to_string(),
+                got: got.to_string(),
+            })
+        }
+    }
+
+    fn has_lists(&self) -> bool {
+        self.has_lists.load(Ordering::Relaxed)
+    }
+
+    /// Record lists that arrived in external Y.js state
+    fn note_lists(&self, ydoc: &Doc) {
+        if !self.has_lists() && list::exists(ydoc) {
+            self.has_lists.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn require_lists(&self) -> Result<()> {
+        if self.strategy() == Strategy::CrdtText {
+            return Err(Error::Crdt("Lists are not supported on crdt-text documents".into()));
+        }
+        Ok(())
+    }
+
+    /// Move an array stored as a plain value at `path` into the list CRDT
+    fn adopt_array(&self, ydoc: &Doc, path: &str) -> Result<()> {
+        if !list::items(ydoc, path).is_empty() {
+            return Ok(());
+        }
+
+        let existing = match self.strategy() {
+            Strategy::CrdtMap => map::get(ydoc, Some(path)).ok(),
+            _ => self.lww_data.read().get_path(path).cloned(),
+        };
+        match existing {
+            None | Some(Value::Null) => Ok(()),
+            Some(Value::Array(items)) => {
+                for item in items {
+                    list::push(ydoc, path, item);
+                }
+                match self.strategy() {
+                    Strategy::CrdtMap => {
+                        map::delete(ydoc, Some(path))?;
+                    }
+                    _ => self.lww_data.write().set_path(path, Value::Null)?,
+                }
+                Ok(())
+            }
+            Some(_) => Err(Error::InvalidPath(format!("{} is not an array", path))),
+        }
+    }
+
     fn increment_update_count(&self) {
         self.update_count.fetch_add(1, Ordering::Relaxed);
     }
@@ -474,6 +1053,55 @@ impl Document {
     }
 }
 
+/// Record every update produced on `ydoc` into `pending`
+fn observe(ydoc: &Doc, pending: &Arc<Mutex<PendingUpdates>>) -> Option<Subscription> {
+    let pending = Arc::clone(pending);
+    ydoc.observe_update_v1(move |_, event| {
+        let mut pending = pending.lock();
+        if pending.snapshot {
+            return;
+        }
+        pending.size += event.update.len();
+        if pending.size > MAX_PENDING_SIZE {
+            *pending = PendingUpdates {
+                snapshot: true,
+                ..Default::default()
+            };
+        } else {
+            pending.updates.push(event.update.clone());
+        }
+    })
+    .ok()
+}
+
+/// Split a snapshot into its Y.js state and LWW data
+fn decode_snapshot(snapshot: &[u8]) -> Result<(&[u8], Value)> {
+    let empty = || Value::Object(std::collections::HashMap::new());
+
+    let Some(body) = snapshot.strip_prefix(SNAPSHOT_MAGIC) else {
+        // Legacy blob: bare Y.js update
+        return Ok((snapshot, empty()));
+    };
+
+    if body.len() < 4 {
+        return Err(Error::RestoreError("Truncated snapshot header".into()));
+    }
+    let state_len = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+    let body = &body[4..];
+    if body.len() < state_len {
+        return Err(Error::RestoreError("Truncated snapshot state".into()));
+    }
+
+    let (state, data) = body.split_at(state_len);
+    let data = if data.is_empty() {
+        empty()
+    } else {
+        rmp_serde::from_slice(data).map_err(|e| Error::RestoreError(e.to_string()))?
+    };
+
+    Ok((state, data))
+}
+
 impl std::fmt::Debug for Document {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Document")
@@ -521,6 +1149,63 @@ mod tests {
         assert_eq!(value, Value::String("Bob".into()));
     }
 
+    #[test]
+    fn test_concurrent_map_increments() {
+        let id = DocumentId::new("test:map3").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::CrdtMap));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        doc.increment("stats.views", 1).unwrap();
+                        doc.get(Some("stats")).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.get(Some("stats.views")).unwrap(), Value::from(1600i64));
+    }
+
+    #[test]
+    fn test_map_merges_across_replicas() {
+        let a = Document::new(DocumentId::new("test:map").unwrap(), Strategy::CrdtMap);
+        let b = Document::new(DocumentId::new("test:map").unwrap(), Strategy::CrdtMap);
+
+        a.set("prefs.theme", Value::from("dark")).unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+
+        a.set("prefs.lang", Value::from("en")).unwrap();
+        b.set("prefs.tz", Value::from("UTC")).unwrap();
+        b.delete(Some("prefs.theme")).unwrap();
+
+        a.apply_update(&b.encode_state()).unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+
+        assert_eq!(a.get(None).unwrap(), b.get(None).unwrap());
+        assert_eq!(a.get(Some("prefs.lang")).unwrap(), Value::from("en"));
+        assert_eq!(a.get(Some("prefs.tz")).unwrap(), Value::from("UTC"));
+        assert!(a.get(Some("prefs.theme")).is_err());
+    }
+
+    #[test]
+    fn test_map_migrates_legacy_lww_snapshot() {
+        let id = DocumentId::new("test:maplegacy").unwrap();
+        let legacy = Document::new(id.clone(), Strategy::Lww);
+        legacy.set("user.name", Value::from("Dana")).unwrap();
+
+        let meta = DocumentMeta::new(id, Strategy::CrdtMap);
+        let doc = Document::from_snapshot(meta, &legacy.encode_snapshot()).unwrap();
+
+        assert_eq!(doc.get(Some("user.name")).unwrap(), Value::from("Dana"));
+        assert!(!doc.encode_state().is_empty());
+    }
+
     #[test]
     fn test_document_increment() {
         let id = DocumentId::new("test:3").unwrap();
@@ -531,6 +1216,364 @@ mod tests {
         assert_eq!(doc.increment("count", -2).unwrap(), 6);
     }
 
+    #[test]
+    fn test_concurrent_increments() {
+        let id = DocumentId::new("test:counter3").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::CrdtCounter));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        doc.increment("count", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.get(Some("count")).unwrap(), Value::from(1600i64));
+    }
+
+    #[test]
+    fn test_counter_merges_across_replicas() {
+        let a = Document::new(DocumentId::new("test:counter").unwrap(), Strategy::CrdtCounter);
+        let b = Document::new(DocumentId::new("test:counter").unwrap(), Strategy::CrdtCounter);
+
+        a.increment("stats.views", 5).unwrap();
+        b.increment("stats.views", 2).unwrap();
+
+        a.apply_update(&b.encode_state()).unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+
+        assert_eq!(a.get(Some("stats.views")).unwrap(), Value::from(7i64));
+        assert_eq!(b.get(Some("stats.views")).unwrap(), Value::from(7i64));
+    }
+
+    #[test]
+    fn test_counter_survives_compaction() {
+        let id = DocumentId::new("test:counter2").unwrap();
+        let doc = Document::new(id, Strategy::CrdtCounter);
+
+        doc.increment("count", 10).unwrap();
+        doc.compact().unwrap();
+        assert_eq!(doc.increment("count", -3).unwrap(), 7);
+        assert_eq!(doc.get(Some("count")).unwrap(), Value::from(7i64));
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let id = DocumentId::new("test:set").unwrap();
+        let doc = Document::new(id, Strategy::CrdtSet);
+
+        assert!(doc.set_add("tags", Value::from("rust")).unwrap());
+        assert!(doc.set_add("tags", Value::from("crdt")).unwrap());
+        assert!(!doc.set_add("tags", Value::from("rust")).unwrap());
+        assert!(doc.set_contains("tags", &Value::from("rust")).unwrap());
+
+        assert!(doc.set_remove("tags", &Value::from("rust")).unwrap());
+        assert!(!doc.set_remove("tags", &Value::from("rust")).unwrap());
+
+        assert_eq!(doc.set_members("tags").unwrap(), vec![Value::from("crdt")]);
+        assert_eq!(doc.get(Some("tags")).unwrap(), Value::Array(vec![Value::from("crdt")]));
+    }
+
+    #[test]
+    fn test_concurrent_set_updates_and_reads() {
+        let id = DocumentId::new("test:set3").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::CrdtSet));
+
+        let threads: Vec<_> = (0..8)
+            .map(|t: i64| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let member = Value::from(t * 100 + i);
+                        doc.set_add("tags", member.clone()).unwrap();
+                        assert!(doc.set_contains("tags", &member).unwrap());
+                        doc.set_members("tags").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.set_members("tags").unwrap().len(), 800);
+    }
+
+    #[test]
+    fn test_set_requires_set_strategy() {
+        let id = DocumentId::new("test:notset").unwrap();
+        let doc = Document::new(id, Strategy::Lww);
+
+        assert!(matches!(
+            doc.set_add("tags", Value::from("x")),
+            Err(Error::StrategyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_text_edits_apply_incrementally() {
+        let a = Document::new(DocumentId::new("test:text").unwrap(), Strategy::CrdtText);
+        let b = Document::new(DocumentId::new("test:text").unwrap(), Strategy::CrdtText);
+
+        a.text_insert(0, "Hello").unwrap();
+        b.apply_update(&a.take_delta(None).data).unwrap();
+        a.text_insert(5, " World").unwrap();
+        a.text_delete(0, 1).unwrap();
+        b.apply_update(&a.take_delta(None).data).unwrap();
+
+        assert_eq!(b.get(None).unwrap(), Value::from("ello World"));
+        assert_eq!(b.text_len().unwrap(), 10);
+        assert!(a.text_insert(42, "x").is_err());
+    }
+
+    #[test]
+    fn test_concurrent_text_edits() {
+        let id = DocumentId::new("test:text3").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::CrdtText));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        doc.text_insert(0, "ab").unwrap();
+                        doc.text_delete(0, 1).unwrap();
+                        doc.get(None).unwrap();
+                        doc.text_len().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.text_len().unwrap(), 800);
+    }
+
+    #[test]
+    fn test_concurrent_text_formatting() {
+        let id = DocumentId::new("test:text4").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::CrdtText));
+        doc.text_insert(0, "Hello World").unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    let attributes = std::collections::HashMap::from([
+                        ("bold".to_string(), Value::Bool(t % 2 == 0)),
+                    ]);
+                    for _ in 0..100 {
+                        doc.text_format(0, 5, attributes.clone()).unwrap();
+                        doc.text_delta().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.get(None).unwrap(), Value::from("Hello World"));
+    }
+
+    #[test]
+    fn test_state_vector_sync() {
+        let a = Document::new(DocumentId::new("test:sv").unwrap(), Strategy::CrdtText);
+        let b = Document::new(DocumentId::new("test:sv").unwrap(), Strategy::CrdtText);
+
+        a.text_insert(0, "shared").unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+
+        // b goes offline and edits; a only needs what b's state vector lacks
+        b.text_insert(6, " offline").unwrap();
+        let missing = b.encode_diff(&a.state_vector()).unwrap();
+        assert!(missing.len() < b.encode_state().len());
+
+        a.apply_update(&missing).unwrap();
+        assert_eq!(a.get(None).unwrap(), Value::from("shared offline"));
+        assert!(a.encode_diff(b"\xff\xff").is_err());
+    }
+
+    #[test]
+    fn test_deltas_since() {
+        let doc = Document::new(DocumentId::new("test:log").unwrap(), Strategy::CrdtText);
+        let start = doc.version();
+        for i in 0..3 {
+            doc.text_insert(i, "x").unwrap();
+            doc.take_delta(None);
+        }
+
+        let missed = doc.deltas_since(start + 1);
+        assert_eq!(missed.iter().map(|d| d.version).collect::<Vec<_>>(), vec![start + 2, start + 3]);
+        assert!(doc.deltas_since(doc.version()).is_empty());
+
+        // Once the log is truncated past the requested version, send everything
+        for i in 0..UPDATE_LOG_LEN as u32 {
+            doc.text_insert(i, "y").unwrap();
+            doc.take_delta(None);
+        }
+        let missed = doc.deltas_since(start + 1);
+        assert_eq!(missed.len(), 1);
+        assert!(missed[0].snapshot);
+        assert_eq!(missed[0].event, EventKind::Resync);
+        assert_eq!(doc.deltas_since(doc.version() - 2).len(), 2);
+    }
+
+    #[test]
+    fn test_apply_malformed_update() {
+        let doc = Document::new(DocumentId::new("test:bad").unwrap(), Strategy::CrdtText);
+        doc.text_insert(0, "intact").unwrap();
+
+        for garbage in [&b"\x01\x02\x03"[..], b"\xff\xff\xff\xff", b"not an update"] {
+            assert!(matches!(doc.apply_update(garbage), Err(Error::Crdt(_))));
+        }
+        assert_eq!(doc.get(None).unwrap(), Value::from("intact"));
+    }
+
+    #[test]
+    fn test_take_delta_is_incremental() {
+        let id = DocumentId::new("test:delta").unwrap();
+        let doc = Document::new(id, Strategy::CrdtText);
+        doc.set("content", Value::from("x".repeat(10_000))).unwrap();
+        let first = doc.take_delta(None);
+        assert!(!first.snapshot);
+
+        doc.text_insert(0, "y").unwrap();
+        let delta = doc.take_delta(Some("content".into()));
+        assert!(!delta.snapshot);
+        assert!(delta.data.len() < 100);
+        assert_eq!(delta.version, doc.version());
+
+        // Nothing changed since: an empty update
+        assert!(doc.take_delta(None).data.len() <= 2);
+
+        // Compaction rewrites history, so subscribers get the full state
+        doc.compact().unwrap();
+        let delta = doc.take_delta(None);
+        assert!(delta.snapshot);
+        assert_eq!(delta.data, doc.encode_state());
+    }
+
+    #[test]
+    fn test_text_formatting_survives_compaction() {
+        let id = DocumentId::new("test:rich").unwrap();
+        let doc = Document::new(id, Strategy::CrdtText);
+        doc.text_insert(0, "Title").unwrap();
+
+        let mut attrs = std::collections::HashMap::new();
+        attrs.insert("header".to_string(), Value::from(1i64));
+        doc.text_format(0, 5, attrs).unwrap();
+
+        let before = doc.text_delta().unwrap();
+        doc.compact().unwrap();
+        assert_eq!(doc.text_delta().unwrap(), before);
+        assert_eq!(doc.get(None).unwrap(), Value::from("Title"));
+    }
+
+    #[test]
+    fn test_list_operations() {
+        let id = DocumentId::new("test:list").unwrap();
+        let doc = Document::new(id, Strategy::Lww);
+
+        doc.push("cart", Value::from("milk")).unwrap();
+        doc.push("cart", Value::from("eggs")).unwrap();
+        assert_eq!(doc.list_insert("cart", 0, Value::from("bread")).unwrap(), 3);
+        doc.list_move("cart", 0, 2).unwrap();
+        assert_eq!(doc.list_remove("cart", 0, 1).unwrap(), 1);
+
+        let expected = vec![Value::from("eggs"), Value::from("bread")];
+        assert_eq!(doc.list_range("cart", 0, -1).unwrap(), expected);
+        assert_eq!(doc.get(Some("cart")).unwrap(), Value::Array(expected));
+
+        // A plain value replaces the list
+        doc.set("cart", Value::from("none")).unwrap();
+        assert_eq!(doc.get(Some("cart")).unwrap(), Value::from("none"));
+        assert!(doc.push("cart", Value::from("x")).is_err());
+    }
+
+    #[test]
+    fn test_list_adopts_plain_array() {
+        let id = DocumentId::new("test:list2").unwrap();
+        let doc = Document::new(id, Strategy::CrdtMap);
+        doc.set("board.todo", Value::from(vec!["a", "b"])).unwrap();
+
+        assert_eq!(doc.list_range("board.todo", 1, 1).unwrap(), vec![Value::from("b")]);
+        doc.list_insert("board.todo", 1, Value::from("c")).unwrap();
+        assert_eq!(doc.get(Some("board.todo")).unwrap(), Value::from(vec!["a", "c", "b"]));
+    }
+
+    #[test]
+    fn test_concurrent_pushes_merge() {
+        let a = Document::new(DocumentId::new("test:list3").unwrap(), Strategy::Lww);
+        let b = Document::new(DocumentId::new("test:list3").unwrap(), Strategy::Lww);
+
+        a.push("items", Value::from(1i64)).unwrap();
+        b.push("items", Value::from(2i64)).unwrap();
+        a.apply_update(&b.encode_state()).unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+
+        assert_eq!(a.list_range("items", 0, -1).unwrap().len(), 2);
+        assert_eq!(a.get(None).unwrap(), b.get(None).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_sets_and_pushes() {
+        let id = DocumentId::new("test:list4").unwrap();
+        let doc = Arc::new(Document::new(id, Strategy::Lww));
+
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let doc = Arc::clone(&doc);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        if t % 2 == 0 {
+                            doc.set("name", Value::from(i)).unwrap();
+                        } else {
+                            doc.push("items", Value::from(i)).unwrap();
+                        }
+                        doc.get(None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(doc.list_range("items", 0, -1).unwrap().len(), 400);
+    }
+
+    #[test]
+    fn test_snapshot_size_follows_changes() {
+        let doc = Document::new(DocumentId::new("test:size").unwrap(), Strategy::Lww);
+        assert_eq!(doc.snapshot_size(), doc.encode_snapshot().len());
+
+        doc.set("name", Value::from("a much longer value than before")).unwrap();
+        assert_eq!(doc.snapshot_size(), doc.encode_snapshot().len());
+    }
+
+    #[test]
+    fn test_lists_arrive_in_updates() {
+        let a = Document::new(DocumentId::new("test:list5").unwrap(), Strategy::Lww);
+        let b = Document::new(DocumentId::new("test:list5").unwrap(), Strategy::Lww);
+
+        a.push("items", Value::from(1i64)).unwrap();
+        b.apply_update(&a.encode_state()).unwrap();
+        assert_eq!(b.get(Some("items")).unwrap(), Value::Array(vec![Value::from(1i64)]));
+
+        b.set("items", Value::from("plain")).unwrap();
+        assert_eq!(b.get(Some("items")).unwrap(), Value::from("plain"));
+    }
+
     #[test]
     fn test_document_update_count() {
         let id = DocumentId::new("test:4").unwrap();
@@ -572,6 +1615,71 @@ mod tests {
         assert_eq!(doc.compaction_count(), 1);
     }
 
+    #[test]
+    fn test_from_snapshot_keeps_meta() {
+        let id = DocumentId::new("test:7").unwrap();
+        let doc = Document::new(id, Strategy::CrdtText);
+        doc.set("content", Value::String("persisted".into())).unwrap();
+
+        let meta = doc.meta();
+        let restored = Document::from_snapshot(meta.clone(), &doc.encode_snapshot()).unwrap();
+
+        assert_eq!(restored.get(None).unwrap(), Value::String("persisted".into()));
+        assert_eq!(restored.version(), meta.version);
+        assert_eq!(restored.meta().updated_at, meta.updated_at);
+        assert_eq!(restored.meta().created_at, meta.created_at);
+    }
+
+    #[test]
+    fn test_snapshot_includes_lww_data() {
+        let id = DocumentId::new("test:8").unwrap();
+        let doc = Document::new(id, Strategy::Lww);
+        doc.set("user.name", Value::String("Carol".into())).unwrap();
+
+        let restored = Document::from_snapshot(doc.meta(), &doc.encode_snapshot()).unwrap();
+        assert_eq!(restored.get(Some("user.name")).unwrap(), Value::String("Carol".into()));
+    }
+
+    #[test]
+    fn test_replay_changes() {
+        for strategy in [Strategy::Lww, Strategy::CrdtMap, Strategy::CrdtCounter] {
+            let id = DocumentId::new("test:replay").unwrap();
+            let doc = Document::new(id, strategy);
+            doc.set("name", Value::String("Dave".into())).unwrap();
+            let snapshot = doc.encode_snapshot();
+            doc.take_delta(None);
+
+            let mut changes = Vec::new();
+            let mut log = |path: &str| {
+                let delta = doc.take_delta(Some(path.to_string()));
+                let logged = doc.changes(&delta).unwrap();
+                changes.extend(logged.iter().map(|c| Change::decode(&c.encode()).unwrap()));
+            };
+            doc.set("name", Value::String("Erin".into())).unwrap();
+            log("name");
+            doc.push("tags", Value::String("a".into())).unwrap();
+            log("tags");
+            doc.increment("visits", 3).unwrap();
+            log("visits");
+            doc.delete(Some("name")).unwrap();
+            log("name");
+
+            let replayed = Document::replay(doc.meta(), &snapshot, &changes).unwrap();
+            assert_eq!(replayed.get(None).unwrap(), doc.get(None).unwrap(), "{:?}", strategy);
+            assert_eq!(replayed.version(), doc.version());
+        }
+    }
+
+    #[test]
+    fn test_from_snapshot_legacy_state() {
+        let id = DocumentId::new("test:9").unwrap();
+        let doc = Document::new(id, Strategy::CrdtText);
+        doc.set("content", Value::String("legacy".into())).unwrap();
+
+        let restored = Document::from_snapshot(doc.meta(), &doc.encode_state()).unwrap();
+        assert_eq!(restored.get(None).unwrap(), Value::String("legacy".into()));
+    }
+
     #[test]
     fn test_should_compact_threshold() {
         let id = DocumentId::new("test:6").unwrap();
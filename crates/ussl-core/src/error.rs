@@ -14,6 +14,9 @@ pub enum Error {
     #[error("Invalid path: {0}")]
     InvalidPath(String),
 
+    #[error("Index out of range: {index} > {len}")]
+    IndexOutOfRange { index: usize, len: usize },
+
     #[error("Strategy mismatch: expected {expected}, got {got}")]
     StrategyMismatch { expected: String, got: String },
 
@@ -37,6 +40,12 @@ pub enum Error {
 
     #[error("Failed to restore state: {0}")]
     RestoreError(String),
+
+    #[error("Storage error: {0}")]
+    Storage(String),
+
+    #[error("IO error: {0}")]
+    Io(String),
 }
 
 /// Result type alias for USSL Core operations
@@ -4,13 +4,27 @@
 //! - Document management with unique identifiers
 //! - CRDT-based conflict resolution strategies
 //! - Subscription and presence management
+//! - Lazy loading and eviction over a persistent backing store
 
+pub mod backup;
+pub mod base64;
+pub mod cache;
+mod counter;
 pub mod document;
 pub mod crdt;
 pub mod error;
+mod list;
 pub mod manager;
+mod map;
+mod orset;
+pub mod pattern;
+pub mod subscription;
+mod text;
 
-pub use document::{Document, DocumentId, DocumentMeta, COMPACTION_THRESHOLD, COMPACTION_SIZE_THRESHOLD};
+pub use cache::{Backing, CacheConfig, EvictionPolicy};
+pub use document::{Change, Document, DocumentId, DocumentMeta, COMPACTION_THRESHOLD, COMPACTION_SIZE_THRESHOLD};
 pub use crdt::{Strategy, Value};
 pub use error::{Error, Result};
-pub use manager::{DocumentManager, Backup, DocumentBackup};
+pub use manager::{DocumentManager, Backup, DocumentBackup, EventKind, ScanPage, BACKUP_VERSION};
+pub use pattern::Glob;
+pub use subscription::{SubscriberId, SubscriptionRegistry};
@@ -0,0 +1,233 @@
+//! List CRDT stored inside a document's Y.Doc
+//!
+//! Each list path gets its own root Y.Array named `list:<path>`. Root types
+//! are identified by name, so replicas that start a list at the same path
+//! concurrently end up with one merged array instead of racing on a nested
+//! container. Concurrent inserts interleave, removals only affect the items
+//! they saw, and moves keep the identity of the moved item.
+
+use crate::crdt::{is_within, Value};
+use crate::error::{Error, Result};
+use crate::map::{any_to_value, value_to_any};
+use std::collections::BTreeMap;
+use yrs::types::{ToJson, Value as YValue};
+use yrs::{Array, ArrayRef, Doc, ReadTxn, Transact};
+
+/// Prefix of the root Y.Array names holding lists
+const PREFIX: &str = "list:";
+
+fn array(doc: &Doc, path: &str) -> ArrayRef {
+    doc.get_or_insert_array(format!("{}{}", PREFIX, path))
+}
+
+/// Every list root, by path
+///
+/// Roots that arrived in an update (from a snapshot, restore or peer) have
+/// no type until opened by name, but read back as arrays all the same, so
+/// no mutable transaction is needed to list them.
+fn lists<T: ReadTxn>(txn: &T) -> Vec<(String, ArrayRef)> {
+    let paths: Vec<String> = txn
+        .root_refs()
+        .filter_map(|(name, _)| name.strip_prefix(PREFIX).map(str::to_string))
+        .collect();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let list = txn.get_array(format!("{}{}", PREFIX, path))?;
+            Some((path, list))
+        })
+        .collect()
+}
+
+/// Whether the document has ever held a list
+pub(crate) fn exists(doc: &Doc) -> bool {
+    let txn = doc.transact();
+    let exists = txn.root_refs().any(|(name, _)| name.starts_with(PREFIX));
+    exists
+}
+
+/// Insert `value` before the item at `index` (`index == len` appends)
+///
+/// Returns the new length of the list.
+pub(crate) fn insert(doc: &Doc, path: &str, index: u32, value: Value) -> Result<u32> {
+    let list = array(doc, path);
+    let mut txn = doc.transact_mut();
+
+    let len = list.len(&txn);
+    if index > len {
+        return Err(out_of_range(index, len));
+    }
+    list.insert(&mut txn, index, value_to_any(value));
+    Ok(len + 1)
+}
+
+/// Append `value` to the list at `path`
+pub(crate) fn push(doc: &Doc, path: &str, value: Value) {
+    let list = array(doc, path);
+    let mut txn = doc.transact_mut();
+    list.push_back(&mut txn, value_to_any(value));
+}
+
+/// Remove up to `count` items starting at `index`; returns how many were removed
+pub(crate) fn remove(doc: &Doc, path: &str, index: u32, count: u32) -> Result<u32> {
+    let list = array(doc, path);
+    let mut txn = doc.transact_mut();
+
+    let len = list.len(&txn);
+    if index >= len {
+        return Err(out_of_range(index, len));
+    }
+    let count = count.min(len - index);
+    list.remove_range(&mut txn, index, count);
+    Ok(count)
+}
+
+/// Move the item at `from` so that it ends up at index `to`
+pub(crate) fn move_item(doc: &Doc, path: &str, from: u32, to: u32) -> Result<()> {
+    let list = array(doc, path);
+    let mut txn = doc.transact_mut();
+
+    let len = list.len(&txn);
+    if from >= len {
+        return Err(out_of_range(from, len));
+    }
+    if to >= len {
+        return Err(out_of_range(to, len));
+    }
+    // Y.Array targets the gap before an item in the current state
+    let target = if to > from { to + 1 } else { to };
+    list.move_to(&mut txn, from, target);
+    Ok(())
+}
+
+/// Items of the list at `path` between `start` and `stop`, as for `slice`
+pub(crate) fn range(doc: &Doc, path: &str, start: i64, stop: i64) -> Vec<Value> {
+    slice(&items(doc, path), start, stop)
+}
+
+/// Items between `start` and `stop` inclusive; negative indexes count from the end
+pub(crate) fn slice(items: &[Value], start: i64, stop: i64) -> Vec<Value> {
+    let len = items.len() as i64;
+
+    let resolve = |i: i64| if i < 0 { len + i } else { i };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(len - 1);
+    if start > stop {
+        return Vec::new();
+    }
+    items[start as usize..=stop as usize].to_vec()
+}
+
+/// All items of the list at `path`
+pub(crate) fn items(doc: &Doc, path: &str) -> Vec<Value> {
+    let txn = doc.transact();
+    txn.get_array(format!("{}{}", PREFIX, path))
+        .map(|list| list.iter(&txn).map(|v| to_value(v, &txn)).collect())
+        .unwrap_or_default()
+}
+
+/// Items of every non-empty list in the document, keyed by path
+pub(crate) fn all(doc: &Doc) -> BTreeMap<String, Vec<Value>> {
+    let txn = doc.transact();
+    lists(&txn)
+        .into_iter()
+        .filter_map(|(path, list)| {
+            let items: Vec<Value> = list.iter(&txn).map(|v| to_value(v, &txn)).collect();
+            (!items.is_empty()).then_some((path, items))
+        })
+        .collect()
+}
+
+/// Empty lists at `path` (and below it), or all lists if `path` is None
+///
+/// Returns true if anything was removed.
+pub(crate) fn clear(doc: &Doc, path: Option<&str>) -> bool {
+    let doomed: Vec<String> = {
+        let txn = doc.transact();
+        lists(&txn)
+            .into_iter()
+            .filter(|(list_path, list)| path.is_none_or(|p| is_within(list_path, p)) && list.len(&txn) > 0)
+            .map(|(list_path, _)| list_path)
+            .collect()
+    };
+    if doomed.is_empty() {
+        return false;
+    }
+
+    let lists: Vec<ArrayRef> = doomed.iter().map(|list_path| array(doc, list_path)).collect();
+    let mut txn = doc.transact_mut();
+    for list in &lists {
+        let len = list.len(&txn);
+        list.remove_range(&mut txn, 0, len);
+    }
+    true
+}
+
+fn to_value<T: ReadTxn>(value: YValue, txn: &T) -> Value {
+    any_to_value(value.to_json(txn))
+}
+
+fn out_of_range(index: u32, len: u32) -> Error {
+    Error::IndexOutOfRange {
+        index: index as usize,
+        len: len as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::updates::decoder::Decode;
+    use yrs::{StateVector, Update};
+
+    fn sync(from: &Doc, to: &Doc) {
+        let update = from.transact().encode_state_as_update_v1(&StateVector::default());
+        to.transact_mut().apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_pushes_are_kept() {
+        let a = Doc::new();
+        let b = Doc::new();
+
+        push(&a, "cart", Value::from("milk"));
+        push(&b, "cart", Value::from("eggs"));
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        assert_eq!(items(&a, "cart").len(), 2);
+        assert_eq!(items(&a, "cart"), items(&b, "cart"));
+    }
+
+    #[test]
+    fn test_insert_remove_move() {
+        let doc = Doc::new();
+        for (i, item) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            insert(&doc, "col", i as u32, Value::from(item)).unwrap();
+        }
+
+        move_item(&doc, "col", 0, 2).unwrap();
+        assert_eq!(items(&doc, "col"), vec![Value::from("b"), Value::from("c"), Value::from("a"), Value::from("d")]);
+
+        move_item(&doc, "col", 3, 0).unwrap();
+        assert_eq!(range(&doc, "col", 0, 1), vec![Value::from("d"), Value::from("b")]);
+
+        assert_eq!(remove(&doc, "col", 2, 10).unwrap(), 2);
+        assert_eq!(range(&doc, "col", -1, -1), vec![Value::from("b")]);
+        assert!(insert(&doc, "col", 5, Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_all_and_clear() {
+        let doc = Doc::new();
+        push(&doc, "board.todo", Value::from(1i64));
+        push(&doc, "board.done", Value::from(2i64));
+        push(&doc, "other", Value::from(3i64));
+
+        assert!(clear(&doc, Some("board")));
+        let all = all(&doc);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all["other"], vec![Value::from(3i64)]);
+    }
+}
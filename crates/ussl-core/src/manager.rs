@@ -1,20 +1,80 @@
 //! Document Manager - handles document lifecycle and subscriptions
 
+use crate::backup::SnapshotInfo;
+use crate::cache::{Backing, CacheConfig, EvictionPolicy};
 use crate::crdt::Strategy;
 use crate::document::{Document, DocumentId, DocumentMeta};
 use crate::error::{Error, Result};
+use crate::pattern::{self, Glob};
+use crate::subscription::SubscriptionRegistry;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// What happened to a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Created,
+    Updated,
+    Deleted,
+    Expired,
+    Restored,
+    /// Sent after updates were dropped for a slow subscriber; carries the full state
+    Resync,
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Created => write!(f, "created"),
+            EventKind::Updated => write!(f, "updated"),
+            EventKind::Deleted => write!(f, "deleted"),
+            EventKind::Expired => write!(f, "expired"),
+            EventKind::Restored => write!(f, "restored"),
+            EventKind::Resync => write!(f, "resync"),
+        }
+    }
+}
 
 /// Delta update sent to subscribers
 #[derive(Debug, Clone)]
 pub struct Delta {
+    pub event: EventKind,
     pub document_id: DocumentId,
     pub version: u64,
     pub path: Option<String>,
+    pub strategy: Strategy,
+    /// Y.js update (v1 encoding) with the changes, or the full state if `snapshot`
     pub data: Vec<u8>,
+    /// `data` is the full document state rather than an incremental update
+    pub snapshot: bool,
+}
+
+impl Delta {
+    /// Event for a document that is gone; `data` is an empty update
+    pub fn removed(meta: &DocumentMeta, event: EventKind) -> Self {
+        Self {
+            event,
+            document_id: meta.id.clone(),
+            version: meta.version,
+            path: None,
+            strategy: meta.strategy,
+            data: Document::empty_update(),
+            snapshot: false,
+        }
+    }
+
+    /// Report the change as `event` instead of an update
+    pub fn with_event(mut self, event: EventKind) -> Self {
+        self.event = event;
+        self
+    }
 }
 
 /// Presence information for a client
@@ -29,24 +89,63 @@ pub struct Presence {
 pub struct DocumentManager {
     /// All documents indexed by ID
     documents: DashMap<String, Arc<Document>>,
-    /// Broadcast channel for document updates
-    update_sender: broadcast::Sender<Delta>,
+    /// IDs of resident documents in order, for `scan`
+    order: RwLock<BTreeSet<String>>,
+    /// Routes document updates to the subscribers interested in them
+    subscriptions: SubscriptionRegistry,
     /// Presence information per document
     presence: DashMap<String, Vec<Presence>>,
+    /// Optional persistent store for lazy loading and eviction
+    backing: Option<Arc<dyn Backing>>,
+    /// Eviction settings (only used with a backing store)
+    cache: Option<CacheConfig>,
+    /// Logical clock for access tracking
+    clock: AtomicU64,
+    /// Last snapshot written to disk, for `LASTSAVE` and `INFO`
+    last_snapshot: RwLock<Option<SnapshotInfo>>,
+    /// Held shared while documents change and exclusively by `backup`,
+    /// so a backup captures a single instant
+    barrier: RwLock<()>,
+    /// Held by `backup` throughout, so one backup runs at a time
+    backing_up: Mutex<()>,
+    /// While a backup reads documents from the backing store, the state of
+    /// each document loaded meanwhile, as it was before any change
+    loaded_during_backup: Mutex<Option<HashMap<String, DocumentBackup>>>,
 }
 
 impl DocumentManager {
     /// Create a new document manager
     pub fn new() -> Self {
-        let (update_sender, _) = broadcast::channel(10000);
-
         Self {
             documents: DashMap::new(),
-            update_sender,
+            order: RwLock::new(BTreeSet::new()),
+            subscriptions: SubscriptionRegistry::default(),
             presence: DashMap::new(),
+            backing: None,
+            cache: None,
+            clock: AtomicU64::new(0),
+            last_snapshot: RwLock::new(None),
+            barrier: RwLock::new(()),
+            backing_up: Mutex::new(()),
+            loaded_during_backup: Mutex::new(None),
         }
     }
 
+    /// Back the manager with a persistent store
+    ///
+    /// Misses in `get` fall through to the store, and `evict` flushes cold
+    /// documents to it once resident snapshots exceed the memory budget.
+    pub fn with_backing(mut self, backing: Arc<dyn Backing>, config: CacheConfig) -> Self {
+        self.backing = Some(backing);
+        self.cache = Some(config);
+        self
+    }
+
+    /// Whether documents may live outside memory
+    pub fn is_backed(&self) -> bool {
+        self.backing.is_some()
+    }
+
     /// Create a new document
     pub fn create(
         &self,
@@ -56,7 +155,7 @@ impl DocumentManager {
     ) -> Result<Arc<Document>> {
         let key = id.as_str().to_string();
 
-        if self.documents.contains_key(&key) {
+        if self.documents.contains_key(&key) || self.load_from_backing(&id)?.is_some() {
             return Err(Error::DocumentExists(key));
         }
 
@@ -64,40 +163,165 @@ impl DocumentManager {
             Some(ttl_ms) => Document::with_ttl(id, strategy, ttl_ms),
             None => Document::new(id, strategy),
         });
+        doc.touch(self.tick());
+        self.order.write().insert(key.clone());
         self.documents.insert(key, doc.clone());
+        self.publish_update(doc.take_delta(None).with_event(EventKind::Created));
+
+        Ok(doc)
+    }
 
+    /// Load a persisted document into the manager
+    ///
+    /// Used when rehydrating from storage: the stored metadata (strategy,
+    /// version, timestamps, TTL) is kept as-is. Replaces any in-memory copy.
+    pub fn load(&self, meta: DocumentMeta, state: &[u8]) -> Result<Arc<Document>> {
+        let key = meta.id.as_str().to_string();
+        let doc = Arc::new(Document::from_snapshot(meta, state)?);
+        self.order.write().insert(key.clone());
+        self.documents.insert(key, doc.clone());
         Ok(doc)
     }
 
-    /// Get an existing document
+    /// Get an existing document, loading it from the backing store if needed
     pub fn get(&self, id: &DocumentId) -> Result<Arc<Document>> {
-        self.documents
-            .get(id.as_str())
-            .map(|r| r.value().clone())
+        if let Some(doc) = self.documents.get(id.as_str()).map(|r| r.value().clone()) {
+            doc.touch(self.tick());
+            return Ok(doc);
+        }
+
+        self.load_from_backing(id)?
             .ok_or_else(|| Error::DocumentNotFound(id.to_string()))
     }
 
     /// Get or create a document
+    ///
+    /// Fails only if the backing store cannot be read, so that a transient
+    /// storage error never shadows a stored document with an empty one.
     pub fn get_or_create(
         &self,
         id: DocumentId,
         strategy: Strategy,
-    ) -> Arc<Document> {
-        let key = id.as_str().to_string();
+    ) -> Result<Arc<Document>> {
+        match self.get(&id) {
+            Ok(doc) => return Ok(doc),
+            Err(Error::DocumentNotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
 
-        self.documents
+        let key = id.as_str().to_string();
+        let mut created = false;
+        self.order.write().insert(key.clone());
+        let doc = self.documents
             .entry(key)
-            .or_insert_with(|| Arc::new(Document::new(id, strategy)))
+            .or_insert_with(|| {
+                created = true;
+                Arc::new(Document::new(id, strategy))
+            })
             .value()
-            .clone()
+            .clone();
+        doc.touch(self.tick());
+        if created {
+            self.publish_update(doc.take_delta(None).with_event(EventKind::Created));
+        }
+        Ok(doc)
     }
 
-    /// Delete a document
+    /// Delete a document (from the backing store as well, if any)
     pub fn delete(&self, id: &DocumentId) -> Result<()> {
-        self.documents
-            .remove(id.as_str())
-            .map(|_| ())
-            .ok_or_else(|| Error::DocumentNotFound(id.to_string()))
+        let mut meta = self.documents.remove(id.as_str()).map(|(_, doc)| doc.meta());
+        self.order.write().remove(id.as_str());
+
+        if let Some(ref backing) = self.backing {
+            if let Some((stored, _)) = backing.load(id)? {
+                backing.delete(id)?;
+                meta.get_or_insert(stored);
+            }
+        }
+
+        match meta {
+            Some(meta) => {
+                self.publish_update(Delta::removed(&meta, EventKind::Deleted));
+                Ok(())
+            }
+            None => Err(Error::DocumentNotFound(id.to_string())),
+        }
+    }
+
+    /// List IDs of all documents matching a pattern, including ones that
+    /// are only present in the backing store
+    pub fn keys(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>> {
+        let mut keys: BTreeSet<String> = self
+            .documents
+            .iter()
+            .filter(|entry| pattern.is_none_or(|p| pattern::matches(p, entry.key())))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        if let Some(ref backing) = self.backing {
+            keys.extend(backing.list(pattern)?.into_iter().map(|id| id.as_str().to_string()));
+        }
+
+        Ok(keys.into_iter().filter_map(|k| DocumentId::new(k).ok()).collect())
+    }
+
+    /// Evict cold documents until resident snapshots fit the memory budget
+    ///
+    /// Each victim is flushed to the backing store before it is dropped.
+    /// Documents that are in use or change while being flushed are skipped.
+    /// Returns the number of documents evicted.
+    pub fn evict(&self) -> Result<usize> {
+        let (Some(backing), Some(cache)) = (self.backing.as_ref(), self.cache.as_ref()) else {
+            return Ok(0);
+        };
+
+        let mut resident: Vec<(String, Arc<Document>, usize)> = self
+            .documents
+            .iter()
+            .map(|entry| {
+                let doc = entry.value().clone();
+                let size = doc.snapshot_size();
+                (entry.key().clone(), doc, size)
+            })
+            .collect();
+
+        let mut total: usize = resident.iter().map(|(_, _, size)| size).sum();
+        if total <= cache.memory_budget {
+            return Ok(0);
+        }
+
+        match cache.policy {
+            EvictionPolicy::Lru => resident.sort_by_key(|(_, doc, _)| doc.last_access()),
+            EvictionPolicy::Lfu => {
+                resident.sort_by_key(|(_, doc, _)| (doc.access_count(), doc.last_access()))
+            }
+        }
+
+        let mut evicted = 0;
+        for (key, doc, size) in resident {
+            if total <= cache.memory_budget {
+                break;
+            }
+
+            let version = doc.version();
+            backing.flush(&doc.meta(), &doc.encode_snapshot())?;
+
+            // Only drop it if nobody else holds it and it did not change
+            // after the flush; the map and `doc` account for two references.
+            let removed = self
+                .documents
+                .remove_if(&key, |_, d| Arc::strong_count(d) == 2 && d.version() == version)
+                .is_some();
+
+            if removed {
+                self.order.write().remove(&key);
+                total = total.saturating_sub(size);
+                evicted += 1;
+            }
+        }
+
+        debug!(evicted = evicted, resident_bytes = total, "Eviction pass completed");
+        Ok(evicted)
     }
 
     /// List all documents matching a pattern (glob syntax)
@@ -105,20 +329,112 @@ impl DocumentManager {
         self.documents
             .iter()
             .filter(|entry| {
-                pattern.map_or(true, |p| Self::matches_pattern(entry.key(), p))
+                pattern.is_none_or(|p| pattern::matches(p, entry.key()))
             })
             .map(|entry| entry.value().meta())
             .collect()
     }
 
-    /// Subscribe to document updates
-    pub fn subscribe(&self) -> broadcast::Receiver<Delta> {
-        self.update_sender.subscribe()
+    /// IDs of the documents matching `pattern` that may have changed after `version`
+    ///
+    /// Every resident match is included, as checking it is cheap. Documents
+    /// that are only in the backing store are included only if their stored
+    /// version is above `version`, so unchanged ones are not loaded.
+    pub fn changed_since(&self, pattern: &str, version: u64) -> Result<Vec<DocumentId>> {
+        let mut ids: BTreeSet<String> = self
+            .documents
+            .iter()
+            .filter(|entry| pattern::matches(pattern, entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        ids.extend(
+            self.stored_only(Some(pattern))?
+                .into_iter()
+                .filter(|meta| meta.version > version)
+                .map(|meta| meta.id.as_str().to_string()),
+        );
+        Ok(ids.into_iter().filter_map(|k| DocumentId::new(k).ok()).collect())
+    }
+
+    /// Walk documents in id order, one page at a time
+    ///
+    /// Examines up to `count` documents with ids greater than `cursor` that
+    /// match `pattern`, resident or only in the backing store, and returns
+    /// those of the given strategy. The cursor to continue from is the last
+    /// id examined, so pages may come back short (or empty) when filtering by
+    /// strategy; it is None once everything has been examined. Documents
+    /// created or removed during a walk may or may not be seen, but every
+    /// document present throughout is returned exactly once.
+    pub fn scan(
+        &self,
+        cursor: Option<&str>,
+        pattern: Option<&str>,
+        strategy: Option<Strategy>,
+        count: usize,
+    ) -> Result<ScanPage> {
+        let count = count.max(1);
+        let glob = pattern.map(Glob::new);
+        let prefix = glob.as_ref().map(Glob::literal_prefix).unwrap_or_default();
+
+        // Start at the cursor or at the pattern's fixed prefix, whichever is later
+        let start = match cursor {
+            Some(c) if c >= prefix.as_str() => Bound::Excluded(c.to_string()),
+            _ => Bound::Included(prefix.clone()),
+        };
+        // Each examined id with its strategy, or None if it has expired
+        let resident: Vec<(String, Option<Strategy>)> = {
+            let order = self.order.read();
+            order
+                .range((start, Bound::Unbounded))
+                .take_while(|key| key.starts_with(&prefix))
+                .filter(|key| glob.as_ref().is_none_or(|g| g.matches(key)))
+                .filter_map(|key| {
+                    let doc = self.documents.get(key.as_str())?;
+                    Some((key.clone(), (!doc.is_expired()).then(|| doc.strategy())))
+                })
+                .take(count)
+                .collect()
+        };
+
+        let stored = match self.backing {
+            Some(ref backing) => backing.scan(cursor, pattern, count)?,
+            None => Vec::new(),
+        };
+        let exhausted = resident.len() < count && stored.len() < count;
+        let stored = stored
+            .into_iter()
+            .map(|meta| (meta.id.as_str().to_string(), (!meta.is_expired()).then_some(meta.strategy)));
+
+        // Merge both sorted sources; the resident copy wins for shared ids
+        let mut examined: Vec<(String, Option<Strategy>)> = resident;
+        examined.extend(stored);
+        examined.sort_by(|a, b| a.0.cmp(&b.0));
+        examined.dedup_by(|a, b| a.0 == b.0);
+        let more = examined.len() > count;
+        examined.truncate(count);
+
+        let cursor = if exhausted && !more {
+            None
+        } else {
+            examined.last().map(|(key, _)| key.clone())
+        };
+        let ids = examined
+            .into_iter()
+            .filter(|(_, s)| s.is_some_and(|s| strategy.is_none_or(|wanted| s == wanted)))
+            .filter_map(|(key, _)| DocumentId::new(key).ok())
+            .collect();
+
+        Ok(ScanPage { ids, cursor })
+    }
+
+    /// Subscriber registry that `publish_update` routes through
+    pub fn subscriptions(&self) -> &SubscriptionRegistry {
+        &self.subscriptions
     }
 
-    /// Publish an update to all subscribers
+    /// Publish an update to the subscribers of its document
     pub fn publish_update(&self, delta: Delta) {
-        let _ = self.update_sender.send(delta);
+        self.subscriptions.publish(delta);
     }
 
     /// Set presence for a client
@@ -132,7 +448,7 @@ impl DocumentManager {
         let key = document_id.as_str().to_string();
         self.presence
             .entry(key)
-            .or_insert_with(Vec::new)
+            .or_default()
             .retain(|p| p.client_id != client_id);
 
         self.presence
@@ -171,6 +487,8 @@ impl DocumentManager {
 
     /// Run garbage collection - removes expired documents
     /// Returns the number of documents removed
+    ///
+    /// Covers documents that are only in the backing store as well.
     pub fn gc(&self) -> usize {
         let mut to_remove = Vec::new();
 
@@ -180,11 +498,43 @@ impl DocumentManager {
             }
         }
 
-        let count = to_remove.len();
+        let mut count = to_remove.len();
         for key in to_remove {
-            self.documents.remove(&key);
+            if let Some((_, doc)) = self.documents.remove(&key) {
+                self.publish_update(Delta::removed(&doc.meta(), EventKind::Expired));
+            }
+            self.order.write().remove(&key);
             // Also clean up any presence data
             self.presence.remove(&key);
+
+            if let Some(ref backing) = self.backing {
+                if let Ok(id) = DocumentId::new(&key) {
+                    if let Err(e) = backing.delete(&id) {
+                        warn!(doc_id = %key, error = %e, "Failed to drop expired document from storage");
+                    }
+                }
+            }
+        }
+
+        let stored = match self.stored_only(None) {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!(error = %e, "Failed to scan storage for expired documents");
+                return count;
+            }
+        };
+        for meta in stored.into_iter().filter(DocumentMeta::is_expired) {
+            let Some(ref backing) = self.backing else { break };
+            match backing.delete(&meta.id) {
+                Ok(()) => {
+                    self.presence.remove(meta.id.as_str());
+                    self.publish_update(Delta::removed(&meta, EventKind::Expired));
+                    count += 1;
+                }
+                Err(e) => {
+                    warn!(doc_id = %meta.id, error = %e, "Failed to drop expired document from storage");
+                }
+            }
         }
 
         count
@@ -199,89 +549,241 @@ impl DocumentManager {
     }
 
     /// Get statistics
-    pub fn stats(&self) -> ManagerStats {
-        ManagerStats {
-            document_count: self.documents.len(),
-            subscriber_count: self.update_sender.receiver_count(),
-        }
+    ///
+    /// Counts documents that are only in the backing store too, so this
+    /// fails if the store cannot be read.
+    pub fn stats(&self) -> Result<ManagerStats> {
+        let resident_count = self.documents.len();
+        Ok(ManagerStats {
+            document_count: resident_count + self.stored_only(None)?.len(),
+            resident_count,
+            subscriber_count: self.subscriptions.subscriber_count(),
+        })
     }
 
-    /// Simple glob pattern matching
-    fn matches_pattern(key: &str, pattern: &str) -> bool {
-        if pattern == "*" {
-            return true;
-        }
+    /// Record a snapshot that was written successfully
+    pub fn record_snapshot(&self, info: SnapshotInfo) {
+        *self.last_snapshot.write() = Some(info);
+    }
+
+    /// The last snapshot recorded with [`Self::record_snapshot`]
+    pub fn last_snapshot(&self) -> Option<SnapshotInfo> {
+        self.last_snapshot.read().clone()
+    }
 
-        if let Some(prefix) = pattern.strip_suffix('*') {
-            return key.starts_with(prefix);
+    /// Load a document from the backing store into memory
+    ///
+    /// Expired documents are treated as missing.
+    fn load_from_backing(&self, id: &DocumentId) -> Result<Option<Arc<Document>>> {
+        let Some(ref backing) = self.backing else {
+            return Ok(None);
+        };
+
+        let Some((meta, snapshot)) = backing.load(id)? else {
+            return Ok(None);
+        };
+        if meta.is_expired() {
+            return Ok(None);
         }
 
-        if let Some(suffix) = pattern.strip_prefix('*') {
-            return key.ends_with(suffix);
+        let doc = Arc::new(Document::from_snapshot(meta.clone(), &snapshot)?);
+        // Another caller may have loaded it concurrently; keep the first one
+        self.order.write().insert(id.as_str().to_string());
+        let doc = match self.documents.entry(id.as_str().to_string()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => {
+                // Recorded before anyone can change it
+                if let Some(ref mut loaded) = *self.loaded_during_backup.lock() {
+                    loaded
+                        .entry(id.as_str().to_string())
+                        .or_insert_with(|| DocumentBackup::stored(meta, snapshot));
+                }
+                entry.insert(doc).clone()
+            }
+        };
+        doc.touch(self.tick());
+        Ok(Some(doc))
+    }
+
+    /// Metadata of the documents matching `pattern` that are in the backing
+    /// store but not resident
+    fn stored_only(&self, pattern: Option<&str>) -> Result<Vec<DocumentMeta>> {
+        let Some(ref backing) = self.backing else {
+            return Ok(Vec::new());
+        };
+
+        let mut stored = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = backing.scan(after.as_deref(), pattern, STORED_PAGE)?;
+            let done = page.len() < STORED_PAGE;
+            after = page.last().map(|meta| meta.id.as_str().to_string());
+            stored.extend(page.into_iter().filter(|meta| !self.documents.contains_key(meta.id.as_str())));
+            if done {
+                return Ok(stored);
+            }
         }
+    }
 
-        key == pattern
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
     }
 
-    /// Create a backup of all documents
-    pub fn backup(&self) -> Backup {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
+    /// Hold off backups while changing documents
+    ///
+    /// Callers that modify documents (commands, not reads) keep the guard
+    /// until the change and its delta are complete, so a backup never
+    /// captures a change half-applied. Must not be held while calling
+    /// `backup` or `restore`, which take the barrier themselves.
+    pub fn write_barrier(&self) -> RwLockReadGuard<'_, ()> {
+        self.barrier.read()
+    }
 
-        let documents: Vec<DocumentBackup> = self
-            .documents
-            .iter()
-            .filter(|entry| !entry.value().is_expired())
-            .map(|entry| {
-                let doc = entry.value();
-                DocumentBackup {
-                    id: entry.key().clone(),
-                    strategy: doc.strategy().to_string(),
-                    state: doc.encode_state(),
-                    ttl_remaining_ms: doc.ttl_remaining(),
-                }
-            })
-            .collect();
+    /// Create a backup of all documents, as of a single instant
+    ///
+    /// Each document is saved as a full snapshot (Y.js state and LWW data)
+    /// with its complete metadata, so every strategy round-trips. Writers
+    /// holding [`Self::write_barrier`] are paused while the snapshots are
+    /// taken, and the backup records that instant in `taken_at`.
+    ///
+    /// Documents that are only in the backing store are read from it after
+    /// the pause, without making them resident. They cannot change while
+    /// they are not resident, and one loaded in the meantime is backed up
+    /// as it was when loaded.
+    pub fn backup(&self) -> Result<Backup> {
+        let _backing_up = self.backing_up.lock();
+        let (taken_at, mut documents) = {
+            let _barrier = self.barrier.write();
+            if self.backing.is_some() {
+                *self.loaded_during_backup.lock() = Some(HashMap::new());
+            }
+            let taken_at = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            let documents: Vec<DocumentBackup> = self
+                .documents
+                .iter()
+                .filter(|entry| !entry.value().is_expired())
+                .map(|entry| DocumentBackup::new(entry.value()))
+                .collect();
+            (taken_at, documents)
+        };
+
+        if self.backing.is_some() {
+            let resident: BTreeSet<String> = documents.iter().map(|d| d.id.clone()).collect();
+            let cold = self.read_cold(&resident);
+            let loaded = self.loaded_during_backup.lock().take().unwrap_or_default();
+            let mut cold = cold?;
+            // The state a document was loaded in predates its later changes
+            cold.extend(loaded.into_iter().filter(|(id, _)| !resident.contains(id)));
+            documents.extend(
+                cold.into_values()
+                    .filter(|d| !d.meta.as_ref().is_some_and(DocumentMeta::is_expired)),
+            );
+        }
 
-        Backup {
-            version: 1,
-            timestamp,
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Backup {
+            version: BACKUP_VERSION,
+            timestamp: taken_at / 1000,
+            taken_at: Some(taken_at),
             documents,
+        })
+    }
+
+    /// Read the stored documents that are not in `resident`
+    fn read_cold(&self, resident: &BTreeSet<String>) -> Result<HashMap<String, DocumentBackup>> {
+        let Some(ref backing) = self.backing else {
+            return Ok(HashMap::new());
+        };
+
+        let mut cold = HashMap::new();
+        for meta in self.stored_only(None)? {
+            if meta.is_expired() || resident.contains(meta.id.as_str()) {
+                continue;
+            }
+            if let Some((meta, snapshot)) = backing.load(&meta.id)? {
+                cold.insert(meta.id.as_str().to_string(), DocumentBackup::stored(meta, snapshot));
+            }
         }
+        Ok(cold)
     }
 
     /// Restore documents from a backup
     /// Returns the number of documents restored
+    ///
+    /// Reads both formats: version 1 (Y.js state and remaining TTL only)
+    /// and version 2. Documents whose expiry has passed are skipped. A
+    /// backup taken meanwhile sees either none or all of the documents.
     pub fn restore(&self, backup: &Backup) -> Result<usize> {
-        let mut restored = 0;
+        check_backup_version(backup.version)?;
 
+        let _barrier = self.write_barrier();
+        let mut restored = 0;
         for doc_backup in &backup.documents {
-            let id = DocumentId::new(&doc_backup.id)?;
-            let strategy: Strategy = doc_backup.strategy.parse()
-                .map_err(|_| Error::InvalidStrategy(doc_backup.strategy.clone()))?;
-
-            // Create document with TTL if it had one
-            let ttl = doc_backup.ttl_remaining_ms
-                .filter(|&t| t > 0)
-                .map(|t| t as u64);
-
-            let doc = match ttl {
-                Some(ttl_ms) => Document::with_ttl(id.clone(), strategy, ttl_ms),
-                None => Document::new(id.clone(), strategy),
-            };
-
-            // Restore the state
-            doc.apply_state(&doc_backup.state)?;
-
-            // Insert into manager (overwrite if exists)
-            self.documents.insert(doc_backup.id.clone(), Arc::new(doc));
-            restored += 1;
+            if self.restore_entry(backup.version, doc_backup)? {
+                restored += 1;
+            }
         }
 
         Ok(restored)
     }
+
+    /// Restore a single document from a backup in format `version`
+    ///
+    /// Returns false if the document had already expired and was skipped.
+    pub fn restore_document(&self, version: u32, doc_backup: &DocumentBackup) -> Result<bool> {
+        check_backup_version(version)?;
+
+        let _barrier = self.write_barrier();
+        self.restore_entry(version, doc_backup)
+    }
+
+    fn restore_entry(&self, version: u32, doc_backup: &DocumentBackup) -> Result<bool> {
+        let id = DocumentId::new(&doc_backup.id)?;
+        let doc = match doc_backup.meta {
+            Some(ref meta) if version >= 2 => {
+                let mut meta = meta.clone();
+                meta.id = id;
+                meta.ttl = doc_backup
+                    .expires_at
+                    .map(|at| at.saturating_sub(meta.created_at));
+                if meta.is_expired() {
+                    return Ok(false);
+                }
+                Document::from_snapshot(meta, &doc_backup.state)?
+            }
+            _ => Self::restore_v1(id, doc_backup)?,
+        };
+
+        // Insert into manager (overwrite if exists)
+        let delta = doc.full_delta(EventKind::Restored);
+        self.order.write().insert(doc_backup.id.clone());
+        self.documents.insert(doc_backup.id.clone(), Arc::new(doc));
+        self.publish_update(delta);
+        Ok(true)
+    }
+
+    /// A document from a version 1 entry: Y.js state, TTL counted from now
+    fn restore_v1(id: DocumentId, doc_backup: &DocumentBackup) -> Result<Document> {
+        let strategy: Strategy = doc_backup.strategy.parse()
+            .map_err(|_| Error::InvalidStrategy(doc_backup.strategy.clone()))?;
+
+        // Create document with TTL if it had one
+        let ttl = doc_backup.ttl_remaining_ms
+            .filter(|&t| t > 0)
+            .map(|t| t as u64);
+
+        let doc = match ttl {
+            Some(ttl_ms) => Document::with_ttl(id, strategy, ttl_ms),
+            None => Document::new(id, strategy),
+        };
+
+        // Restore the state
+        doc.apply_state(&doc_backup.state)?;
+        Ok(doc)
+    }
 }
 
 impl Default for DocumentManager {
@@ -293,24 +795,85 @@ impl Default for DocumentManager {
 /// Manager statistics
 #[derive(Debug, Clone)]
 pub struct ManagerStats {
+    /// Documents in memory or in the backing store
     pub document_count: usize,
+    /// Documents in memory
+    pub resident_count: usize,
     pub subscriber_count: usize,
 }
 
+/// Page size used to walk the backing store
+const STORED_PAGE: usize = 1000;
+
+/// Format version written by `DocumentManager::backup`
+pub const BACKUP_VERSION: u32 = 2;
+
+/// Fail unless backups in format `version` can be restored
+pub(crate) fn check_backup_version(version: u32) -> Result<()> {
+    if version == 0 || version > BACKUP_VERSION {
+        return Err(Error::RestoreError(format!("Unsupported backup version {}", version)));
+    }
+    Ok(())
+}
+
 /// Backup format for a single document
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentBackup {
     pub id: String,
     pub strategy: String,
+    /// Version 1: Y.js state only. Version 2: `Document::encode_snapshot`
+    ///
+    /// Written as base64; an array of numbers is read too.
+    #[serde(with = "crate::base64::serde")]
     pub state: Vec<u8>,
+    /// Remaining TTL when the backup was taken (version 1 only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ttl_remaining_ms: Option<i64>,
+    /// Complete metadata (version 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<DocumentMeta>,
+    /// When the document expires, in ms since the epoch (version 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl DocumentBackup {
+    /// Current-format entry for `doc`
+    pub fn new(doc: &Document) -> Self {
+        Self::stored(doc.meta(), doc.encode_snapshot())
+    }
+
+    /// Current-format entry for a snapshot read from a backing store
+    pub fn stored(meta: DocumentMeta, snapshot: Vec<u8>) -> Self {
+        Self {
+            id: meta.id.as_str().to_string(),
+            strategy: meta.strategy.to_string(),
+            state: snapshot,
+            ttl_remaining_ms: None,
+            expires_at: meta.expires_at(),
+            meta: Some(meta),
+        }
+    }
+}
+
+/// One page of `DocumentManager::scan`
+#[derive(Debug, Clone, Default)]
+pub struct ScanPage {
+    /// Matching document IDs, in order
+    pub ids: Vec<DocumentId>,
+    /// Where the next page starts, or None if the walk is complete
+    pub cursor: Option<String>,
 }
 
 /// Full backup containing all documents
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Backup {
     pub version: u32,
+    /// Seconds since the epoch when the backup was taken
     pub timestamp: u64,
+    /// Milliseconds since the epoch of the instant the documents reflect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<u64>,
     pub documents: Vec<DocumentBackup>,
 }
 
@@ -341,11 +904,215 @@ mod tests {
     }
 
     #[test]
-    fn test_pattern_matching() {
-        assert!(DocumentManager::matches_pattern("user:123", "user:*"));
-        assert!(DocumentManager::matches_pattern("user:123", "*:123"));
-        assert!(DocumentManager::matches_pattern("anything", "*"));
-        assert!(!DocumentManager::matches_pattern("cart:456", "user:*"));
+    fn test_load_keeps_meta() {
+        let manager = DocumentManager::new();
+        let id = DocumentId::new("load:1").unwrap();
+
+        let mut meta = DocumentMeta::with_ttl(id.clone(), Strategy::CrdtText, 60_000);
+        meta.version = 42;
+        meta.created_at -= 1_000;
+
+        manager.load(meta.clone(), &[]).unwrap();
+
+        let doc = manager.get(&id).unwrap();
+        assert_eq!(doc.strategy(), Strategy::CrdtText);
+        assert_eq!(doc.version(), 42);
+        assert_eq!(doc.meta().created_at, meta.created_at);
+        assert!(doc.ttl_remaining().unwrap() <= 59_000);
+    }
+
+    #[test]
+    fn test_backup_restore_counter() {
+        let manager = DocumentManager::new();
+        let id = DocumentId::new("counter:1").unwrap();
+
+        let doc = manager.create(id.clone(), Strategy::CrdtCounter, None).unwrap();
+        doc.increment("hits", 4).unwrap();
+
+        let backup = manager.backup().unwrap();
+        let restored = DocumentManager::new();
+        restored.restore(&backup).unwrap();
+
+        let doc = restored.get(&id).unwrap();
+        assert_eq!(doc.get(Some("hits")).unwrap(), Value::from(4i64));
+        assert_eq!(doc.increment("hits", 1).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_backup_is_point_in_time() {
+        let manager = Arc::new(DocumentManager::new());
+        let from = manager.create(DocumentId::new("acct:a").unwrap(), Strategy::CrdtCounter, None).unwrap();
+        let to = manager.create(DocumentId::new("acct:b").unwrap(), Strategy::CrdtCounter, None).unwrap();
+
+        // Transfers between two documents; a backup must never see one half
+        let writer = {
+            let manager = manager.clone();
+            std::thread::spawn(move || {
+                for _ in 0..500 {
+                    let _barrier = manager.write_barrier();
+                    from.increment("balance", -1).unwrap();
+                    to.increment("balance", 1).unwrap();
+                }
+            })
+        };
+
+        let mut backups = Vec::new();
+        while !writer.is_finished() {
+            backups.push(manager.backup().unwrap());
+        }
+        writer.join().unwrap();
+        backups.push(manager.backup().unwrap());
+
+        for backup in backups {
+            assert!(backup.taken_at.is_some_and(|at| at / 1000 == backup.timestamp));
+            let restored = DocumentManager::new();
+            restored.restore(&backup).unwrap();
+            let balance = |id: &str| {
+                let doc = restored.get(&DocumentId::new(id).unwrap()).unwrap();
+                doc.get(Some("balance")).ok().and_then(|v| v.as_i64()).unwrap_or(0)
+            };
+            assert_eq!(balance("acct:a") + balance("acct:b"), 0);
+        }
+    }
+
+    /// Id whose next load signals the first channel, then waits on the second
+    type Gate = (String, std::sync::mpsc::Sender<()>, std::sync::mpsc::Receiver<()>);
+
+    /// Backing store whose next load of a gated document waits until released
+    #[derive(Default)]
+    struct GatedBacking {
+        docs: Mutex<HashMap<String, (DocumentMeta, Vec<u8>)>>,
+        gated: Mutex<Option<Gate>>,
+    }
+
+    impl Backing for GatedBacking {
+        fn load(&self, id: &DocumentId) -> Result<Option<(DocumentMeta, Vec<u8>)>> {
+            let gate = self.gated.lock().take_if(|(gated, _, _)| gated == id.as_str());
+            if let Some((_, reached, resume)) = gate {
+                reached.send(()).unwrap();
+                resume.recv().unwrap();
+            }
+            Ok(self.docs.lock().get(id.as_str()).cloned())
+        }
+
+        fn flush(&self, meta: &DocumentMeta, snapshot: &[u8]) -> Result<()> {
+            self.docs.lock().insert(meta.id.as_str().to_string(), (meta.clone(), snapshot.to_vec()));
+            Ok(())
+        }
+
+        fn delete(&self, id: &DocumentId) -> Result<()> {
+            self.docs.lock().remove(id.as_str());
+            Ok(())
+        }
+
+        fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>> {
+            Ok(self.docs.lock().values().map(|(meta, _)| meta.id.clone())
+                .filter(|id| pattern.is_none_or(|p| pattern::matches(p, id.as_str())))
+                .collect())
+        }
+
+        fn scan(&self, after: Option<&str>, pattern: Option<&str>, count: usize) -> Result<Vec<DocumentMeta>> {
+            let mut metas: Vec<DocumentMeta> = self.docs.lock().values().map(|(meta, _)| meta.clone())
+                .filter(|meta| after.is_none_or(|a| meta.id.as_str() > a))
+                .filter(|meta| pattern.is_none_or(|p| pattern::matches(p, meta.id.as_str())))
+                .collect();
+            metas.sort_by(|a, b| a.id.as_str().cmp(b.id.as_str()));
+            metas.truncate(count);
+            Ok(metas)
+        }
+    }
+
+    #[test]
+    fn test_backup_reads_cold_documents_after_the_pause() {
+        let backing = Arc::new(GatedBacking::default());
+        let manager = Arc::new(
+            DocumentManager::new().with_backing(backing.clone(), CacheConfig::new(0, EvictionPolicy::Lru)),
+        );
+        let ids: Vec<DocumentId> = ["cold:a", "cold:b", "hot"].iter().map(|id| DocumentId::new(*id).unwrap()).collect();
+        for id in &ids {
+            manager.create(id.clone(), Strategy::Lww, None).unwrap().set("v", Value::from(1i64)).unwrap();
+        }
+        let hot = manager.get(&ids[2]).unwrap();
+        assert_eq!(manager.evict().unwrap(), 2);
+
+        let (reached_tx, reached) = std::sync::mpsc::channel();
+        let (resume, resume_rx) = std::sync::mpsc::channel();
+        *backing.gated.lock() = Some(("cold:a".into(), reached_tx, resume_rx));
+        let backup = {
+            let manager = manager.clone();
+            std::thread::spawn(move || manager.backup().unwrap())
+        };
+        reached.recv().unwrap();
+
+        // Writers go ahead while cold documents are read
+        assert!(manager.barrier.try_read().is_some());
+        hot.set("v", Value::from(2i64)).unwrap();
+        let loaded = manager.get(&ids[1]).unwrap();
+        loaded.set("v", Value::from(2i64)).unwrap();
+        backing.flush(&loaded.meta(), &loaded.encode_snapshot()).unwrap();
+        resume.send(()).unwrap();
+
+        // Yet the backup holds every document as of its instant
+        let restored = DocumentManager::new();
+        assert_eq!(restored.restore(&backup.join().unwrap()).unwrap(), 3);
+        for id in &ids {
+            assert_eq!(restored.get(id).unwrap().get(Some("v")).unwrap(), Value::from(1i64), "{}", id);
+        }
+    }
+
+    #[test]
+    fn test_backup_restore_every_strategy() {
+        let manager = DocumentManager::new();
+        let lww = manager.create(DocumentId::new("b:lww").unwrap(), Strategy::Lww, Some(3_600_000)).unwrap();
+        lww.set("name", Value::from("alice")).unwrap();
+        let map = manager.create(DocumentId::new("b:map").unwrap(), Strategy::CrdtMap, None).unwrap();
+        map.set("profile.city", Value::from("Lyon")).unwrap();
+        let counter = manager.create(DocumentId::new("b:counter").unwrap(), Strategy::CrdtCounter, None).unwrap();
+        counter.increment("hits", 3).unwrap();
+        let set = manager.create(DocumentId::new("b:set").unwrap(), Strategy::CrdtSet, None).unwrap();
+        set.set_add("tags", Value::from("red")).unwrap();
+        let text = manager.create(DocumentId::new("b:text").unwrap(), Strategy::CrdtText, None).unwrap();
+        text.text_insert(0, "hello").unwrap();
+
+        // Round-trip through JSON, as BACKUP and RESTORE do
+        let backup: Backup = serde_json::from_str(&serde_json::to_string(&manager.backup().unwrap()).unwrap()).unwrap();
+        assert_eq!(backup.version, BACKUP_VERSION);
+        let restored = DocumentManager::new();
+        assert_eq!(restored.restore(&backup).unwrap(), 5);
+
+        for original in [&lww, &map, &counter, &set, &text] {
+            let copy = restored.get(&original.id()).unwrap();
+            assert_eq!(copy.get(None).unwrap(), original.get(None).unwrap(), "{}", original.id());
+            let (a, b) = (copy.meta(), original.meta());
+            assert_eq!((a.strategy, a.created_at, a.updated_at, a.version), (b.strategy, b.created_at, b.updated_at, b.version));
+            assert_eq!(a.expires_at(), b.expires_at());
+        }
+    }
+
+    #[test]
+    fn test_restore_v1_backup() {
+        let source = Document::new(DocumentId::new("old:1").unwrap(), Strategy::CrdtText);
+        source.text_insert(0, "legacy").unwrap();
+        let v1 = serde_json::json!({
+            "version": 1,
+            "timestamp": 0,
+            "documents": [{
+                "id": "old:1",
+                "strategy": "crdt-text",
+                "state": source.encode_state(),
+                "ttl_remaining_ms": 60_000,
+            }],
+        });
+
+        let manager = DocumentManager::new();
+        let backup: Backup = serde_json::from_value(v1).unwrap();
+        assert_eq!(manager.restore(&backup).unwrap(), 1);
+        let doc = manager.get(&DocumentId::new("old:1").unwrap()).unwrap();
+        assert_eq!(doc.get(None).unwrap(), Value::from("legacy"));
+        assert!(doc.ttl_remaining().unwrap() > 59_000);
+
+        let future = Backup { version: BACKUP_VERSION + 1, timestamp: 0, taken_at: None, documents: Vec::new() };
+        assert!(matches!(manager.restore(&future), Err(Error::RestoreError(_))));
     }
 
     #[test]
@@ -358,25 +1125,57 @@ mod tests {
 
         let users = manager.list(Some("user:*"));
         assert_eq!(users.len(), 2);
+        assert_eq!(manager.list(Some("*:1")).len(), 2);
+        assert_eq!(manager.list(Some("user:[2-9]")).len(), 1);
+        assert_eq!(manager.keys(Some("?ser:?")).unwrap().len(), 2);
 
         let all = manager.list(None);
         assert_eq!(all.len(), 3);
     }
 
+    #[test]
+    fn test_scan_pages_in_order() {
+        let manager = DocumentManager::new();
+        for i in (0..7).rev() {
+            let strategy = if i % 2 == 0 { Strategy::Lww } else { Strategy::CrdtCounter };
+            manager.create(DocumentId::new(format!("item:{}", i)).unwrap(), strategy, None).unwrap();
+        }
+        manager.create(DocumentId::new("other:1").unwrap(), Strategy::Lww, None).unwrap();
+
+        let page = manager.scan(None, Some("item:*"), None, 3).unwrap();
+        let ids: Vec<&str> = page.ids.iter().map(|id| id.as_str()).collect();
+        assert_eq!(ids, ["item:0", "item:1", "item:2"]);
+        assert_eq!(page.cursor.as_deref(), Some("item:2"));
+
+        // Documents created mid-walk before the cursor are not revisited
+        manager.create(DocumentId::new("item:00").unwrap(), Strategy::Lww, None).unwrap();
+        let page = manager.scan(page.cursor.as_deref(), Some("item:*"), Some(Strategy::Lww), 3).unwrap();
+        let ids: Vec<&str> = page.ids.iter().map(|id| id.as_str()).collect();
+        assert_eq!(ids, ["item:4"]);
+
+        let page = manager.scan(page.cursor.as_deref(), Some("item:*"), None, 3).unwrap();
+        let ids: Vec<&str> = page.ids.iter().map(|id| id.as_str()).collect();
+        assert_eq!(ids, ["item:6"]);
+        assert_eq!(page.cursor, None);
+
+        manager.delete(&DocumentId::new("item:6").unwrap()).unwrap();
+        assert_eq!(manager.scan(Some("item:5"), None, None, 10).unwrap().ids.len(), 1);
+    }
+
     #[test]
     fn test_create_with_ttl() {
         let manager = DocumentManager::new();
         let id = DocumentId::new("ttl:1").unwrap();
 
         // Create with 1 hour TTL
-        let doc = manager.create(id.clone(), Strategy::Lww, Some(3600_000)).unwrap();
+        let doc = manager.create(id.clone(), Strategy::Lww, Some(3_600_000)).unwrap();
 
         // Document should not be expired yet
         assert!(!doc.is_expired());
 
         // TTL should be approximately 1 hour (in ms)
         let ttl = doc.ttl_remaining().unwrap();
-        assert!(ttl > 3599_000 && ttl <= 3600_000);
+        assert!(ttl > 3_599_000 && ttl <= 3_600_000);
     }
 
     #[test]
@@ -425,8 +1224,34 @@ mod tests {
         assert_eq!(removed, 1);
 
         // Only the non-expired document should remain
-        assert_eq!(manager.stats().document_count, 1);
+        assert_eq!(manager.stats().unwrap().document_count, 1);
         assert!(manager.get(&id).is_err()); // Expired doc removed
         assert!(manager.get(&id2).is_ok()); // Non-expired doc remains
     }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let manager = DocumentManager::new();
+        let subscriber = manager.subscriptions().register();
+        manager.subscriptions().add(subscriber, "*");
+        let mut rx = manager.subscriptions().receiver(subscriber).unwrap();
+        let id = DocumentId::new("events:1").unwrap();
+
+        manager.create(id.clone(), Strategy::Lww, None).unwrap();
+        manager.get_or_create(id.clone(), Strategy::Lww).unwrap();
+        let backup = manager.backup().unwrap();
+        manager.delete(&id).unwrap();
+        manager.restore(&backup).unwrap();
+        manager.set_expire(&id, Some(1)).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        manager.gc();
+
+        let events: Vec<EventKind> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|delta| delta.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![EventKind::Created, EventKind::Deleted, EventKind::Restored, EventKind::Expired]
+        );
+    }
 }
@@ -0,0 +1,231 @@
+//! Nested map CRDT stored inside a document's Y.Doc
+//!
+//! A `crdt-map` document is a tree of Y.Maps under a single root map. Each
+//! object level is its own Y.Map, so replicas editing different keys of the
+//! same object merge cleanly, while concurrent writes to the same key resolve
+//! last-writer-wins. Non-object values (strings, numbers, arrays) are stored
+//! as plain Y.js values and are replaced as a whole.
+
+use crate::crdt::{Number, PathSegment, Value};
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+use yrs::types::{ToJson, Value as YValue};
+use yrs::{Any, Doc, Map, MapPrelim, MapRef, ReadTxn, Transact, TransactionMut};
+
+/// Name of the root Y.Map holding the document content
+const ROOT: &str = "data";
+
+/// Largest integer a JavaScript number represents exactly (2^53 - 1)
+const MAX_SAFE_INTEGER: i64 = 9_007_199_254_740_991;
+
+/// Read the value at `path`, or the whole map if `path` is None
+pub(crate) fn get(doc: &Doc, path: Option<&str>) -> Result<Value> {
+    // Read-only lookup, so readers can share the Y.Doc
+    let txn = doc.transact();
+    let data = match txn.get_map(ROOT) {
+        Some(root) => any_to_value(root.to_json(&txn)),
+        None => Value::Object(HashMap::new()),
+    };
+
+    match path {
+        Some(p) => data
+            .get_path(p)
+            .cloned()
+            .ok_or_else(|| Error::InvalidPath(p.to_string())),
+        None => Ok(data),
+    }
+}
+
+/// Write `value` at `path`, creating intermediate maps as needed
+///
+/// Objects become nested Y.Maps. An empty path replaces the whole content
+/// and therefore requires an object.
+pub(crate) fn set(doc: &Doc, path: &str, value: Value) -> Result<()> {
+    let keys = keys(path)?;
+    let root = doc.get_or_insert_map(ROOT);
+    let mut txn = doc.transact_mut();
+
+    let Some((last, parents)) = keys.split_last() else {
+        let Value::Object(entries) = value else {
+            return Err(Error::Crdt("crdt-map content must be an object".into()));
+        };
+        root.clear(&mut txn);
+        for (key, value) in entries {
+            insert(&root, &mut txn, &key, value);
+        }
+        return Ok(());
+    };
+
+    let mut current = root;
+    for key in parents {
+        current = match current.get(&txn, key) {
+            Some(YValue::YMap(map)) => map,
+            _ => current.insert(&mut txn, key.to_string(), MapPrelim::<Any>::new()),
+        };
+    }
+    insert(&current, &mut txn, last, value);
+    Ok(())
+}
+
+/// Remove the entry at `path`, or all entries if `path` is None
+///
+/// Returns true if anything was removed.
+pub(crate) fn delete(doc: &Doc, path: Option<&str>) -> Result<bool> {
+    let root = doc.get_or_insert_map(ROOT);
+    let mut txn = doc.transact_mut();
+
+    let Some(path) = path else {
+        let removed = root.len(&txn) > 0;
+        root.clear(&mut txn);
+        return Ok(removed);
+    };
+
+    let keys = keys(path)?;
+    let Some((last, parents)) = keys.split_last() else {
+        return Ok(false);
+    };
+
+    let mut current = root;
+    for key in parents {
+        current = match current.get(&txn, key) {
+            Some(YValue::YMap(map)) => map,
+            _ => return Ok(false),
+        };
+    }
+    Ok(current.remove(&mut txn, last).is_some())
+}
+
+/// Split a dotted path into map keys; array indexes are not addressable
+fn keys(path: &str) -> Result<Vec<&str>> {
+    PathSegment::parse(path)
+        .map(|segment| match segment {
+            PathSegment::Key(key) => Ok(key),
+            PathSegment::Index(_) => Err(Error::InvalidPath(format!(
+                "{}: crdt-map paths cannot index into arrays",
+                path
+            ))),
+        })
+        .collect()
+}
+
+fn insert(map: &MapRef, txn: &mut TransactionMut, key: &str, value: Value) {
+    match value {
+        Value::Object(entries) => {
+            let child = map.insert(txn, key.to_string(), MapPrelim::<Any>::new());
+            for (key, value) in entries {
+                insert(&child, txn, &key, value);
+            }
+        }
+        other => {
+            map.insert(txn, key.to_string(), value_to_any(other));
+        }
+    }
+}
+
+/// Convert a document value into a Y.js value
+///
+/// Integers within the JavaScript safe range are stored as numbers so Yjs
+/// clients read them as plain numbers.
+pub(crate) fn value_to_any(value: Value) -> Any {
+    match value {
+        Value::Null => Any::Null,
+        Value::Bool(b) => Any::Bool(b),
+        Value::Number(Number::Integer(n)) if n.abs() <= MAX_SAFE_INTEGER => Any::Number(n as f64),
+        Value::Number(Number::Integer(n)) => Any::BigInt(n),
+        Value::Number(Number::Float(n)) => Any::Number(n),
+        Value::String(s) => Any::String(s.into()),
+        Value::Binary(b) => Any::Buffer(b.into()),
+        Value::Array(items) => Any::Array(items.into_iter().map(value_to_any).collect()),
+        Value::Object(entries) => Any::Map(Arc::new(
+            entries.into_iter().map(|(k, v)| (k, value_to_any(v))).collect(),
+        )),
+    }
+}
+
+/// Convert a Y.js value into a document value
+///
+/// Whole numbers come back as integers, since Y.js has a single number type.
+pub(crate) fn any_to_value(any: Any) -> Value {
+    match any {
+        Any::Null | Any::Undefined => Value::Null,
+        Any::Bool(b) => Value::Bool(b),
+        Any::Number(n) if n.fract() == 0.0 && n.abs() <= MAX_SAFE_INTEGER as f64 => {
+            Value::Number(Number::Integer(n as i64))
+        }
+        Any::Number(n) => Value::Number(Number::Float(n)),
+        Any::BigInt(n) => Value::Number(Number::Integer(n)),
+        Any::String(s) => Value::String(s.to_string()),
+        Any::Buffer(b) => Value::Binary(b.to_vec()),
+        Any::Array(items) => Value::Array(items.iter().cloned().map(any_to_value).collect()),
+        Any::Map(entries) => Value::Object(
+            entries
+                .iter()
+                .map(|(k, v)| (k.clone(), any_to_value(v.clone())))
+                .collect::<HashMap<_, _>>(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::updates::decoder::Decode;
+    use yrs::{ReadTxn, StateVector, Update};
+
+    fn sync(from: &Doc, to: &Doc) {
+        let update = from.transact().encode_state_as_update_v1(&StateVector::default());
+        to.transact_mut().apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_keys_merge() {
+        let a = Doc::new();
+        set(&a, "user.name", Value::from("Alice")).unwrap();
+
+        let b = Doc::new();
+        sync(&a, &b);
+
+        // Different keys of the same nested map, edited concurrently
+        set(&a, "user.email", Value::from("alice@example.com")).unwrap();
+        set(&b, "user.age", Value::from(30i64)).unwrap();
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        for doc in [&a, &b] {
+            assert_eq!(get(doc, Some("user.name")).unwrap(), Value::from("Alice"));
+            assert_eq!(get(doc, Some("user.email")).unwrap(), Value::from("alice@example.com"));
+            assert_eq!(get(doc, Some("user.age")).unwrap(), Value::from(30i64));
+        }
+    }
+
+    #[test]
+    fn test_delete_nested_key() {
+        let doc = Doc::new();
+        set(&doc, "prefs.theme", Value::from("dark")).unwrap();
+        set(&doc, "prefs.lang", Value::from("en")).unwrap();
+
+        assert!(delete(&doc, Some("prefs.theme")).unwrap());
+        assert!(!delete(&doc, Some("prefs.missing.key")).unwrap());
+
+        assert!(get(&doc, Some("prefs.theme")).is_err());
+        assert_eq!(get(&doc, Some("prefs.lang")).unwrap(), Value::from("en"));
+    }
+
+    #[test]
+    fn test_value_roundtrip() {
+        let mut obj = HashMap::new();
+        obj.insert("n".to_string(), Value::from(1.5));
+        let value = Value::Array(vec![
+            Value::Null,
+            Value::from(true),
+            Value::from(42i64),
+            Value::from(i64::MAX),
+            Value::from("s"),
+            Value::Object(obj),
+        ]);
+
+        assert_eq!(any_to_value(value_to_any(value.clone())), value);
+    }
+}
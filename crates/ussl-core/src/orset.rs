@@ -0,0 +1,152 @@
+//! Observed-Remove Set CRDT stored inside a document's Y.Doc
+//!
+//! Each member is an entry `<path>\x1f<member-json>` in a flat Y.Map. Y.Map
+//! already has observed-remove semantics: a removal only deletes the
+//! insertions it has seen, so an add that is concurrent with a remove
+//! survives the merge (add-wins). Members are keyed by their canonical JSON
+//! encoding, so equal values added on different replicas collapse into one.
+//!
+//! Like counters, entries live in a single flat map so that replicas never
+//! race on creating a nested container for a new path.
+
+use crate::crdt::{is_within, Value};
+use std::collections::BTreeMap;
+use yrs::{Doc, Map, ReadTxn, Transact};
+
+/// Name of the root Y.Map holding set members
+const SETS: &str = "sets";
+
+/// Separator between path and member; never appears in JSON output
+const SEPARATOR: char = '\u{1f}';
+
+/// Add `member` to the set at `path`; returns false if it was already present
+///
+/// The entry is rewritten even when present, giving the add a fresh tag so
+/// that it wins against a concurrent remove of the older one.
+pub(crate) fn add(doc: &Doc, path: &str, member: &Value) -> bool {
+    let sets = doc.get_or_insert_map(SETS);
+    let mut txn = doc.transact_mut();
+
+    let key = member_key(path, member);
+    let existed = sets.contains_key(&txn, &key);
+    sets.insert(&mut txn, key, true);
+    !existed
+}
+
+/// Remove `member` from the set at `path`; returns false if it was absent
+pub(crate) fn remove(doc: &Doc, path: &str, member: &Value) -> bool {
+    let sets = doc.get_or_insert_map(SETS);
+    let mut txn = doc.transact_mut();
+    sets.remove(&mut txn, &member_key(path, member)).is_some()
+}
+
+/// Whether `member` is in the set at `path`
+pub(crate) fn contains(doc: &Doc, path: &str, member: &Value) -> bool {
+    let txn = doc.transact();
+    txn.get_map(SETS)
+        .is_some_and(|sets| sets.contains_key(&txn, &member_key(path, member)))
+}
+
+/// Members of every set in the document, keyed by path
+///
+/// Members are ordered by their JSON encoding so every replica lists them
+/// identically.
+pub(crate) fn all(doc: &Doc) -> BTreeMap<String, Vec<Value>> {
+    // Read-only lookup, so readers can share the Y.Doc
+    let txn = doc.transact();
+    let Some(sets) = txn.get_map(SETS) else {
+        return BTreeMap::new();
+    };
+
+    let mut entries: Vec<(String, String)> = sets
+        .keys(&txn)
+        .filter_map(|key| {
+            let (path, member) = key.rsplit_once(SEPARATOR)?;
+            Some((path.to_string(), member.to_string()))
+        })
+        .collect();
+    entries.sort();
+
+    let mut all: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+    for (path, member) in entries {
+        if let Ok(value) = serde_json::from_str(&member) {
+            all.entry(path).or_default().push(value);
+        }
+    }
+    all
+}
+
+/// Drop sets at `path` (and below it), or all sets if `path` is None
+///
+/// Returns true if anything was removed.
+pub(crate) fn clear(doc: &Doc, path: Option<&str>) -> bool {
+    let sets = doc.get_or_insert_map(SETS);
+    let mut txn = doc.transact_mut();
+
+    let doomed: Vec<String> = sets
+        .keys(&txn)
+        .filter(|key| {
+            key.rsplit_once(SEPARATOR)
+                .is_some_and(|(set_path, _)| path.is_none_or(|p| is_within(set_path, p)))
+        })
+        .map(str::to_string)
+        .collect();
+
+    for key in &doomed {
+        sets.remove(&mut txn, key);
+    }
+    !doomed.is_empty()
+}
+
+/// Canonical key for a member (object keys sorted by serde_json)
+fn member_key(path: &str, member: &Value) -> String {
+    let json = serde_json::to_value(member)
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "null".to_string());
+    format!("{}{}{}", path, SEPARATOR, json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::updates::decoder::Decode;
+    use yrs::{StateVector, Update};
+
+    fn sync(from: &Doc, to: &Doc) {
+        let update = from.transact().encode_state_as_update_v1(&StateVector::default());
+        to.transact_mut().apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_add_wins_over_remove() {
+        let a = Doc::new();
+        let tag = Value::from("rust");
+        add(&a, "tags", &tag);
+
+        let b = Doc::new();
+        sync(&a, &b);
+
+        // a removes while b concurrently re-adds
+        assert!(remove(&a, "tags", &tag));
+        assert!(!add(&b, "tags", &tag));
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        assert!(contains(&a, "tags", &tag));
+        assert!(contains(&b, "tags", &tag));
+    }
+
+    #[test]
+    fn test_members_are_deduplicated_and_ordered() {
+        let doc = Doc::new();
+        assert!(add(&doc, "team", &Value::from("bob")));
+        assert!(add(&doc, "team", &Value::from("alice")));
+        assert!(!add(&doc, "team", &Value::from("bob")));
+        add(&doc, "other", &Value::from("carol"));
+
+        let all = all(&doc);
+        assert_eq!(all["team"], vec![Value::from("alice"), Value::from("bob")]);
+        assert_eq!(all["other"], vec![Value::from("carol")]);
+    }
+}
@@ -0,0 +1,219 @@
+//! Glob patterns for selecting documents by id
+//!
+//! Used by `KEYS`, `SUB`, `SCAN` and the storage backends, so a pattern
+//! selects the same documents wherever it is evaluated. The syntax is the
+//! familiar shell/Redis one:
+//!
+//! - `*` matches any run of characters, including none
+//! - `?` matches exactly one character
+//! - `[abc]` matches one of the listed characters, `[a-z]` one in a range,
+//!   and `[!abc]` or `[^abc]` one that is not listed
+//! - `\` makes the next character literal
+//!
+//! A `[` without a closing `]` is taken literally.
+
+/// One element of a parsed glob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A character matched as is
+    Literal(char),
+    /// `?`
+    One,
+    /// `*`
+    Any,
+    /// `[...]`, as inclusive character ranges (`[a]` is `('a', 'a')`)
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::One => true,
+            Token::Any => true,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+        }
+    }
+}
+
+/// A parsed glob pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// Parse a pattern; every string is a valid pattern
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '*' => {
+                    // Consecutive stars are one star
+                    if tokens.last() != Some(&Token::Any) {
+                        tokens.push(Token::Any);
+                    }
+                }
+                '?' => tokens.push(Token::One),
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    tokens.push(Token::Literal(chars[i]));
+                }
+                '[' => match parse_class(&chars[i + 1..]) {
+                    Some((token, len)) => {
+                        tokens.push(token);
+                        i += len;
+                    }
+                    None => tokens.push(Token::Literal('[')),
+                },
+                c => tokens.push(Token::Literal(c)),
+            }
+            i += 1;
+        }
+
+        Self { tokens }
+    }
+
+    /// Parsed elements of the pattern
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Whether `key` matches the whole pattern
+    pub fn matches(&self, key: &str) -> bool {
+        let key: Vec<char> = key.chars().collect();
+        let (mut t, mut k) = (0, 0);
+        // Position of the last `*` and the key index it currently absorbs up to
+        let mut star: Option<(usize, usize)> = None;
+
+        while k < key.len() {
+            match self.tokens.get(t) {
+                Some(Token::Any) => {
+                    star = Some((t, k));
+                    t += 1;
+                }
+                Some(token) if token.matches(key[k]) => {
+                    t += 1;
+                    k += 1;
+                }
+                _ => match star {
+                    // Let the last star absorb one more character and retry
+                    Some((star_t, star_k)) => {
+                        star = Some((star_t, star_k + 1));
+                        t = star_t + 1;
+                        k = star_k + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        self.tokens[t..].iter().all(|token| *token == Token::Any)
+    }
+
+    /// The literal text every match starts with
+    pub fn literal_prefix(&self) -> String {
+        self.tokens
+            .iter()
+            .map_while(|token| match token {
+                Token::Literal(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The whole pattern as a literal, if it has no wildcards
+    pub fn as_literal(&self) -> Option<String> {
+        let prefix = self.literal_prefix();
+        (prefix.chars().count() == self.tokens.len()).then_some(prefix)
+    }
+}
+
+/// Parse the body of a `[...]` class; returns the token and the number of
+/// characters consumed including the closing `]`
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    // A `]` right after the opening bracket is a member, not the end
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        first = false;
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                ranges.push((c.min(hi), c.max(hi)));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Whether `key` matches the glob `pattern`
+pub fn matches(pattern: &str, key: &str) -> bool {
+    Glob::new(pattern).matches(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*", "anything"));
+        assert!(matches("*", ""));
+        assert!(matches("user:*", "user:123"));
+        assert!(matches("*:123", "user:123"));
+        assert!(!matches("user:*", "cart:456"));
+        assert!(matches("tenant:*:user:?", "tenant:acme:user:7"));
+        assert!(!matches("tenant:*:user:?", "tenant:acme:user:42"));
+        assert!(matches("a*b*c", "axxbyyc"));
+        assert!(matches("a*b*c", "abcbc"));
+        assert!(!matches("a*b*c", "abcb"));
+        assert!(matches("exact", "exact"));
+        assert!(!matches("exact", "exact:1"));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        assert!(matches("doc:[abc]", "doc:b"));
+        assert!(!matches("doc:[abc]", "doc:d"));
+        assert!(matches("doc:[0-9][0-9]", "doc:42"));
+        assert!(matches("doc:[!0-9]", "doc:x"));
+        assert!(!matches("doc:[^0-9]", "doc:4"));
+        assert!(matches("doc:[]x]", "doc:]"));
+        assert!(matches("doc:\\*", "doc:*"));
+        assert!(!matches("doc:\\*", "doc:1"));
+        assert!(matches("doc:[x", "doc:[x"));
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(Glob::new("user:*").literal_prefix(), "user:");
+        assert_eq!(Glob::new("user:*").as_literal(), None);
+        assert_eq!(Glob::new("user:1").as_literal(), Some("user:1".to_string()));
+        assert_eq!(Glob::new("a\\?b").as_literal(), Some("a?b".to_string()));
+        assert_eq!(Glob::new("[ab]c").literal_prefix(), "");
+    }
+}
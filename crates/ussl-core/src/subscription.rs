@@ -0,0 +1,291 @@
+//! Routing of deltas to the subscribers interested in them
+//!
+//! Each subscriber (usually a connection) has its own channel and registers
+//! glob patterns (see [`crate::pattern`]). Patterns without wildcards and
+//! patterns whose only wildcard is a trailing `*` are indexed by id and prefix:
+//! publishing looks up the document id and each of its prefixes, so the cost
+//! of a delta depends on how many subscribers want it, not on how many
+//! subscribers there are. Other patterns are matched one by one.
+
+use crate::manager::Delta;
+use crate::pattern::{Glob, Token};
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast;
+
+/// Identifies a registered subscriber
+pub type SubscriberId = u64;
+
+/// Deltas buffered per subscriber before it starts lagging
+pub const SUBSCRIBER_CAPACITY: usize = 256;
+
+/// Index a pattern is stored under
+enum Key {
+    Exact(String),
+    Prefix(String),
+    Glob(Glob),
+}
+
+impl Key {
+    fn parse(pattern: &str) -> Self {
+        let glob = Glob::new(pattern);
+        if let Some(literal) = glob.as_literal() {
+            return Key::Exact(literal);
+        }
+        let prefix = glob.literal_prefix();
+        match &glob.tokens()[prefix.chars().count()..] {
+            [Token::Any] => Key::Prefix(prefix),
+            _ => Key::Glob(glob),
+        }
+    }
+}
+
+/// Subscribers and the document patterns they listen to
+pub struct SubscriptionRegistry {
+    next_id: AtomicU64,
+    capacity: usize,
+    senders: DashMap<SubscriberId, broadcast::Sender<Delta>>,
+    /// Patterns of each subscriber, for cleanup on unregister
+    patterns: DashMap<SubscriberId, HashSet<String>>,
+    exact: DashMap<String, HashSet<SubscriberId>>,
+    prefixes: DashMap<String, HashSet<SubscriberId>>,
+    /// Patterns that need a full match, keyed by pattern text
+    globs: DashMap<String, (Glob, HashSet<SubscriberId>)>,
+}
+
+impl SubscriptionRegistry {
+    /// Create a registry buffering up to `capacity` deltas per subscriber
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            capacity,
+            senders: DashMap::new(),
+            patterns: DashMap::new(),
+            exact: DashMap::new(),
+            prefixes: DashMap::new(),
+            globs: DashMap::new(),
+        }
+    }
+
+    /// Add a subscriber with no patterns yet
+    pub fn register(&self) -> SubscriberId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, _) = broadcast::channel(self.capacity);
+        self.senders.insert(id, sender);
+        id
+    }
+
+    /// A receiver for the deltas routed to `id`
+    pub fn receiver(&self, id: SubscriberId) -> Option<broadcast::Receiver<Delta>> {
+        self.senders.get(&id).map(|sender| sender.subscribe())
+    }
+
+    /// Remove a subscriber and all its patterns
+    pub fn unregister(&self, id: SubscriberId) {
+        self.senders.remove(&id);
+        if let Some((_, patterns)) = self.patterns.remove(&id) {
+            for pattern in patterns {
+                self.unindex(id, &pattern);
+            }
+        }
+    }
+
+    /// Route deltas of documents matching `pattern` to `id`
+    pub fn add(&self, id: SubscriberId, pattern: &str) {
+        if !self.senders.contains_key(&id) {
+            return;
+        }
+        if !self.patterns.entry(id).or_default().insert(pattern.to_string()) {
+            return;
+        }
+        let (index, key) = match Key::parse(pattern) {
+            Key::Exact(key) => (&self.exact, key),
+            Key::Prefix(key) => (&self.prefixes, key),
+            Key::Glob(glob) => {
+                self.globs
+                    .entry(pattern.to_string())
+                    .or_insert_with(|| (glob, HashSet::new()))
+                    .1
+                    .insert(id);
+                return;
+            }
+        };
+        index.entry(key).or_default().insert(id);
+    }
+
+    /// Stop routing deltas matching `pattern` to `id`
+    pub fn remove(&self, id: SubscriberId, pattern: &str) {
+        let removed = self
+            .patterns
+            .get_mut(&id)
+            .is_some_and(|mut patterns| patterns.remove(pattern));
+        if removed {
+            self.unindex(id, pattern);
+        }
+    }
+
+    /// Send a delta to every subscriber with a matching pattern
+    ///
+    /// Each subscriber gets it once, however many of its patterns match.
+    /// Returns the number of subscribers it was sent to.
+    pub fn publish(&self, delta: Delta) -> usize {
+        let doc_id = delta.document_id.as_str();
+        let mut targets: HashSet<SubscriberId> = HashSet::new();
+
+        if let Some(ids) = self.exact.get(doc_id) {
+            targets.extend(ids.iter());
+        }
+        if !self.prefixes.is_empty() {
+            let boundaries = doc_id.char_indices().map(|(i, _)| i).chain(Some(doc_id.len()));
+            for end in boundaries {
+                if let Some(ids) = self.prefixes.get(&doc_id[..end]) {
+                    targets.extend(ids.iter());
+                }
+            }
+        }
+        for entry in self.globs.iter() {
+            let (glob, ids) = entry.value();
+            if glob.matches(doc_id) {
+                targets.extend(ids.iter());
+            }
+        }
+
+        let mut sent = 0;
+        for id in targets {
+            if let Some(sender) = self.senders.get(&id) {
+                // Fails only if nobody is receiving for this subscriber
+                if sender.send(delta.clone()).is_ok() {
+                    sent += 1;
+                }
+            }
+        }
+        sent
+    }
+
+    /// Number of registered subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    fn unindex(&self, id: SubscriberId, pattern: &str) {
+        let (index, key) = match Key::parse(pattern) {
+            Key::Exact(key) => (&self.exact, key),
+            Key::Prefix(key) => (&self.prefixes, key),
+            Key::Glob(_) => {
+                if let Some(mut entry) = self.globs.get_mut(pattern) {
+                    entry.1.remove(&id);
+                }
+                self.globs.remove_if(pattern, |_, (_, ids)| ids.is_empty());
+                return;
+            }
+        };
+        if let Some(mut ids) = index.get_mut(&key) {
+            ids.remove(&id);
+        }
+        index.remove_if(&key, |_, ids| ids.is_empty());
+    }
+}
+
+impl Default for SubscriptionRegistry {
+    fn default() -> Self {
+        Self::new(SUBSCRIBER_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::Strategy;
+    use crate::document::DocumentId;
+    use crate::manager::EventKind;
+
+    fn delta(id: &str) -> Delta {
+        Delta {
+            event: EventKind::Updated,
+            document_id: DocumentId::new(id).unwrap(),
+            version: 1,
+            path: None,
+            strategy: Strategy::Lww,
+            data: Vec::new(),
+            snapshot: false,
+        }
+    }
+
+    #[test]
+    fn test_routes_only_to_matching_subscribers() {
+        let registry = SubscriptionRegistry::default();
+        let users = registry.register();
+        let alice = registry.register();
+        let all = registry.register();
+        let mut users_rx = registry.receiver(users).unwrap();
+        let mut alice_rx = registry.receiver(alice).unwrap();
+        let mut all_rx = registry.receiver(all).unwrap();
+
+        registry.add(users, "user:*");
+        registry.add(alice, "user:alice");
+        registry.add(all, "*");
+
+        assert_eq!(registry.publish(delta("user:bob")), 2);
+        assert_eq!(users_rx.try_recv().unwrap().document_id.as_str(), "user:bob");
+        assert_eq!(all_rx.try_recv().unwrap().document_id.as_str(), "user:bob");
+        assert!(alice_rx.try_recv().is_err());
+
+        assert_eq!(registry.publish(delta("order:1")), 1);
+        assert!(users_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_overlapping_patterns_deliver_once() {
+        let registry = SubscriptionRegistry::default();
+        let id = registry.register();
+        let mut rx = registry.receiver(id).unwrap();
+
+        registry.add(id, "user:*");
+        registry.add(id, "user:alice");
+        registry.add(id, "*");
+
+        assert_eq!(registry.publish(delta("user:alice")), 1);
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_remove_and_unregister() {
+        let registry = SubscriptionRegistry::default();
+        let id = registry.register();
+        let _rx = registry.receiver(id).unwrap();
+
+        registry.add(id, "user:*");
+        registry.add(id, "order:1");
+        registry.add(id, "*:[0-9]");
+        registry.remove(id, "user:*");
+        registry.remove(id, "*:[0-9]");
+        assert_eq!(registry.publish(delta("user:alice")), 0);
+        assert_eq!(registry.publish(delta("order:1")), 1);
+
+        registry.unregister(id);
+        assert_eq!(registry.subscriber_count(), 0);
+        assert_eq!(registry.publish(delta("order:1")), 0);
+        assert!(registry.exact.is_empty());
+        assert!(registry.prefixes.is_empty());
+        assert!(registry.globs.is_empty());
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let registry = SubscriptionRegistry::default();
+        let tenants = registry.register();
+        let escaped = registry.register();
+        let mut tenants_rx = registry.receiver(tenants).unwrap();
+        let _escaped_rx = registry.receiver(escaped).unwrap();
+
+        registry.add(tenants, "tenant:*:user:?");
+        registry.add(escaped, "user\\:1");
+
+        assert_eq!(registry.publish(delta("tenant:acme:user:7")), 1);
+        assert!(tenants_rx.try_recv().is_ok());
+        assert_eq!(registry.publish(delta("tenant:acme:user:42")), 0);
+        assert_eq!(registry.publish(delta("user:1")), 1);
+    }
+}
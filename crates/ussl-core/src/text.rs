@@ -0,0 +1,307 @@
+//! Positional editing of the Y.Text held by `crdt-text` documents
+//!
+//! Indexes and lengths on this interface count Unicode scalar values, so
+//! clients never have to know how the text is encoded. They are translated
+//! to the byte offsets the Y.Doc works with before touching the Y.Text.
+//!
+//! Formatting uses Y.Text attributes exactly like browser Yjs editors do
+//! (a `null` attribute value clears it), and is read back as a Quill delta.
+
+use crate::crdt::Value;
+use crate::error::{Error, Result};
+use crate::map::{any_to_value, value_to_any};
+use similar::{capture_diff_slices_deadline, Algorithm, DiffOp};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use yrs::types::text::Diff;
+use yrs::types::{Attrs, Value as YValue};
+use yrs::{Any, Doc, GetString, ReadTxn, Text, Transact};
+
+/// Name of the root Y.Text holding the document content
+pub(crate) const CONTENT: &str = "content";
+
+/// Time budget for computing a diff; past it the diff gets coarser, not wrong
+const DIFF_DEADLINE: Duration = Duration::from_millis(100);
+
+/// Insert `chunk` before the character at `index`
+pub(crate) fn insert(doc: &Doc, index: u32, chunk: &str) -> Result<()> {
+    let text = doc.get_or_insert_text(CONTENT);
+    let mut txn = doc.transact_mut();
+
+    let current = text.get_string(&txn);
+    let offset = byte_offset(&current, index)?;
+    text.insert(&mut txn, offset, chunk);
+    Ok(())
+}
+
+/// Remove `len` characters starting at `index`
+///
+/// A range running past the end is truncated to the end of the text.
+pub(crate) fn delete(doc: &Doc, index: u32, len: u32) -> Result<()> {
+    let text = doc.get_or_insert_text(CONTENT);
+    let mut txn = doc.transact_mut();
+
+    let current = text.get_string(&txn);
+    let start = byte_offset(&current, index)?;
+    let end = byte_offset(&current, index.saturating_add(len)).unwrap_or(current.len() as u32);
+    if end > start {
+        text.remove_range(&mut txn, start, end - start);
+    }
+    Ok(())
+}
+
+/// Turn the text into `new` using a minimal set of inserts and deletes
+///
+/// Characters common to the old and new content are left in place, so
+/// concurrent positional edits from other peers survive a whole-string
+/// write. All changes are made in one transaction.
+pub(crate) fn replace(doc: &Doc, new: &str) {
+    let text = doc.get_or_insert_text(CONTENT);
+    let mut txn = doc.transact_mut();
+
+    let old: Vec<char> = text.get_string(&txn).chars().collect();
+    let new: Vec<char> = new.chars().collect();
+    let ops = capture_diff_slices_deadline(
+        Algorithm::Myers,
+        &old,
+        &new,
+        Some(Instant::now() + DIFF_DEADLINE),
+    );
+
+    // Byte offset into the text as edited so far
+    let mut pos = 0u32;
+    for op in ops {
+        match op {
+            DiffOp::Equal { old_index, len, .. } => {
+                pos += utf8_len(&old[old_index..old_index + len]);
+            }
+            DiffOp::Delete { old_index, old_len, .. } => {
+                text.remove_range(&mut txn, pos, utf8_len(&old[old_index..old_index + old_len]));
+            }
+            DiffOp::Insert { new_index, new_len, .. } => {
+                let chunk: String = new[new_index..new_index + new_len].iter().collect();
+                text.insert(&mut txn, pos, &chunk);
+                pos += chunk.len() as u32;
+            }
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                text.remove_range(&mut txn, pos, utf8_len(&old[old_index..old_index + old_len]));
+                let chunk: String = new[new_index..new_index + new_len].iter().collect();
+                text.insert(&mut txn, pos, &chunk);
+                pos += chunk.len() as u32;
+            }
+        }
+    }
+}
+
+/// Apply formatting `attributes` to `len` characters starting at `index`
+pub(crate) fn format(
+    doc: &Doc,
+    index: u32,
+    len: u32,
+    attributes: HashMap<String, Value>,
+) -> Result<()> {
+    let text = doc.get_or_insert_text(CONTENT);
+    let mut txn = doc.transact_mut();
+
+    let current = text.get_string(&txn);
+    let start = byte_offset(&current, index)?;
+    let end = byte_offset(&current, index.saturating_add(len)).unwrap_or(current.len() as u32);
+    if end > start {
+        let attrs: Attrs = attributes
+            .into_iter()
+            .map(|(key, value)| (Arc::from(key), value_to_any(value)))
+            .collect();
+        text.format(&mut txn, start, end - start, attrs);
+    }
+    Ok(())
+}
+
+/// Content and formatting as a Quill delta (`{"ops": [{"insert", "attributes"}]}`)
+pub(crate) fn delta(doc: &Doc) -> Value {
+    let ops = chunks(doc)
+        .into_iter()
+        .filter_map(|chunk| {
+            let YValue::Any(insert) = chunk.insert else {
+                return None;
+            };
+            let mut op = HashMap::new();
+            op.insert("insert".to_string(), any_to_value(insert));
+            if let Some(attrs) = chunk.attributes.filter(|a| !a.is_empty()) {
+                let attrs = attrs
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), any_to_value(value.clone())))
+                    .collect();
+                op.insert("attributes".to_string(), Value::Object(attrs));
+            }
+            Some(Value::Object(op))
+        })
+        .collect();
+
+    let mut delta = HashMap::new();
+    delta.insert("ops".to_string(), Value::Array(ops));
+    Value::Object(delta)
+}
+
+/// Copy content and formatting, but not edit history, into an empty doc
+pub(crate) fn copy(from: &Doc, to: &Doc) {
+    let text = to.get_or_insert_text(CONTENT);
+    let mut txn = to.transact_mut();
+
+    let mut pos = 0u32;
+    for chunk in chunks(from) {
+        let attrs = chunk.attributes.map(|a| *a).unwrap_or_default();
+        match chunk.insert {
+            YValue::Any(Any::String(s)) => {
+                text.insert_with_attributes(&mut txn, pos, &s, attrs);
+                pos += s.len() as u32;
+            }
+            YValue::Any(embed) => {
+                text.insert_embed_with_attributes(&mut txn, pos, embed, attrs);
+                pos += 1;
+            }
+            // Nested shared types are not produced by this server
+            _ => {}
+        }
+    }
+}
+
+fn chunks(doc: &Doc) -> Vec<Diff<()>> {
+    let txn = doc.transact();
+    txn.get_text(CONTENT)
+        .map(|text| text.diff(&txn, |_| ()))
+        .unwrap_or_default()
+}
+
+/// The text as a plain string
+pub(crate) fn content(doc: &Doc) -> String {
+    // Read-only lookup, so readers can share the Y.Doc
+    let txn = doc.transact();
+    txn.get_text(CONTENT)
+        .map(|text| text.get_string(&txn))
+        .unwrap_or_default()
+}
+
+/// Length of the text in characters
+pub(crate) fn len(doc: &Doc) -> u32 {
+    content(doc).chars().count() as u32
+}
+
+fn utf8_len(chars: &[char]) -> u32 {
+    chars.iter().map(|c| c.len_utf8() as u32).sum()
+}
+
+/// Byte offset of the character at `index` (the end of `s` is a valid index)
+fn byte_offset(s: &str, index: u32) -> Result<u32> {
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(s.len()))
+        .nth(index as usize)
+        .map(|offset| offset as u32)
+        .ok_or_else(|| Error::IndexOutOfRange {
+            index: index as usize,
+            len: s.chars().count(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::updates::decoder::Decode;
+    use yrs::{StateVector, Update};
+
+    fn sync(from: &Doc, to: &Doc) {
+        let update = from.transact().encode_state_as_update_v1(&StateVector::default());
+        to.transact_mut().apply_update(Update::decode_v1(&update).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_inserts_merge() {
+        let a = Doc::new();
+        let b = Doc::new();
+
+        insert(&a, 0, "hello world").unwrap();
+        sync(&a, &b);
+
+        insert(&a, 5, ",").unwrap();
+        insert(&b, 11, "!").unwrap();
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        assert_eq!(content(&a), "hello, world!");
+        assert_eq!(content(&b), "hello, world!");
+    }
+
+    #[test]
+    fn test_indexes_count_characters() {
+        let doc = Doc::new();
+        insert(&doc, 0, "héllo").unwrap();
+        insert(&doc, 2, "✓").unwrap();
+        assert_eq!(content(&doc), "hé✓llo");
+        assert_eq!(len(&doc), 6);
+
+        delete(&doc, 1, 2).unwrap();
+        assert_eq!(content(&doc), "hllo");
+
+        delete(&doc, 2, 100).unwrap();
+        assert_eq!(content(&doc), "hl");
+        assert!(insert(&doc, 3, "x").is_err());
+    }
+
+    #[test]
+    fn test_replace_keeps_concurrent_edits() {
+        let a = Doc::new();
+        let b = Doc::new();
+
+        insert(&a, 0, "the quick fox").unwrap();
+        sync(&a, &b);
+
+        // a rewrites the whole string while b inserts a word
+        replace(&a, "the quick brown fox");
+        insert(&b, 13, " jumps").unwrap();
+
+        sync(&a, &b);
+        sync(&b, &a);
+
+        assert_eq!(content(&a), "the quick brown fox jumps");
+        assert_eq!(content(&b), "the quick brown fox jumps");
+    }
+
+    #[test]
+    fn test_format_delta_and_copy() {
+        let doc = Doc::new();
+        insert(&doc, 0, "Hello world").unwrap();
+
+        let mut bold = HashMap::new();
+        bold.insert("bold".to_string(), Value::Bool(true));
+        format(&doc, 6, 5, bold).unwrap();
+
+        let copied = Doc::new();
+        copy(&doc, &copied);
+
+        for d in [&doc, &copied] {
+            let delta = delta(d);
+            let ops = delta.get_path("ops").and_then(Value::as_array).unwrap();
+            assert_eq!(ops.len(), 2);
+            assert_eq!(ops[0].get_path("insert"), Some(&Value::from("Hello ")));
+            assert_eq!(ops[0].get_path("attributes"), None);
+            assert_eq!(ops[1].get_path("insert"), Some(&Value::from("world")));
+            assert_eq!(ops[1].get_path("attributes.bold"), Some(&Value::Bool(true)));
+        }
+
+        // Null clears the attribute again
+        let mut clear = HashMap::new();
+        clear.insert("bold".to_string(), Value::Null);
+        format(&doc, 0, 11, clear).unwrap();
+        assert_eq!(delta(&doc).get_path("ops").and_then(Value::as_array).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_replace_multibyte() {
+        let doc = Doc::new();
+        replace(&doc, "naïve café");
+        replace(&doc, "naïf café ☕");
+        assert_eq!(content(&doc), "naïf café ☕");
+    }
+}
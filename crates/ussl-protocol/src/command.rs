@@ -1,7 +1,7 @@
 //! USSP Command types
 
+use std::collections::HashMap;
 use ussl_core::{Strategy, Value};
-use serde::{Deserialize, Serialize};
 
 /// A parsed USSP command
 #[derive(Debug, Clone)]
@@ -24,9 +24,10 @@ pub enum CommandKind {
         ttl: Option<u64>,
     },
 
-    /// GET <id> [PATH <path>]
+    /// GET <id> [PATH <path>] [FORMAT delta]
     Get {
         path: Option<String>,
+        format: GetFormat,
     },
 
     /// SET <id> <path> <value>
@@ -40,10 +41,11 @@ pub enum CommandKind {
         path: Option<String>,
     },
 
-    /// SUB <pattern> [PATH <path>]
+    /// SUB <pattern> [PATH <path>] [FROM <version>]
     Subscribe {
         pattern: String,
         path: Option<String>,
+        from: Option<u64>,
     },
 
     /// UNSUB <pattern>
@@ -63,6 +65,97 @@ pub enum CommandKind {
         delta: i64,
     },
 
+    /// LINSERT <id> <path> <index> <value>
+    ListInsert {
+        path: String,
+        index: u32,
+        value: Value,
+    },
+
+    /// LREMOVE <id> <path> <index> [count]
+    ListRemove {
+        path: String,
+        index: u32,
+        count: u32,
+    },
+
+    /// LMOVE <id> <path> <from> <to>
+    ListMove {
+        path: String,
+        from: u32,
+        to: u32,
+    },
+
+    /// LRANGE <id> <path> [start stop]
+    ListRange {
+        path: String,
+        start: i64,
+        stop: i64,
+    },
+
+    /// SADD <id> <path> <value>
+    SetAdd {
+        path: String,
+        value: Value,
+    },
+
+    /// SREM <id> <path> <value>
+    SetRemove {
+        path: String,
+        value: Value,
+    },
+
+    /// SISMEMBER <id> <path> <value>
+    SetIsMember {
+        path: String,
+        value: Value,
+    },
+
+    /// SMEMBERS <id> <path>
+    SetMembers {
+        path: String,
+    },
+
+    /// TEXT.INSERT <id> <index> <string>
+    TextInsert {
+        index: u32,
+        text: String,
+    },
+
+    /// TEXT.DELETE <id> <index> <len>
+    TextDelete {
+        index: u32,
+        len: u32,
+    },
+
+    /// TEXT.FORMAT <id> <index> <len> <json-attrs>
+    TextFormat {
+        index: u32,
+        len: u32,
+        attributes: HashMap<String, Value>,
+    },
+
+    /// TEXT.LEN <id>
+    TextLen,
+
+    /// SYNC1 <id> <base64 state vector> - Request the updates a client is missing
+    Sync1 {
+        state_vector: Vec<u8>,
+    },
+
+    /// SYNC2 <id> <base64 update> - Send the updates the server is missing
+    Sync2 {
+        update: Vec<u8>,
+    },
+
+    /// APPLY <id> <base64 update> - Apply a raw Y.js update
+    Apply {
+        update: Vec<u8>,
+    },
+
+    /// STATE <id> - Get the full Y.js state as an update
+    State,
+
     /// PRESENCE <id> [DATA <json>]
     Presence {
         data: Option<serde_json::Value>,
@@ -82,6 +175,14 @@ pub enum CommandKind {
         pattern: Option<String>,
     },
 
+    /// SCAN <cursor> [MATCH <pattern>] [COUNT <n>] [STRATEGY <s>]
+    Scan {
+        cursor: String,
+        pattern: Option<String>,
+        count: Option<usize>,
+        strategy: Option<Strategy>,
+    },
+
     /// COMPACT <id> - Force compaction of a document
     Compact,
 
@@ -93,13 +194,62 @@ pub enum CommandKind {
     /// TTL <id> - Get remaining TTL for a document
     Ttl,
 
-    /// BACKUP - Export all documents as JSON
-    Backup,
+    /// BACKUP [TO <path> | STREAM] - Export all documents
+    Backup {
+        target: BackupTarget,
+    },
 
-    /// RESTORE <json> - Import documents from JSON backup
+    /// RESTORE <json> | FROM <path> | STREAM - Import documents from a backup
     Restore {
-        data: String,
+        source: RestoreSource,
     },
+
+    /// LASTSAVE - Time of the last successful snapshot
+    LastSave,
+}
+
+/// Where BACKUP writes the backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupTarget {
+    /// One JSON bulk reply
+    Reply,
+    /// A file in the server's backup directory
+    File(String),
+    /// An array of NDJSON records, one document each
+    Stream,
+}
+
+/// Where RESTORE reads the backup from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreSource {
+    /// JSON given on the command line
+    Inline(String),
+    /// A file in the server's backup directory
+    File(String),
+    /// NDJSON records sent on the following lines, up to a line `END`
+    Stream,
+}
+
+/// Representation returned by GET
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GetFormat {
+    /// Plain document value
+    #[default]
+    Value,
+    /// Quill-style delta of a text document, including formatting
+    Delta,
+}
+
+impl std::str::FromStr for GetFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "value" => Ok(GetFormat::Value),
+            "delta" => Ok(GetFormat::Delta),
+            _ => Err(format!("Unknown format: {}", s)),
+        }
+    }
 }
 
 impl Command {
@@ -110,9 +260,9 @@ impl Command {
         }
     }
 
-    pub fn get(id: String, path: Option<String>) -> Self {
+    pub fn get(id: String, path: Option<String>, format: GetFormat) -> Self {
         Command {
-            kind: CommandKind::Get { path },
+            kind: CommandKind::Get { path, format },
             document_id: Some(id),
         }
     }
@@ -131,9 +281,9 @@ impl Command {
         }
     }
 
-    pub fn subscribe(pattern: String, path: Option<String>) -> Self {
+    pub fn subscribe(pattern: String, path: Option<String>, from: Option<u64>) -> Self {
         Command {
-            kind: CommandKind::Subscribe { pattern: pattern.clone(), path },
+            kind: CommandKind::Subscribe { pattern: pattern.clone(), path, from },
             document_id: Some(pattern),
         }
     }
@@ -159,6 +309,118 @@ impl Command {
         }
     }
 
+    pub fn linsert(id: String, path: String, index: u32, value: Value) -> Self {
+        Command {
+            kind: CommandKind::ListInsert { path, index, value },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn lremove(id: String, path: String, index: u32, count: u32) -> Self {
+        Command {
+            kind: CommandKind::ListRemove { path, index, count },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn lmove(id: String, path: String, from: u32, to: u32) -> Self {
+        Command {
+            kind: CommandKind::ListMove { path, from, to },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn lrange(id: String, path: String, start: i64, stop: i64) -> Self {
+        Command {
+            kind: CommandKind::ListRange { path, start, stop },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn sadd(id: String, path: String, value: Value) -> Self {
+        Command {
+            kind: CommandKind::SetAdd { path, value },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn srem(id: String, path: String, value: Value) -> Self {
+        Command {
+            kind: CommandKind::SetRemove { path, value },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn sismember(id: String, path: String, value: Value) -> Self {
+        Command {
+            kind: CommandKind::SetIsMember { path, value },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn smembers(id: String, path: String) -> Self {
+        Command {
+            kind: CommandKind::SetMembers { path },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn text_insert(id: String, index: u32, text: String) -> Self {
+        Command {
+            kind: CommandKind::TextInsert { index, text },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn text_delete(id: String, index: u32, len: u32) -> Self {
+        Command {
+            kind: CommandKind::TextDelete { index, len },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn text_format(id: String, index: u32, len: u32, attributes: HashMap<String, Value>) -> Self {
+        Command {
+            kind: CommandKind::TextFormat { index, len, attributes },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn text_len(id: String) -> Self {
+        Command {
+            kind: CommandKind::TextLen,
+            document_id: Some(id),
+        }
+    }
+
+    pub fn sync1(id: String, state_vector: Vec<u8>) -> Self {
+        Command {
+            kind: CommandKind::Sync1 { state_vector },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn sync2(id: String, update: Vec<u8>) -> Self {
+        Command {
+            kind: CommandKind::Sync2 { update },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn apply(id: String, update: Vec<u8>) -> Self {
+        Command {
+            kind: CommandKind::Apply { update },
+            document_id: Some(id),
+        }
+    }
+
+    pub fn state(id: String) -> Self {
+        Command {
+            kind: CommandKind::State,
+            document_id: Some(id),
+        }
+    }
+
     pub fn presence(id: String, data: Option<serde_json::Value>) -> Self {
         Command {
             kind: CommandKind::Presence { data },
@@ -194,6 +456,18 @@ impl Command {
         }
     }
 
+    pub fn scan(
+        cursor: String,
+        pattern: Option<String>,
+        count: Option<usize>,
+        strategy: Option<Strategy>,
+    ) -> Self {
+        Command {
+            kind: CommandKind::Scan { cursor, pattern, count, strategy },
+            document_id: None,
+        }
+    }
+
     pub fn auth(password: String) -> Self {
         Command {
             kind: CommandKind::Auth { password },
@@ -222,16 +496,23 @@ impl Command {
         }
     }
 
-    pub fn backup() -> Self {
+    pub fn backup(target: BackupTarget) -> Self {
+        Command {
+            kind: CommandKind::Backup { target },
+            document_id: None,
+        }
+    }
+
+    pub fn restore(source: RestoreSource) -> Self {
         Command {
-            kind: CommandKind::Backup,
+            kind: CommandKind::Restore { source },
             document_id: None,
         }
     }
 
-    pub fn restore(data: String) -> Self {
+    pub fn last_save() -> Self {
         Command {
-            kind: CommandKind::Restore { data },
+            kind: CommandKind::LastSave,
             document_id: None,
         }
     }
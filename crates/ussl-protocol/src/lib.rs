@@ -13,15 +13,22 @@
 //! -ERR <code> <message>    # Error
 //! $<length>\r\n<data>      # Bulk data
 //! *<count>\r\n<items>      # Array
-//! #<version> <delta>       # Delta update
+//! #<version> <frame>       # Delta update
 //! ```
+//!
+//! A delta frame is `#<version> <event> <document_id> <path> <strategy> <encoding> <payload>`.
+//! `event` is `created`, `updated`, `deleted`, `expired`, `restored` or `resync`.
+//! `path` is `-` when the change is not tied to a path, and `encoding` is
+//! `update` (incremental Y.js update), `snapshot` (full Y.js state), both
+//! base64, or `json` (value at the path of a path subscription, as-is).
 
+pub use ussl_core::base64;
 pub mod command;
 pub mod response;
 pub mod parser;
 pub mod error;
 
-pub use command::{Command, CommandKind};
-pub use response::Response;
+pub use command::{BackupTarget, Command, CommandKind, GetFormat, RestoreSource};
+pub use response::{DeltaEncoding, Response};
 pub use parser::Parser;
 pub use error::{ProtocolError, ProtocolResult};
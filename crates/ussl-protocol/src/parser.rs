@@ -1,31 +1,43 @@
 //! USSP Command Parser
 
-use crate::command::{Command, CommandKind};
+use crate::base64;
+use crate::command::{BackupTarget, Command, GetFormat, RestoreSource};
 use crate::error::{ProtocolError, ProtocolResult};
 use ussl_core::{Strategy, Value};
 use bytes::BytesMut;
 
 /// Maximum message size (1MB)
-const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 
 /// USSP Protocol Parser
 pub struct Parser {
     buffer: BytesMut,
+    /// Most input buffered at once, `MAX_MESSAGE_SIZE` unless raised
+    max_size: usize,
 }
 
 impl Parser {
     pub fn new() -> Self {
         Self {
             buffer: BytesMut::with_capacity(4096),
+            max_size: MAX_MESSAGE_SIZE,
         }
     }
 
+    /// Change how much input may be buffered at once
+    ///
+    /// Raised while reading lines that are data rather than commands, such
+    /// as the records of `RESTORE STREAM`, which hold whole documents.
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
     /// Add data to the parser buffer
     pub fn feed(&mut self, data: &[u8]) -> ProtocolResult<()> {
-        if self.buffer.len() + data.len() > MAX_MESSAGE_SIZE {
+        if self.buffer.len() + data.len() > self.max_size {
             return Err(ProtocolError::MessageTooLarge {
                 size: self.buffer.len() + data.len(),
-                max: MAX_MESSAGE_SIZE,
+                max: self.max_size,
             });
         }
         self.buffer.extend_from_slice(data);
@@ -34,11 +46,19 @@ impl Parser {
 
     /// Try to parse a complete command from the buffer
     pub fn parse(&mut self) -> ProtocolResult<Option<Command>> {
+        match self.next_line() {
+            Some(line) => Self::parse_line(&line).map(Some),
+            None => Ok(None), // Incomplete
+        }
+    }
+
+    /// Take the next complete line from the buffer without parsing it
+    ///
+    /// Used for data that follows a command, such as the records of
+    /// `RESTORE STREAM`.
+    pub fn next_line(&mut self) -> Option<String> {
         // Find line ending
-        let line_end = match self.buffer.iter().position(|&b| b == b'\n') {
-            Some(pos) => pos,
-            None => return Ok(None), // Incomplete
-        };
+        let line_end = self.buffer.iter().position(|&b| b == b'\n')?;
 
         // Extract line (excluding \r\n or \n)
         let line_len = if line_end > 0 && self.buffer[line_end - 1] == b'\r' {
@@ -49,11 +69,10 @@ impl Parser {
 
         let line = String::from_utf8_lossy(&self.buffer[..line_len]).to_string();
 
-        // Remove the parsed line from buffer
+        // Remove the line from buffer
         let _ = self.buffer.split_to(line_end + 1);
 
-        // Parse the command
-        Self::parse_line(&line).map(Some)
+        Some(line)
     }
 
     /// Parse a single command line
@@ -78,16 +97,34 @@ impl Parser {
             "UNSUB" | "UNSUBSCRIBE" => Self::parse_unsubscribe(&mut tokens),
             "PUSH" => Self::parse_push(&mut tokens),
             "INC" | "INCR" | "INCREMENT" => Self::parse_increment(&mut tokens),
+            "LINSERT" => Self::parse_linsert(&mut tokens),
+            "LREMOVE" => Self::parse_lremove(&mut tokens),
+            "LMOVE" => Self::parse_lmove(&mut tokens),
+            "LRANGE" => Self::parse_lrange(&mut tokens),
+            "SADD" => Self::parse_set_member(&mut tokens, Command::sadd),
+            "SREM" => Self::parse_set_member(&mut tokens, Command::srem),
+            "SISMEMBER" => Self::parse_set_member(&mut tokens, Command::sismember),
+            "SMEMBERS" => Self::parse_smembers(&mut tokens),
+            "TEXT.INSERT" => Self::parse_text_insert(&mut tokens),
+            "TEXT.DELETE" => Self::parse_text_delete(&mut tokens),
+            "TEXT.FORMAT" => Self::parse_text_format(&mut tokens),
+            "TEXT.LEN" => Self::parse_text_len(&mut tokens),
+            "SYNC1" => Self::parse_binary(&mut tokens, "state_vector", Command::sync1),
+            "SYNC2" => Self::parse_binary(&mut tokens, "update", Command::sync2),
+            "APPLY" => Self::parse_binary(&mut tokens, "update", Command::apply),
+            "STATE" => Self::parse_state(&mut tokens),
             "PRESENCE" => Self::parse_presence(&mut tokens),
             "PING" => Ok(Command::ping()),
             "QUIT" => Ok(Command::quit()),
             "INFO" => Ok(Command::info()),
             "KEYS" => Self::parse_keys(&mut tokens),
+            "SCAN" => Self::parse_scan(&mut tokens),
             "COMPACT" => Self::parse_compact(&mut tokens),
             "EXPIRE" => Self::parse_expire(&mut tokens),
             "TTL" => Self::parse_ttl(&mut tokens),
-            "BACKUP" => Ok(Command::backup()),
+            "BACKUP" => Self::parse_backup(&mut tokens),
             "RESTORE" => Self::parse_restore(&mut tokens),
+            "LASTSAVE" => Ok(Command::last_save()),
             _ => Err(ProtocolError::InvalidCommand(format!("Unknown command: {}", cmd))),
         }
     }
@@ -132,6 +169,7 @@ impl Parser {
             .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
 
         let mut path = None;
+        let mut format = GetFormat::default();
 
         while let Some(opt) = tokens.next() {
             match opt.to_uppercase().as_str() {
@@ -140,6 +178,11 @@ impl Parser {
                         .ok_or_else(|| ProtocolError::MissingArgument("path value".into()))?
                         .to_string());
                 }
+                "FORMAT" => {
+                    let f = tokens.next()
+                        .ok_or_else(|| ProtocolError::MissingArgument("format value".into()))?;
+                    format = f.parse().map_err(ProtocolError::InvalidArgument)?;
+                }
                 _ => {
                     // Treat as path directly
                     path = Some(opt.to_string());
@@ -147,7 +190,7 @@ impl Parser {
             }
         }
 
-        Ok(Command::get(id.to_string(), path))
+        Ok(Command::get(id.to_string(), path, format))
     }
 
     fn parse_set(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
@@ -190,6 +233,7 @@ impl Parser {
             .ok_or_else(|| ProtocolError::MissingArgument("pattern".into()))?;
 
         let mut path = None;
+        let mut from = None;
 
         while let Some(opt) = tokens.next() {
             match opt.to_uppercase().as_str() {
@@ -198,11 +242,14 @@ impl Parser {
                         .ok_or_else(|| ProtocolError::MissingArgument("path value".into()))?
                         .to_string());
                 }
+                "FROM" => {
+                    from = Some(parse_u64(tokens.next(), "version")?);
+                }
                 _ => return Err(ProtocolError::InvalidArgument(format!("Unknown option: {}", opt))),
             }
         }
 
-        Ok(Command::subscribe(pattern.to_string(), path))
+        Ok(Command::subscribe(pattern.to_string(), path, from))
     }
 
     fn parse_unsubscribe(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
@@ -237,6 +284,154 @@ impl Parser {
         Ok(Command::increment(id.to_string(), path.to_string(), delta))
     }
 
+    fn parse_linsert(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+        let index = parse_u32(tokens.next(), "index")?;
+        let value_str = tokens.rest()
+            .ok_or_else(|| ProtocolError::MissingArgument("value".into()))?;
+
+        let value = parse_value(&value_str)?;
+
+        Ok(Command::linsert(id.to_string(), path.to_string(), index, value))
+    }
+
+    fn parse_lremove(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+        let index = parse_u32(tokens.next(), "index")?;
+        let count = match tokens.next() {
+            Some(c) => parse_u32(Some(c), "count")?,
+            None => 1,
+        };
+
+        Ok(Command::lremove(id.to_string(), path.to_string(), index, count))
+    }
+
+    fn parse_lmove(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+        let from = parse_u32(tokens.next(), "from")?;
+        let to = parse_u32(tokens.next(), "to")?;
+
+        Ok(Command::lmove(id.to_string(), path.to_string(), from, to))
+    }
+
+    fn parse_lrange(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+
+        let (start, stop) = match tokens.next() {
+            Some(start) => (parse_i64(Some(start), "start")?, parse_i64(tokens.next(), "stop")?),
+            None => (0, -1),
+        };
+
+        Ok(Command::lrange(id.to_string(), path.to_string(), start, stop))
+    }
+
+    fn parse_set_member(
+        tokens: &mut Tokenizer,
+        build: fn(String, String, Value) -> Command,
+    ) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+        let value_str = tokens.rest()
+            .ok_or_else(|| ProtocolError::MissingArgument("value".into()))?;
+
+        let value = parse_value(&value_str)?;
+
+        Ok(build(id.to_string(), path.to_string(), value))
+    }
+
+    fn parse_smembers(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let path = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+
+        Ok(Command::smembers(id.to_string(), path.to_string()))
+    }
+
+    fn parse_text_insert(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let index = parse_u32(tokens.next(), "index")?;
+        let text_str = tokens.rest()
+            .ok_or_else(|| ProtocolError::MissingArgument("text".into()))?;
+
+        // A JSON string literal allows escapes and leading/trailing spaces
+        let text = if text_str.starts_with('"') {
+            serde_json::from_str(&text_str)
+                .map_err(|e| ProtocolError::InvalidJson(e.to_string()))?
+        } else {
+            text_str
+        };
+
+        Ok(Command::text_insert(id.to_string(), index, text))
+    }
+
+    fn parse_text_delete(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let index = parse_u32(tokens.next(), "index")?;
+        let len = parse_u32(tokens.next(), "len")?;
+
+        Ok(Command::text_delete(id.to_string(), index, len))
+    }
+
+    fn parse_text_format(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let index = parse_u32(tokens.next(), "index")?;
+        let len = parse_u32(tokens.next(), "len")?;
+        let attrs_str = tokens.rest()
+            .ok_or_else(|| ProtocolError::MissingArgument("attributes".into()))?;
+
+        let json = serde_json::from_str(&attrs_str)
+            .map_err(|e| ProtocolError::InvalidJson(e.to_string()))?;
+        let Value::Object(attributes) = json_to_value(json)? else {
+            return Err(ProtocolError::InvalidArgument("Attributes must be a JSON object".into()));
+        };
+
+        Ok(Command::text_format(id.to_string(), index, len, attributes))
+    }
+
+    fn parse_text_len(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+
+        Ok(Command::text_len(id.to_string()))
+    }
+
+    fn parse_binary(
+        tokens: &mut Tokenizer,
+        name: &str,
+        build: fn(String, Vec<u8>) -> Command,
+    ) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+        let payload = parse_base64(tokens.next(), name)?;
+
+        Ok(build(id.to_string(), payload))
+    }
+
+    fn parse_state(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let id = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
+
+        Ok(Command::state(id.to_string()))
+    }
+
     fn parse_presence(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
         let id = tokens.next()
             .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
@@ -269,6 +464,37 @@ impl Parser {
         Ok(Command::keys(pattern))
     }
 
+    fn parse_scan(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let cursor = tokens.next()
+            .ok_or_else(|| ProtocolError::MissingArgument("cursor".into()))?;
+
+        let mut pattern = None;
+        let mut count = None;
+        let mut strategy = None;
+
+        while let Some(opt) = tokens.next() {
+            match opt.to_uppercase().as_str() {
+                "MATCH" => {
+                    pattern = Some(tokens.next()
+                        .ok_or_else(|| ProtocolError::MissingArgument("pattern".into()))?
+                        .to_string());
+                }
+                "COUNT" => {
+                    count = Some(parse_u32(tokens.next(), "count")? as usize);
+                }
+                "STRATEGY" => {
+                    let s = tokens.next()
+                        .ok_or_else(|| ProtocolError::MissingArgument("strategy value".into()))?;
+                    strategy = Some(s.parse()
+                        .map_err(|_| ProtocolError::InvalidArgument(format!("Invalid strategy: {}", s)))?);
+                }
+                _ => return Err(ProtocolError::InvalidArgument(format!("Unknown option: {}", opt))),
+            }
+        }
+
+        Ok(Command::scan(cursor.to_string(), pattern, count, strategy))
+    }
+
     fn parse_compact(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
         let id = tokens.next()
             .ok_or_else(|| ProtocolError::MissingArgument("document_id".into()))?;
@@ -291,15 +517,44 @@ impl Parser {
         Ok(Command::ttl(id.to_string()))
     }
 
+    fn parse_backup(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
+        let target = match tokens.next().map(|t| t.to_uppercase()).as_deref() {
+            None => BackupTarget::Reply,
+            Some("TO") => {
+                let path = tokens.next()
+                    .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+                BackupTarget::File(path.to_string())
+            }
+            Some("STREAM") => BackupTarget::Stream,
+            Some(opt) => return Err(ProtocolError::InvalidArgument(format!("Unknown option: {}", opt))),
+        };
+        Ok(Command::backup(target))
+    }
+
     fn parse_restore(tokens: &mut Tokenizer) -> ProtocolResult<Command> {
         let data = tokens.rest()
             .ok_or_else(|| ProtocolError::MissingArgument("json_data".into()))?
             .trim()
             .to_string();
+
+        if !data.starts_with('{') {
+            let mut tokens = Tokenizer::new(&data);
+            let source = match tokens.next().map(|t| t.to_uppercase()).as_deref() {
+                Some("FROM") => {
+                    let path = tokens.next()
+                        .ok_or_else(|| ProtocolError::MissingArgument("path".into()))?;
+                    RestoreSource::File(path.to_string())
+                }
+                Some("STREAM") => RestoreSource::Stream,
+                _ => return Err(ProtocolError::InvalidArgument(format!("Unknown option: {}", data))),
+            };
+            return Ok(Command::restore(source));
+        }
+
         // Validate it's valid JSON
         serde_json::from_str::<serde_json::Value>(&data)
             .map_err(|e| ProtocolError::InvalidJson(e.to_string()))?;
-        Ok(Command::restore(data))
+        Ok(Command::restore(RestoreSource::Inline(data)))
     }
 }
 
@@ -310,17 +565,17 @@ impl Default for Parser {
 }
 
 /// Simple tokenizer that handles quoted strings
-struct Tokenizer<'a> {
+pub(crate) struct Tokenizer<'a> {
     input: &'a str,
     pos: usize,
 }
 
 impl<'a> Tokenizer<'a> {
-    fn new(input: &'a str) -> Self {
+    pub(crate) fn new(input: &'a str) -> Self {
         Self { input, pos: 0 }
     }
 
-    fn next(&mut self) -> Option<&'a str> {
+    pub(crate) fn next(&mut self) -> Option<&'a str> {
         // Skip whitespace
         while self.pos < self.input.len() && self.input[self.pos..].starts_with(' ') {
             self.pos += 1;
@@ -333,9 +588,9 @@ impl<'a> Tokenizer<'a> {
         let remaining = &self.input[self.pos..];
 
         // Handle quoted string
-        if remaining.starts_with('"') {
-            if let Some(end) = remaining[1..].find('"') {
-                let token = &remaining[1..end + 1];
+        if let Some(quoted) = remaining.strip_prefix('"') {
+            if let Some(end) = quoted.find('"') {
+                let token = &quoted[..end];
                 self.pos += end + 2;
                 return Some(token);
             }
@@ -365,6 +620,34 @@ impl<'a> Tokenizer<'a> {
     }
 }
 
+/// Parse a required unsigned integer argument
+fn parse_u32(token: Option<&str>, name: &str) -> ProtocolResult<u32> {
+    let token = token.ok_or_else(|| ProtocolError::MissingArgument(name.into()))?;
+    token.parse()
+        .map_err(|_| ProtocolError::InvalidArgument(format!("Invalid {}: {}", name, token)))
+}
+
+/// Parse a required 64-bit unsigned integer argument
+fn parse_u64(token: Option<&str>, name: &str) -> ProtocolResult<u64> {
+    let token = token.ok_or_else(|| ProtocolError::MissingArgument(name.into()))?;
+    token.parse()
+        .map_err(|_| ProtocolError::InvalidArgument(format!("Invalid {}: {}", name, token)))
+}
+
+/// Parse a required signed integer argument
+fn parse_i64(token: Option<&str>, name: &str) -> ProtocolResult<i64> {
+    let token = token.ok_or_else(|| ProtocolError::MissingArgument(name.into()))?;
+    token.parse()
+        .map_err(|_| ProtocolError::InvalidArgument(format!("Invalid {}: {}", name, token)))
+}
+
+/// Parse a required base64-encoded binary argument
+fn parse_base64(token: Option<&str>, name: &str) -> ProtocolResult<Vec<u8>> {
+    let token = token.ok_or_else(|| ProtocolError::MissingArgument(name.into()))?;
+    base64::decode(token)
+        .ok_or_else(|| ProtocolError::InvalidArgument(format!("Invalid base64 {}", name)))
+}
+
 /// Parse a value from string (JSON-like)
 fn parse_value(s: &str) -> ProtocolResult<Value> {
     let s = s.trim();
@@ -409,6 +692,7 @@ fn json_to_value(json: serde_json::Value) -> ProtocolResult<Value> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::command::CommandKind;
 
     #[test]
     fn test_parse_create() {
@@ -426,7 +710,20 @@ mod tests {
         parser.feed(b"GET user:123 PATH name\r\n").unwrap();
 
         let cmd = parser.parse().unwrap().unwrap();
-        assert!(matches!(cmd.kind, CommandKind::Get { path: Some(ref p) } if p == "name"));
+        assert!(matches!(
+            cmd.kind,
+            CommandKind::Get { path: Some(ref p), format: GetFormat::Value } if p == "name"
+        ));
+    }
+
+    #[test]
+    fn test_parse_get_delta() {
+        let mut parser = Parser::new();
+        parser.feed(b"GET note:1 FORMAT delta\r\nGET note:1 FORMAT html\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::Get { path: None, format: GetFormat::Delta }));
+        assert!(parser.parse().is_err());
     }
 
     #[test]
@@ -456,13 +753,178 @@ mod tests {
         assert!(matches!(cmd.kind, CommandKind::Increment { delta: 1, .. }));
     }
 
+    #[test]
+    fn test_parse_list_commands() {
+        let mut parser = Parser::new();
+        parser.feed(b"LINSERT list:1 items 0 {\"name\": \"milk\"}\r\n").unwrap();
+        parser.feed(b"LREMOVE list:1 items 2\r\nLMOVE list:1 items 3 0\r\n").unwrap();
+        parser.feed(b"LRANGE list:1 items\r\nLRANGE list:1 items 1 -2\r\nLRANGE list:1 items 1\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::ListInsert { index: 0, value: Value::Object(_), .. }));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::ListRemove { index: 2, count: 1, .. }));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::ListMove { from: 3, to: 0, .. }));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::ListRange { start: 0, stop: -1, .. }));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::ListRange { start: 1, stop: -2, .. }));
+
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_parse_set_commands() {
+        let mut parser = Parser::new();
+        parser.feed(b"SADD room:1 members \"alice\"\r\nSMEMBERS room:1 members\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(
+            cmd.kind,
+            CommandKind::SetAdd { ref path, value: Value::String(ref v) } if path == "members" && v == "alice"
+        ));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::SetMembers { ref path } if path == "members"));
+    }
+
+    #[test]
+    fn test_parse_text_commands() {
+        let mut parser = Parser::new();
+        parser.feed(b"TEXT.INSERT doc:1 5 \" world\\n\"\r\n").unwrap();
+        parser.feed(b"text.delete doc:1 0 3\r\nTEXT.LEN doc:1\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::TextInsert { index: 5, ref text } if text == " world\n"));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::TextDelete { index: 0, len: 3 }));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::TextLen));
+
+        parser.feed(b"TEXT.FORMAT doc:1 0 5 {\"bold\": true, \"link\": null}\r\n").unwrap();
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(
+            cmd.kind,
+            CommandKind::TextFormat { index: 0, len: 5, ref attributes }
+                if attributes.get("bold") == Some(&Value::Bool(true)) && attributes.get("link") == Some(&Value::Null)
+        ));
+        assert_eq!(cmd.document_id, Some("doc:1".to_string()));
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let mut parser = Parser::new();
+        parser.feed(b"SYNC1 doc:1 AAE=\r\nSYNC2 doc:1 AQID\r\nSYNC2 doc:1 not*base64\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::Sync1 { ref state_vector } if state_vector == &[0, 1]));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::Sync2 { ref update } if update == &[1, 2, 3]));
+        assert_eq!(cmd.document_id, Some("doc:1".to_string()));
+
+        assert!(matches!(parser.parse(), Err(ProtocolError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_apply_state() {
+        let mut parser = Parser::new();
+        parser.feed(b"APPLY doc:1 AQID\r\nSTATE doc:1\r\nAPPLY doc:1\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::Apply { ref update } if update == &[1, 2, 3]));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::State));
+        assert_eq!(cmd.document_id, Some("doc:1".to_string()));
+
+        assert!(matches!(parser.parse(), Err(ProtocolError::MissingArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_subscribe() {
+        let mut parser = Parser::new();
+        parser.feed(b"SUB user:* PATH name FROM 42\r\nSUB doc:1 FROM latest\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        match cmd.kind {
+            CommandKind::Subscribe { pattern, path, from } => {
+                assert_eq!(pattern, "user:*");
+                assert_eq!(path, Some("name".to_string()));
+                assert_eq!(from, Some(42));
+            }
+            _ => panic!("Expected Subscribe command"),
+        }
+
+        assert!(matches!(parser.parse(), Err(ProtocolError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_parse_backup_restore() {
+        let mut parser = Parser::new();
+        parser.feed(b"BACKUP\r\nBACKUP TO \"nightly 1.ndjson\"\r\nBACKUP STREAM\r\nBACKUP TO\r\n").unwrap();
+        for target in [
+            BackupTarget::Reply,
+            BackupTarget::File("nightly 1.ndjson".into()),
+            BackupTarget::Stream,
+        ] {
+            let cmd = parser.parse().unwrap().unwrap();
+            assert!(matches!(cmd.kind, CommandKind::Backup { target: ref t } if *t == target));
+        }
+        assert!(matches!(parser.parse(), Err(ProtocolError::MissingArgument(_))));
+
+        parser.feed(b"RESTORE {\"version\":2}\r\nRESTORE FROM nightly.ndjson\r\nrestore stream\r\n{\"id\":\"a\"}\r\nRESTORE NOW\r\n").unwrap();
+        for source in [
+            RestoreSource::Inline("{\"version\":2}".into()),
+            RestoreSource::File("nightly.ndjson".into()),
+            RestoreSource::Stream,
+        ] {
+            let cmd = parser.parse().unwrap().unwrap();
+            assert!(matches!(cmd.kind, CommandKind::Restore { source: ref s } if *s == source));
+        }
+        assert_eq!(parser.next_line().as_deref(), Some("{\"id\":\"a\"}"));
+        assert!(matches!(parser.parse(), Err(ProtocolError::InvalidArgument(_))));
+        assert_eq!(parser.next_line(), None);
+    }
+
+    #[test]
+    fn test_parse_scan() {
+        let mut parser = Parser::new();
+        parser.feed(b"SCAN 0\r\nscan dXNlcjox MATCH user:* COUNT 50 STRATEGY crdt-map\r\nSCAN 0 COUNT many\r\n").unwrap();
+
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::Scan { ref cursor, pattern: None, count: None, strategy: None } if cursor == "0"));
+
+        let cmd = parser.parse().unwrap().unwrap();
+        match cmd.kind {
+            CommandKind::Scan { cursor, pattern, count, strategy } => {
+                assert_eq!(cursor, "dXNlcjox");
+                assert_eq!(pattern, Some("user:*".to_string()));
+                assert_eq!(count, Some(50));
+                assert_eq!(strategy, Some(Strategy::CrdtMap));
+            }
+            _ => panic!("Expected Scan command"),
+        }
+
+        assert!(matches!(parser.parse(), Err(ProtocolError::InvalidArgument(_))));
+    }
+
     #[test]
     fn test_parse_ping() {
         let mut parser = Parser::new();
-        parser.feed(b"PING\r\n").unwrap();
+        parser.feed(b"PING\r\nlastsave\r\n").unwrap();
 
         let cmd = parser.parse().unwrap().unwrap();
         assert!(matches!(cmd.kind, CommandKind::Ping));
+        let cmd = parser.parse().unwrap().unwrap();
+        assert!(matches!(cmd.kind, CommandKind::LastSave));
     }
 
     #[test]
@@ -1,7 +1,12 @@
 //! USSP Response types
 
+use crate::base64;
 use bytes::{BufMut, BytesMut};
-use ussl_core::Value;
+use ussl_core::manager::{Delta, EventKind};
+use ussl_core::{Strategy, Value};
+
+/// Placeholder for the path field of a delta frame that changes the whole document
+const NO_PATH: &str = "-";
 
 /// A USSP response
 #[derive(Debug, Clone)]
@@ -18,8 +23,19 @@ pub enum Response {
     /// *<count>\r\n<items>
     Array(Vec<Response>),
 
-    /// #<version> <delta>
-    Delta { version: u64, data: Vec<u8> },
+    /// *<count>\r\n, for an array whose items are sent as separate responses
+    ArrayHeader(usize),
+
+    /// #<version> <event> <document_id> <path> <strategy> <encoding> <payload>
+    Delta {
+        event: EventKind,
+        document_id: String,
+        version: u64,
+        path: Option<String>,
+        strategy: Strategy,
+        encoding: DeltaEncoding,
+        data: Vec<u8>,
+    },
 
     /// :<integer>
     Integer(i64),
@@ -34,6 +50,28 @@ pub enum Response {
     Pong,
 }
 
+/// How the payload of a delta frame is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaEncoding {
+    /// Incremental Y.js update (v1), base64
+    Update,
+    /// Full Y.js document state (v1), base64
+    Snapshot,
+    /// JSON value at the subscribed path, sent as-is
+    Json,
+}
+
+impl DeltaEncoding {
+    /// Marker written on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeltaEncoding::Update => "update",
+            DeltaEncoding::Snapshot => "snapshot",
+            DeltaEncoding::Json => "json",
+        }
+    }
+}
+
 impl Response {
     pub fn ok() -> Self {
         Response::Ok(None)
@@ -76,8 +114,34 @@ impl Response {
         Response::Value(v)
     }
 
-    pub fn delta(version: u64, data: Vec<u8>) -> Self {
-        Response::Delta { version, data }
+    pub fn delta(delta: Delta) -> Self {
+        let encoding = if delta.snapshot {
+            DeltaEncoding::Snapshot
+        } else {
+            DeltaEncoding::Update
+        };
+        Response::Delta {
+            event: delta.event,
+            document_id: delta.document_id.to_string(),
+            version: delta.version,
+            path: delta.path,
+            strategy: delta.strategy,
+            encoding,
+            data: delta.data,
+        }
+    }
+
+    /// Frame for a path subscription, carrying the value now at `path`
+    pub fn path_value(delta: &Delta, path: &str, value: &Value) -> Self {
+        Response::Delta {
+            event: delta.event,
+            document_id: delta.document_id.to_string(),
+            version: delta.version,
+            path: Some(path.to_string()),
+            strategy: delta.strategy,
+            encoding: DeltaEncoding::Json,
+            data: serde_json::to_vec(value).unwrap_or_else(|_| b"null".to_vec()),
+        }
     }
 
     pub fn pong() -> Self {
@@ -132,13 +196,34 @@ impl Response {
                     item.encode_into(buf);
                 }
             }
-            Response::Delta { version, data } => {
+            Response::ArrayHeader(count) => {
+                buf.put_slice(b"*");
+                buf.put_slice(count.to_string().as_bytes());
+                buf.put_slice(b"\r\n");
+            }
+            Response::Delta { event, document_id, version, path, strategy, encoding, data } => {
                 buf.put_slice(b"#");
                 buf.put_slice(version.to_string().as_bytes());
                 buf.put_slice(b" ");
-                // Encode delta as base64 for text protocol
-                let encoded = base64_encode(data);
-                buf.put_slice(encoded.as_bytes());
+                buf.put_slice(event.to_string().as_bytes());
+                buf.put_slice(b" ");
+                buf.put_slice(document_id.as_bytes());
+                buf.put_slice(b" ");
+                match path {
+                    Some(path) => put_token(buf, path),
+                    None => buf.put_slice(NO_PATH.as_bytes()),
+                }
+                buf.put_slice(b" ");
+                buf.put_slice(strategy.to_string().as_bytes());
+                buf.put_slice(b" ");
+                buf.put_slice(encoding.as_str().as_bytes());
+                buf.put_slice(b" ");
+                if *encoding == DeltaEncoding::Json {
+                    buf.put_slice(data);
+                } else {
+                    // Encode delta as base64 for text protocol
+                    buf.put_slice(base64::encode(data).as_bytes());
+                }
                 buf.put_slice(b"\r\n");
             }
             Response::Integer(n) => {
@@ -164,37 +249,19 @@ impl Response {
     }
 }
 
-/// Simple base64 encoding (no external dependency)
-fn base64_encode(data: &[u8]) -> String {
-    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
-
-    let mut result = String::new();
-    let mut i = 0;
-
-    while i < data.len() {
-        let b0 = data[i];
-        let b1 = if i + 1 < data.len() { data[i + 1] } else { 0 };
-        let b2 = if i + 2 < data.len() { data[i + 2] } else { 0 };
-
-        result.push(ALPHABET[(b0 >> 2) as usize] as char);
-        result.push(ALPHABET[(((b0 & 0x03) << 4) | (b1 >> 4)) as usize] as char);
-
-        if i + 1 < data.len() {
-            result.push(ALPHABET[(((b1 & 0x0f) << 2) | (b2 >> 6)) as usize] as char);
-        } else {
-            result.push('=');
-        }
-
-        if i + 2 < data.len() {
-            result.push(ALPHABET[(b2 & 0x3f) as usize] as char);
-        } else {
-            result.push('=');
-        }
-
-        i += 3;
+/// Write `token` so the request tokenizer reads it back as one argument
+///
+/// Tokens that are empty, contain a space or could be mistaken for `NO_PATH`
+/// are quoted, as in requests. The tokenizer has no escapes, so a path that
+/// needs quoting cannot contain `"` (nor could a request have sent it).
+fn put_token(buf: &mut BytesMut, token: &str) {
+    if token.is_empty() || token == NO_PATH || token.contains(' ') {
+        buf.put_slice(b"\"");
+        buf.put_slice(token.as_bytes());
+        buf.put_slice(b"\"");
+    } else {
+        buf.put_slice(token.as_bytes());
     }
-
-    result
 }
 
 #[cfg(test)]
@@ -231,6 +298,62 @@ mod tests {
         assert_eq!(resp.encode().as_ref(), b"$-1\r\n");
     }
 
+    #[test]
+    fn test_encode_delta() {
+        let mut delta = Delta {
+            event: EventKind::Updated,
+            document_id: ussl_core::DocumentId::new("user:1").unwrap(),
+            version: 7,
+            path: Some("profile.name".into()),
+            strategy: Strategy::CrdtMap,
+            data: b"ab".to_vec(),
+            snapshot: false,
+        };
+        let resp = Response::delta(delta.clone());
+        assert_eq!(resp.encode().as_ref(), b"#7 updated user:1 profile.name crdt-map update YWI=\r\n");
+
+        delta.event = EventKind::Restored;
+        delta.path = None;
+        delta.snapshot = true;
+        let resp = Response::delta(delta.clone());
+        assert_eq!(resp.encode().as_ref(), b"#7 restored user:1 - crdt-map snapshot YWI=\r\n");
+
+        let resp = Response::path_value(&delta, "profile", &Value::from("Ada Lovelace"));
+        assert_eq!(
+            resp.encode().as_ref(),
+            b"#7 restored user:1 profile crdt-map json \"Ada Lovelace\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_delta_path_round_trips() {
+        let mut delta = Delta {
+            event: EventKind::Updated,
+            document_id: ussl_core::DocumentId::new("user:1").unwrap(),
+            version: 7,
+            path: None,
+            strategy: Strategy::Lww,
+            data: Vec::new(),
+            snapshot: false,
+        };
+
+        for path in ["profile.name", "first name", "-", "", "a\"b"] {
+            delta.path = Some(path.to_string());
+            let frame = Response::delta(delta.clone()).encode();
+            let line = std::str::from_utf8(&frame).unwrap().trim_end();
+            let mut tokens = crate::parser::Tokenizer::new(line);
+            for _ in 0..3 {
+                tokens.next();
+            }
+            assert_eq!(tokens.next(), Some(path), "{}", line);
+            assert_eq!(tokens.next(), Some("lww"), "{}", line);
+        }
+
+        delta.path = None;
+        let frame = Response::delta(delta).encode();
+        assert_eq!(frame.as_ref(), b"#7 updated user:1 - lww update \r\n");
+    }
+
     #[test]
     fn test_encode_array() {
         let resp = Response::array(vec![
@@ -0,0 +1,215 @@
+//! Adapter exposing a `Storage` backend as a `DocumentManager` backing store
+
+use crate::{Storage, StorageError};
+use std::future::Future;
+use std::sync::Arc;
+use ussl_core::{Backing, DocumentId, DocumentMeta};
+
+/// Bridges the async `Storage` trait to the synchronous `Backing` trait
+///
+/// Calls block the current worker via `tokio::task::block_in_place`, so the
+/// manager must be used from within a multi-threaded Tokio runtime.
+pub struct StorageBacking {
+    storage: Arc<dyn Storage>,
+}
+
+impl StorageBacking {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+}
+
+impl Backing for StorageBacking {
+    fn load(&self, id: &DocumentId) -> ussl_core::Result<Option<(DocumentMeta, Vec<u8>)>> {
+        block_on(self.storage.load(id)).map_err(to_core)
+    }
+
+    fn flush(&self, meta: &DocumentMeta, snapshot: &[u8]) -> ussl_core::Result<()> {
+        block_on(self.storage.store(&meta.id, meta, snapshot)).map_err(to_core)
+    }
+
+    fn delete(&self, id: &DocumentId) -> ussl_core::Result<()> {
+        block_on(self.storage.delete(id)).map(|_| ()).map_err(to_core)
+    }
+
+    fn list(&self, pattern: Option<&str>) -> ussl_core::Result<Vec<DocumentId>> {
+        block_on(self.storage.list(pattern)).map_err(to_core)
+    }
+
+    fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> ussl_core::Result<Vec<DocumentMeta>> {
+        block_on(self.storage.scan(after, pattern, count)).map_err(to_core)
+    }
+}
+
+fn block_on<F: Future>(fut: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(fut))
+}
+
+fn to_core(e: StorageError) -> ussl_core::Error {
+    ussl_core::Error::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+    use ussl_core::{CacheConfig, Document, DocumentManager, EvictionPolicy, Strategy, Value};
+
+    fn backed_manager(storage: Arc<MemoryStorage>, budget: usize) -> DocumentManager {
+        let backing = Arc::new(StorageBacking::new(storage));
+        DocumentManager::new().with_backing(backing, CacheConfig::new(budget, EvictionPolicy::Lru))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_evicted_documents_reload() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage.clone(), 0);
+
+        let id = DocumentId::new("fleet:1").unwrap();
+        let doc = manager.create(id.clone(), Strategy::Lww, None).unwrap();
+        doc.set("battery", Value::from(87i64)).unwrap();
+        let version = doc.version();
+        drop(doc);
+
+        assert_eq!(manager.evict().unwrap(), 1);
+        assert_eq!(manager.stats().unwrap().resident_count, 0);
+        assert!(storage.exists(&id).await.unwrap());
+
+        let reloaded = manager.get(&id).unwrap();
+        assert_eq!(reloaded.get(Some("battery")).unwrap(), Value::from(87i64));
+        assert_eq!(reloaded.version(), version);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_evict_skips_documents_in_use() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage, 0);
+
+        let id = DocumentId::new("fleet:2").unwrap();
+        let _held = manager.create(id, Strategy::Lww, None).unwrap();
+
+        assert_eq!(manager.evict().unwrap(), 0);
+        assert_eq!(manager.stats().unwrap().resident_count, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_keys_include_cold_documents() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage, 0);
+
+        for i in 0..3 {
+            let id = DocumentId::new(format!("fleet:{}", i)).unwrap();
+            manager.create(id, Strategy::Lww, None).unwrap();
+        }
+        manager.evict().unwrap();
+        manager.create(DocumentId::new("fleet:hot").unwrap(), Strategy::Lww, None).unwrap();
+
+        let keys = manager.keys(Some("fleet:*")).unwrap();
+        assert_eq!(keys.len(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backup_stats_and_gc_include_cold_documents() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage.clone(), 0);
+
+        let cold = DocumentId::new("fleet:cold").unwrap();
+        let doc = manager.create(cold.clone(), Strategy::Lww, None).unwrap();
+        doc.set("battery", Value::from(87i64)).unwrap();
+        drop(doc);
+        manager.create(DocumentId::new("fleet:expiring").unwrap(), Strategy::Lww, Some(1)).unwrap();
+        assert_eq!(manager.evict().unwrap(), 2);
+        let _hot = manager.create(DocumentId::new("fleet:hot").unwrap(), Strategy::Lww, None).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        let stats = manager.stats().unwrap();
+        assert_eq!((stats.document_count, stats.resident_count), (3, 1));
+
+        let backup = manager.backup().unwrap();
+        let ids: Vec<&str> = backup.documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["fleet:cold", "fleet:hot"]);
+        // Backing up does not make cold documents resident
+        assert_eq!(manager.stats().unwrap().resident_count, 1);
+
+        let restored = DocumentManager::new();
+        restored.restore(&backup).unwrap();
+        assert_eq!(restored.get(&cold).unwrap().get(Some("battery")).unwrap(), Value::from(87i64));
+
+        assert_eq!(manager.gc(), 1);
+        assert_eq!(manager.stats().unwrap().document_count, 2);
+        assert!(!storage.exists(&DocumentId::new("fleet:expiring").unwrap()).await.unwrap());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_changed_since_leaves_unchanged_cold_documents_on_disk() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage, 0);
+
+        let quiet = manager.create(DocumentId::new("fleet:quiet").unwrap(), Strategy::Lww, None).unwrap();
+        quiet.set("battery", Value::from(90i64)).unwrap();
+        let version = quiet.version();
+        drop(quiet);
+        let busy = manager.create(DocumentId::new("fleet:busy").unwrap(), Strategy::Lww, None).unwrap();
+        for level in 0..3i64 {
+            busy.set("battery", Value::from(level)).unwrap();
+        }
+        drop(busy);
+        manager.evict().unwrap();
+
+        let changed = manager.changed_since("fleet:*", version).unwrap();
+        assert_eq!(changed, [DocumentId::new("fleet:busy").unwrap()]);
+        assert_eq!(manager.stats().unwrap().resident_count, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_scan_merges_cold_and_hot_documents() {
+        let storage = Arc::new(MemoryStorage::new());
+        let manager = backed_manager(storage, 0);
+
+        for i in 0..5 {
+            let id = DocumentId::new(format!("fleet:{}", i)).unwrap();
+            manager.create(id, Strategy::Lww, None).unwrap();
+        }
+        manager.evict().unwrap();
+        // Resident again, and also still on disk
+        manager.get(&DocumentId::new("fleet:3").unwrap()).unwrap();
+        manager.create(DocumentId::new("fleet:hot").unwrap(), Strategy::Lww, None).unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = manager.scan(cursor.as_deref(), Some("fleet:*"), None, 2).unwrap();
+            seen.extend(page.ids.into_iter().map(|id| id.as_str().to_string()));
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(seen, ["fleet:0", "fleet:1", "fleet:2", "fleet:3", "fleet:4", "fleet:hot"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lru_evicts_coldest_first() {
+        let storage = Arc::new(MemoryStorage::new());
+        let backing = Arc::new(StorageBacking::new(storage));
+        let cold = DocumentId::new("lru:cold").unwrap();
+        let hot = DocumentId::new("lru:hot").unwrap();
+
+        let probe = Document::new(cold.clone(), Strategy::Lww);
+        let budget = probe.encode_snapshot().len();
+        let manager = DocumentManager::new()
+            .with_backing(backing, CacheConfig::new(budget, EvictionPolicy::Lru));
+
+        manager.create(cold.clone(), Strategy::Lww, None).unwrap();
+        manager.create(hot.clone(), Strategy::Lww, None).unwrap();
+        manager.get(&hot).unwrap();
+
+        assert_eq!(manager.evict().unwrap(), 1);
+        assert_eq!(manager.list(None)[0].id, hot);
+    }
+}
@@ -4,31 +4,115 @@
 //! - Memory (default): Fast, volatile storage
 //! - SQLite: Embedded persistence
 //! - PostgreSQL: Scalable persistence
+//!
+//! The `recovery` module loads persisted documents back into a
+//! `DocumentManager` at startup; `backing` lets a manager lazily load and
+//! evict documents instead.
+//!
+//! Every backend can also keep an update log per document: writes append
+//! their changes instead of rewriting the whole document, and `update_log`
+//! folds the log back into the stored snapshot in the background.
 
+pub mod backing;
 pub mod memory;
+pub mod recovery;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 #[cfg(feature = "postgres")]
 pub mod postgres;
+pub mod update_log;
 
 use async_trait::async_trait;
-use ussl_core::{DocumentId, DocumentMeta};
+use ussl_core::{Change, DocumentId, DocumentMeta};
 
 /// Storage backend trait
 #[async_trait]
 pub trait Storage: Send + Sync {
-    /// Store a document
+    /// Store a document, replacing its snapshot and clearing its update log
     async fn store(&self, id: &DocumentId, meta: &DocumentMeta, data: &[u8]) -> Result<(), StorageError>;
 
-    /// Load a document
+    /// Load a document, replaying its update log onto the stored snapshot
     async fn load(&self, id: &DocumentId) -> Result<Option<(DocumentMeta, Vec<u8>)>, StorageError>;
 
-    /// Delete a document
+    /// Load only a document's metadata
+    ///
+    /// Backends that can read it without the snapshot should override this.
+    async fn load_meta(&self, id: &DocumentId) -> Result<Option<DocumentMeta>, StorageError> {
+        Ok(self.load(id).await?.map(|(meta, _)| meta))
+    }
+
+    /// Delete a document and its update log
     async fn delete(&self, id: &DocumentId) -> Result<bool, StorageError>;
 
+    /// Append changes to a document's update log
+    ///
+    /// `meta` replaces the stored metadata; the snapshot is left alone. A
+    /// document that was never stored starts from an empty snapshot.
+    ///
+    /// Backends without an update log apply the changes to the stored
+    /// snapshot and store the result instead.
+    async fn append(&self, id: &DocumentId, meta: &DocumentMeta, changes: &[Change]) -> Result<(), StorageError> {
+        let snapshot = self.load(id).await?.map(|(_, data)| data).unwrap_or_default();
+        let entries: Vec<Vec<u8>> = changes.iter().map(Change::encode).collect();
+        let data = update_log::replay(meta, &snapshot, &entries)?;
+        self.store(id, meta, &data).await
+    }
+
+    /// Fold a document's update log into its snapshot and truncate the log
+    ///
+    /// Returns the number of log entries folded.
+    async fn fold(&self, _id: &DocumentId) -> Result<usize, StorageError> {
+        Ok(0)
+    }
+
+    /// Documents with at least `min_entries` entries in their update log
+    async fn logged(&self, _min_entries: usize) -> Result<Vec<DocumentId>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    /// Whether writes should be appended to the update log instead of
+    /// rewriting the whole document
+    ///
+    /// Off unless a backend opts in.
+    fn logs_updates(&self) -> bool {
+        false
+    }
+
     /// List document IDs matching a pattern
     async fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>, StorageError>;
 
+    /// Metadata of up to `count` documents matching a pattern, in id order
+    ///
+    /// Only ids greater than `after` are returned, so passing the last id of
+    /// one page fetches the next.
+    ///
+    /// By default each page lists every match and loads the metadata of the
+    /// page's ids; backends that can page natively should override this.
+    async fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<DocumentMeta>, StorageError> {
+        let mut ids: Vec<DocumentId> = self
+            .list(pattern)
+            .await?
+            .into_iter()
+            .filter(|id| after.is_none_or(|a| id.as_str() > a))
+            .collect();
+        ids.sort_unstable_by(|a, b| a.as_str().cmp(b.as_str()));
+        ids.truncate(count);
+
+        let mut metas = Vec::with_capacity(ids.len());
+        for id in ids {
+            // Removed since it was listed
+            if let Some(meta) = self.load_meta(&id).await? {
+                metas.push(meta);
+            }
+        }
+        Ok(metas)
+    }
+
     /// Check if a document exists
     async fn exists(&self, id: &DocumentId) -> Result<bool, StorageError>;
 
@@ -60,10 +144,91 @@ pub enum StorageError {
 pub struct StorageStats {
     pub document_count: usize,
     pub total_size_bytes: usize,
+    /// Update log entries not yet folded into snapshots
+    pub log_entries: usize,
 }
 
+pub use backing::StorageBacking;
 pub use memory::MemoryStorage;
+pub use recovery::{recover, RecoveryStats};
+pub use update_log::{fold_logs, FoldStats};
 #[cfg(feature = "sqlite")]
 pub use sqlite::SqliteStorage;
 #[cfg(feature = "postgres")]
 pub use postgres::PostgresStorage;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ussl_core::{Document, Strategy, Value};
+
+    /// A backend implementing only the required methods
+    struct SnapshotOnly(MemoryStorage);
+
+    #[async_trait]
+    impl Storage for SnapshotOnly {
+        async fn store(&self, id: &DocumentId, meta: &DocumentMeta, data: &[u8]) -> Result<(), StorageError> {
+            self.0.store(id, meta, data).await
+        }
+
+        async fn load(&self, id: &DocumentId) -> Result<Option<(DocumentMeta, Vec<u8>)>, StorageError> {
+            self.0.load(id).await
+        }
+
+        async fn delete(&self, id: &DocumentId) -> Result<bool, StorageError> {
+            self.0.delete(id).await
+        }
+
+        async fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>, StorageError> {
+            self.0.list(pattern).await
+        }
+
+        async fn exists(&self, id: &DocumentId) -> Result<bool, StorageError> {
+            self.0.exists(id).await
+        }
+
+        async fn stats(&self) -> Result<StorageStats, StorageError> {
+            self.0.stats().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append_falls_back_to_store() {
+        let storage = SnapshotOnly(MemoryStorage::new());
+        assert!(!storage.logs_updates());
+        let id = DocumentId::new("doc:plain").unwrap();
+        let doc = Document::new(id.clone(), Strategy::Lww);
+
+        for (path, value) in [("title", "draft"), ("owner", "ana"), ("title", "final")] {
+            doc.set(path, Value::from(value)).unwrap();
+            let delta = doc.take_delta(Some(path.to_string()));
+            storage.append(&id, &doc.meta(), &doc.changes(&delta).unwrap()).await.unwrap();
+        }
+
+        let (meta, snapshot) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(meta.version, doc.version());
+        let loaded = Document::from_snapshot(meta, &snapshot).unwrap();
+        assert_eq!(loaded.get(None).unwrap(), doc.get(None).unwrap());
+        assert!(storage.logged(1).await.unwrap().is_empty());
+        assert_eq!(storage.fold(&id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_default_scan_pages_in_order() {
+        let storage = SnapshotOnly(MemoryStorage::new());
+        for id in ["user:3", "cart:1", "user:1", "user:2", "user:10"] {
+            let id = DocumentId::new(id).unwrap();
+            storage.store(&id, &DocumentMeta::new(id.clone(), Strategy::Lww), b"data").await.unwrap();
+        }
+
+        let page = storage.scan(None, Some("user:*"), 2).await.unwrap();
+        let ids: Vec<&str> = page.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["user:1", "user:10"]);
+
+        let page = storage.scan(Some("user:10"), Some("user:*"), 2).await.unwrap();
+        let ids: Vec<&str> = page.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["user:2", "user:3"]);
+
+        assert!(storage.scan(Some("user:3"), Some("user:*"), 2).await.unwrap().is_empty());
+    }
+}
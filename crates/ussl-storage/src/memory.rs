@@ -1,9 +1,9 @@
 //! In-memory storage backend
 
-use crate::{Storage, StorageError, StorageStats};
+use crate::{update_log, Storage, StorageError, StorageStats};
 use async_trait::async_trait;
 use dashmap::DashMap;
-use ussl_core::{DocumentId, DocumentMeta};
+use ussl_core::{Change, DocumentId, DocumentMeta, Glob};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// In-memory storage backend
@@ -13,17 +13,43 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 pub struct MemoryStorage {
     /// Document data: id -> (meta_bytes, data_bytes)
     data: DashMap<String, (Vec<u8>, Vec<u8>)>,
+    /// Update log: id -> (version, encoded change), in arrival order
+    log: DashMap<String, Vec<(u64, Vec<u8>)>>,
     /// Total size tracking
     total_size: AtomicUsize,
+    /// Whether writes go to the update log
+    update_log: bool,
 }
 
 impl MemoryStorage {
     pub fn new() -> Self {
         Self {
             data: DashMap::new(),
+            log: DashMap::new(),
             total_size: AtomicUsize::new(0),
+            update_log: false,
         }
     }
+
+    /// Append writes to an update log instead of rewriting documents
+    pub fn with_update_log(mut self) -> Self {
+        self.update_log = true;
+        self
+    }
+}
+
+/// Version recorded in stored metadata
+fn stored_version(meta_bytes: &[u8]) -> Result<u64, StorageError> {
+    let meta: DocumentMeta = serde_json::from_slice(meta_bytes)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    Ok(meta.version)
+}
+
+/// Log entries in replay order: by version, then by arrival
+fn ordered(log: &[(u64, Vec<u8>)]) -> Vec<Vec<u8>> {
+    let mut log = log.to_vec();
+    log.sort_by_key(|(version, _)| *version);
+    log.into_iter().map(|(_, entry)| entry).collect()
 }
 
 impl Default for MemoryStorage {
@@ -39,16 +65,22 @@ impl Storage for MemoryStorage {
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
         let key = id.as_str().to_string();
-        let new_size = meta_bytes.len() + data.len();
+        let mut entry = self.data.entry(key.clone()).or_default();
+        // A snapshot older than the stored one is already superseded
+        if !entry.0.is_empty() && stored_version(&entry.0)? > meta.version {
+            return Ok(());
+        }
 
         // Update size tracking
-        if let Some(existing) = self.data.get(&key) {
-            let old_size = existing.0.len() + existing.1.len();
-            self.total_size.fetch_sub(old_size, Ordering::Relaxed);
-        }
-        self.total_size.fetch_add(new_size, Ordering::Relaxed);
+        self.total_size.fetch_sub(entry.0.len() + entry.1.len(), Ordering::Relaxed);
+        self.total_size.fetch_add(meta_bytes.len() + data.len(), Ordering::Relaxed);
+        *entry = (meta_bytes, data.to_vec());
 
-        self.data.insert(key, (meta_bytes, data.to_vec()));
+        // The snapshot contains everything logged up to its version
+        if let Some(mut log) = self.log.get_mut(&key) {
+            log.retain(|(version, _)| *version > meta.version);
+        }
+        self.log.remove_if(&key, |_, log| log.is_empty());
         Ok(())
     }
 
@@ -58,13 +90,26 @@ impl Storage for MemoryStorage {
                 let (meta_bytes, data) = entry.value();
                 let meta: DocumentMeta = serde_json::from_slice(meta_bytes)
                     .map_err(|e| StorageError::Serialization(e.to_string()))?;
-                Ok(Some((meta, data.clone())))
+                let entries = self.log.get(id.as_str()).map(|log| ordered(&log)).unwrap_or_default();
+                let data = update_log::replay(&meta, data, &entries)?;
+                Ok(Some((meta, data)))
             }
             None => Ok(None),
         }
     }
 
+    async fn load_meta(&self, id: &DocumentId) -> Result<Option<DocumentMeta>, StorageError> {
+        self.data
+            .get(id.as_str())
+            .map(|entry| {
+                serde_json::from_slice(&entry.value().0)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .transpose()
+    }
+
     async fn delete(&self, id: &DocumentId) -> Result<bool, StorageError> {
+        self.log.remove(id.as_str());
         match self.data.remove(id.as_str()) {
             Some((_, (meta_bytes, data))) => {
                 let size = meta_bytes.len() + data.len();
@@ -75,11 +120,67 @@ impl Storage for MemoryStorage {
         }
     }
 
+    async fn append(&self, id: &DocumentId, meta: &DocumentMeta, changes: &[Change]) -> Result<(), StorageError> {
+        let meta_bytes = serde_json::to_vec(meta)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let key = id.as_str().to_string();
+        let mut entry = self.data.entry(key.clone()).or_default();
+        if entry.0.is_empty() || stored_version(&entry.0)? <= meta.version {
+            self.total_size.fetch_sub(entry.0.len(), Ordering::Relaxed);
+            self.total_size.fetch_add(meta_bytes.len(), Ordering::Relaxed);
+            entry.0 = meta_bytes;
+        }
+
+        self.log
+            .entry(key)
+            .or_default()
+            .extend(changes.iter().map(|change| (meta.version, change.encode())));
+        Ok(())
+    }
+
+    async fn fold(&self, id: &DocumentId) -> Result<usize, StorageError> {
+        // Holding the document entry keeps stores and appends out meanwhile
+        let Some(mut entry) = self.data.get_mut(id.as_str()) else {
+            return Ok(0);
+        };
+        let Some(mut log) = self.log.get_mut(id.as_str()) else {
+            return Ok(0);
+        };
+
+        let meta: DocumentMeta = serde_json::from_slice(&entry.0)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let data = update_log::replay(&meta, &entry.1, &ordered(&log))?;
+        self.total_size.fetch_sub(entry.1.len(), Ordering::Relaxed);
+        self.total_size.fetch_add(data.len(), Ordering::Relaxed);
+        entry.1 = data;
+
+        let folded = log.len();
+        log.clear();
+        drop(log);
+        self.log.remove_if(id.as_str(), |_, log| log.is_empty());
+        Ok(folded)
+    }
+
+    async fn logged(&self, min_entries: usize) -> Result<Vec<DocumentId>, StorageError> {
+        Ok(self
+            .log
+            .iter()
+            .filter(|log| log.len() >= min_entries)
+            .filter_map(|log| DocumentId::new(log.key().clone()).ok())
+            .collect())
+    }
+
+    fn logs_updates(&self) -> bool {
+        self.update_log
+    }
+
     async fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>, StorageError> {
+        let glob = pattern.map(Glob::new);
         let mut ids = Vec::new();
         for entry in self.data.iter() {
             let key = entry.key();
-            let matches = pattern.map_or(true, |p| matches_pattern(key, p));
+            let matches = glob.as_ref().is_none_or(|g| g.matches(key));
             if matches {
                 if let Ok(id) = DocumentId::new(key.clone()) {
                     ids.push(id);
@@ -89,35 +190,51 @@ impl Storage for MemoryStorage {
         Ok(ids)
     }
 
+    async fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<DocumentMeta>, StorageError> {
+        // The map is unordered, so each page sorts the remaining matches
+        let glob = pattern.map(Glob::new);
+        let mut keys: Vec<String> = self
+            .data
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|key| after.is_none_or(|a| key.as_str() > a))
+            .filter(|key| glob.as_ref().is_none_or(|g| g.matches(key)))
+            .collect();
+        keys.sort_unstable();
+        keys.truncate(count);
+
+        let mut metas = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(entry) = self.data.get(&key) {
+                let meta: DocumentMeta = serde_json::from_slice(&entry.value().0)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+                metas.push(meta);
+            }
+        }
+        Ok(metas)
+    }
+
     async fn exists(&self, id: &DocumentId) -> Result<bool, StorageError> {
         Ok(self.data.contains_key(id.as_str()))
     }
 
     async fn stats(&self) -> Result<StorageStats, StorageError> {
+        let (log_entries, log_size) = self.log.iter().fold((0, 0), |(count, size), log| {
+            (count + log.len(), size + log.iter().map(|(_, entry)| entry.len()).sum::<usize>())
+        });
         Ok(StorageStats {
             document_count: self.data.len(),
-            total_size_bytes: self.total_size.load(Ordering::Relaxed),
+            total_size_bytes: self.total_size.load(Ordering::Relaxed) + log_size,
+            log_entries,
         })
     }
 }
 
-/// Simple glob pattern matching
-fn matches_pattern(key: &str, pattern: &str) -> bool {
-    if pattern == "*" {
-        return true;
-    }
-
-    if let Some(prefix) = pattern.strip_suffix('*') {
-        return key.starts_with(prefix);
-    }
-
-    if let Some(suffix) = pattern.strip_prefix('*') {
-        return key.ends_with(suffix);
-    }
-
-    key == pattern
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,10 +289,33 @@ mod tests {
         let carts = storage.list(Some("cart:*")).await.unwrap();
         assert_eq!(carts.len(), 3);
 
+        assert_eq!(storage.list(Some("*:[0-2]")).await.unwrap().len(), 6);
+        assert_eq!(storage.list(Some("?ser:?")).await.unwrap().len(), 5);
+
         let all = storage.list(None).await.unwrap();
         assert_eq!(all.len(), 8);
     }
 
+    #[tokio::test]
+    async fn test_scan_pages() {
+        let storage = MemoryStorage::new();
+        for id in ["user:3", "cart:1", "user:1", "user:2", "user:10"] {
+            let id = DocumentId::new(id).unwrap();
+            let meta = DocumentMeta::new(id.clone(), Strategy::Lww);
+            storage.store(&id, &meta, b"data").await.unwrap();
+        }
+
+        let page = storage.scan(None, Some("user:*"), 2).await.unwrap();
+        let ids: Vec<&str> = page.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["user:1", "user:10"]);
+
+        let page = storage.scan(Some("user:10"), Some("user:*"), 2).await.unwrap();
+        let ids: Vec<&str> = page.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["user:2", "user:3"]);
+
+        assert!(storage.scan(Some("user:3"), Some("user:*"), 2).await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn test_stats() {
         let storage = MemoryStorage::new();
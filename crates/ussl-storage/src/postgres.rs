@@ -1,16 +1,19 @@
 //! PostgreSQL storage backend
 
-use crate::{Storage, StorageError, StorageStats};
+use crate::{update_log, Storage, StorageError, StorageStats};
 use async_trait::async_trait;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::Row;
-use ussl_core::{DocumentId, DocumentMeta};
+use ussl_core::pattern::Token;
+use ussl_core::{Change, DocumentId, DocumentMeta, Glob};
 
 /// PostgreSQL storage backend
 ///
 /// Scalable persistence with NOTIFY/LISTEN support for real-time sync.
 pub struct PostgresStorage {
     pool: PgPool,
+    /// Whether writes go to the update log
+    update_log: bool,
 }
 
 impl PostgresStorage {
@@ -22,7 +25,10 @@ impl PostgresStorage {
             .await
             .map_err(|e| StorageError::Connection(e.to_string()))?;
 
-        let storage = Self { pool };
+        let storage = Self {
+            pool,
+            update_log: false,
+        };
         storage.init_schema().await?;
 
         Ok(storage)
@@ -30,7 +36,16 @@ impl PostgresStorage {
 
     /// Create with an existing connection pool
     pub fn with_pool(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            update_log: false,
+        }
+    }
+
+    /// Append writes to an update log instead of rewriting documents
+    pub fn with_update_log(mut self) -> Self {
+        self.update_log = true;
+        self
     }
 
     async fn init_schema(&self) -> Result<(), StorageError> {
@@ -40,11 +55,24 @@ impl PostgresStorage {
                 id TEXT PRIMARY KEY,
                 meta BYTEA NOT NULL,
                 data BYTEA NOT NULL,
+                version BIGINT NOT NULL DEFAULT 0,
                 created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                 updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
             );
 
+            -- Databases created before documents carried their version
+            ALTER TABLE documents ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 0;
+
             CREATE INDEX IF NOT EXISTS idx_documents_updated_at ON documents(updated_at DESC);
+
+            CREATE TABLE IF NOT EXISTS document_log (
+                seq BIGSERIAL PRIMARY KEY,
+                id TEXT NOT NULL,
+                version BIGINT NOT NULL,
+                entry BYTEA NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_document_log_id ON document_log(id, version, seq);
             "#,
         )
         .execute(&self.pool)
@@ -101,24 +129,41 @@ impl Storage for PostgresStorage {
         let meta_bytes = serde_json::to_vec(meta)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
         sqlx::query(
             r#"
-            INSERT INTO documents (id, meta, data, updated_at)
-            VALUES ($1, $2, $3, NOW())
+            INSERT INTO documents (id, meta, data, version, updated_at)
+            VALUES ($1, $2, $3, $4, NOW())
             ON CONFLICT (id) DO UPDATE SET
                 meta = EXCLUDED.meta,
                 data = EXCLUDED.data,
+                version = EXCLUDED.version,
                 updated_at = NOW()
+            WHERE EXCLUDED.version >= documents.version
             "#,
         )
         .bind(id.as_str())
         .bind(&meta_bytes)
         .bind(data)
-        .execute(&self.pool)
+        .bind(meta.version as i64)
+        .execute(&mut *tx)
         .await
         .map_err(|e| StorageError::Database(e.to_string()))?;
 
-        Ok(())
+        // The snapshot contains everything logged up to its version
+        sqlx::query("DELETE FROM document_log WHERE id = $1 AND version <= $2")
+            .bind(id.as_str())
+            .bind(meta.version as i64)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        tx.commit().await.map_err(|e| StorageError::Database(e.to_string()))
     }
 
     async fn load(
@@ -131,21 +176,33 @@ impl Storage for PostgresStorage {
             .await
             .map_err(|e| StorageError::Database(e.to_string()))?;
 
-        match row {
-            Some(row) => {
-                let meta_bytes: Vec<u8> = row.get("meta");
-                let data: Vec<u8> = row.get("data");
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let meta_bytes: Vec<u8> = row.get("meta");
+        let data: Vec<u8> = row.get("data");
 
-                let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
-                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-                Ok(Some((meta, data)))
-            }
-            None => Ok(None),
-        }
+        let entries = sqlx::query("SELECT entry FROM document_log WHERE id = $1 ORDER BY version, seq")
+            .bind(id.as_str())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        let entries: Vec<Vec<u8>> = entries.iter().map(|row| row.get("entry")).collect();
+
+        let data = update_log::replay(&meta, &data, &entries)?;
+        Ok(Some((meta, data)))
     }
 
     async fn delete(&self, id: &DocumentId) -> Result<bool, StorageError> {
+        sqlx::query("DELETE FROM document_log WHERE id = $1")
+            .bind(id.as_str())
+            .execute(&self.pool)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
         let result = sqlx::query("DELETE FROM documents WHERE id = $1")
             .bind(id.as_str())
             .execute(&self.pool)
@@ -155,28 +212,134 @@ impl Storage for PostgresStorage {
         Ok(result.rows_affected() > 0)
     }
 
+    async fn append(
+        &self,
+        id: &DocumentId,
+        meta: &DocumentMeta,
+        changes: &[Change],
+    ) -> Result<(), StorageError> {
+        let meta_bytes = serde_json::to_vec(meta)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO documents (id, meta, data, version, updated_at)
+            VALUES ($1, $2, ''::bytea, $3, NOW())
+            ON CONFLICT (id) DO UPDATE SET
+                meta = EXCLUDED.meta,
+                version = EXCLUDED.version,
+                updated_at = NOW()
+            WHERE EXCLUDED.version >= documents.version
+            "#,
+        )
+        .bind(id.as_str())
+        .bind(&meta_bytes)
+        .bind(meta.version as i64)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        for change in changes {
+            sqlx::query("INSERT INTO document_log (id, version, entry) VALUES ($1, $2, $3)")
+                .bind(id.as_str())
+                .bind(meta.version as i64)
+                .bind(change.encode())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| StorageError::Database(e.to_string()))?;
+        }
+
+        tx.commit().await.map_err(|e| StorageError::Database(e.to_string()))
+    }
+
+    async fn fold(&self, id: &DocumentId) -> Result<usize, StorageError> {
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        // Locking the document row keeps stores and appends out meanwhile
+        let row = sqlx::query("SELECT meta, data FROM documents WHERE id = $1 FOR UPDATE")
+            .bind(id.as_str())
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        let entries = sqlx::query("SELECT seq, entry FROM document_log WHERE id = $1 ORDER BY version, seq")
+            .bind(id.as_str())
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        if let Some(row) = row {
+            let meta_bytes: Vec<u8> = row.get("meta");
+            let data: Vec<u8> = row.get("data");
+            let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
+                .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            let entries: Vec<Vec<u8>> = entries.iter().map(|row| row.get("entry")).collect();
+
+            sqlx::query("UPDATE documents SET data = $2 WHERE id = $1")
+                .bind(id.as_str())
+                .bind(update_log::replay(&meta, &data, &entries)?)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| StorageError::Database(e.to_string()))?;
+        }
+
+        let last: i64 = entries.iter().map(|row| row.get::<i64, _>("seq")).max().unwrap_or(0);
+        let result = sqlx::query("DELETE FROM document_log WHERE id = $1 AND seq <= $2")
+            .bind(id.as_str())
+            .bind(last)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        tx.commit().await.map_err(|e| StorageError::Database(e.to_string()))?;
+        Ok(result.rows_affected() as usize)
+    }
+
+    async fn logged(&self, min_entries: usize) -> Result<Vec<DocumentId>, StorageError> {
+        let rows = sqlx::query("SELECT id FROM document_log GROUP BY id HAVING COUNT(*) >= $1")
+            .bind(min_entries as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| DocumentId::new(row.get::<String, _>("id")).ok())
+            .collect())
+    }
+
+    fn logs_updates(&self) -> bool {
+        self.update_log
+    }
+
     async fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>, StorageError> {
-        let rows = match pattern {
-            Some(p) if p.ends_with('*') => {
-                let prefix = p.trim_end_matches('*');
-                sqlx::query("SELECT id FROM documents WHERE id LIKE $1 ORDER BY updated_at DESC")
-                    .bind(format!("{}%", prefix))
-                    .fetch_all(&self.pool)
-                    .await
-            }
-            Some(p) if p.starts_with('*') => {
-                let suffix = p.trim_start_matches('*');
-                sqlx::query("SELECT id FROM documents WHERE id LIKE $1 ORDER BY updated_at DESC")
-                    .bind(format!("%{}", suffix))
-                    .fetch_all(&self.pool)
-                    .await
-            }
-            Some(p) => {
-                sqlx::query("SELECT id FROM documents WHERE id = $1")
-                    .bind(p)
-                    .fetch_all(&self.pool)
-                    .await
-            }
+        let rows = match pattern.map(Glob::new) {
+            Some(glob) => match glob.as_literal() {
+                Some(id) => {
+                    sqlx::query("SELECT id FROM documents WHERE id = $1")
+                        .bind(id)
+                        .fetch_all(&self.pool)
+                        .await
+                }
+                None => {
+                    sqlx::query("SELECT id FROM documents WHERE id ~ $1 ORDER BY updated_at DESC")
+                        .bind(to_postgres_regex(&glob))
+                        .fetch_all(&self.pool)
+                        .await
+                }
+            },
             None => {
                 sqlx::query("SELECT id FROM documents ORDER BY updated_at DESC")
                     .fetch_all(&self.pool)
@@ -196,6 +359,38 @@ impl Storage for PostgresStorage {
         Ok(ids)
     }
 
+    async fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<DocumentMeta>, StorageError> {
+        // Compare ids bytewise, as the manager does, whatever the database locale
+        let rows = sqlx::query(
+            r#"
+            SELECT meta FROM documents
+            WHERE ($1::text IS NULL OR id COLLATE "C" > $1)
+              AND ($2::text IS NULL OR id ~ $2)
+            ORDER BY id COLLATE "C"
+            LIMIT $3
+            "#,
+        )
+        .bind(after)
+        .bind(pattern.map(|p| to_postgres_regex(&Glob::new(p))))
+        .bind(count as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        rows.iter()
+            .map(|row| {
+                let meta_bytes: Vec<u8> = row.get("meta");
+                serde_json::from_slice(&meta_bytes)
+                    .map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+
     async fn exists(&self, id: &DocumentId) -> Result<bool, StorageError> {
         let row = sqlx::query("SELECT 1 FROM documents WHERE id = $1")
             .bind(id.as_str())
@@ -223,13 +418,60 @@ impl Storage for PostgresStorage {
 
         let total_size: i64 = size_row.get("size");
 
+        let log_row = sqlx::query(
+            "SELECT COUNT(*) as count, COALESCE(SUM(LENGTH(entry)), 0) as size FROM document_log",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        let log_entries: i64 = log_row.get("count");
+        let log_size: i64 = log_row.get("size");
+
         Ok(StorageStats {
             document_count: document_count as usize,
-            total_size_bytes: total_size as usize,
+            total_size_bytes: (total_size + log_size) as usize,
+            log_entries: log_entries as usize,
         })
     }
 }
 
+/// Translate a glob into an anchored PostgreSQL regular expression
+fn to_postgres_regex(glob: &Glob) -> String {
+    // Outside and inside brackets, `\` before a non-alphanumeric is a literal
+    fn escaped(out: &mut String, c: char) {
+        if !c.is_alphanumeric() {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    let mut out = String::from("^");
+    for token in glob.tokens() {
+        match token {
+            Token::Literal(c) => escaped(&mut out, *c),
+            Token::One => out.push('.'),
+            Token::Any => out.push_str(".*"),
+            Token::Class { negated, ranges } => {
+                out.push('[');
+                if *negated {
+                    out.push('^');
+                }
+                for &(lo, hi) in ranges {
+                    escaped(&mut out, lo);
+                    if hi != lo {
+                        out.push('-');
+                        escaped(&mut out, hi);
+                    }
+                }
+                out.push(']');
+            }
+        }
+    }
+    out.push('$');
+    out
+}
+
 #[cfg(test)]
 mod tests {
     // Integration tests require a running PostgreSQL instance
@@ -237,6 +479,13 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_postgres_regex_translation() {
+        assert_eq!(to_postgres_regex(&Glob::new("tenant:*:user:?")), "^tenant\\:.*\\:user\\:.$");
+        assert_eq!(to_postgres_regex(&Glob::new("a.b[!x-z]")), "^a\\.b[^x-z]$");
+        assert_eq!(to_postgres_regex(&Glob::new("[]a]")), "^[\\]a]$");
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_postgres_crud() {
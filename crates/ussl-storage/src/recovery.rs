@@ -0,0 +1,117 @@
+//! Startup recovery - rehydrates a DocumentManager from persistent storage
+
+use crate::{Storage, StorageError};
+use tracing::{info, warn};
+use ussl_core::DocumentManager;
+
+/// Number of documents between progress log lines
+const PROGRESS_INTERVAL: usize = 1000;
+
+/// Outcome of a recovery run
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryStats {
+    /// Documents found in storage
+    pub total: usize,
+    /// Documents loaded into the manager
+    pub loaded: usize,
+    /// Documents skipped (and removed from storage) because their TTL elapsed
+    pub expired: usize,
+    /// Documents that could not be decoded
+    pub failed: usize,
+}
+
+/// Load every stored document back into the manager
+///
+/// Metadata (strategy, version, timestamps, TTL) is restored verbatim.
+/// Documents whose TTL already elapsed are dropped from storage instead of
+/// being loaded. Individual decode failures are logged and counted but do
+/// not abort the run; storage errors on listing do.
+pub async fn recover(
+    manager: &DocumentManager,
+    storage: &dyn Storage,
+) -> Result<RecoveryStats, StorageError> {
+    let ids = storage.list(None).await?;
+    let mut stats = RecoveryStats {
+        total: ids.len(),
+        ..Default::default()
+    };
+
+    info!(documents = stats.total, "Recovering documents from storage");
+
+    for (i, id) in ids.iter().enumerate() {
+        match storage.load(id).await {
+            Ok(Some((meta, state))) => {
+                if meta.is_expired() {
+                    if let Err(e) = storage.delete(id).await {
+                        warn!(doc_id = %id, error = %e, "Failed to drop expired document");
+                    }
+                    stats.expired += 1;
+                } else {
+                    match manager.load(meta, &state) {
+                        Ok(_) => stats.loaded += 1,
+                        Err(e) => {
+                            warn!(doc_id = %id, error = %e, "Failed to restore document");
+                            stats.failed += 1;
+                        }
+                    }
+                }
+            }
+            // Deleted between list and load
+            Ok(None) => {}
+            Err(e) => {
+                warn!(doc_id = %id, error = %e, "Failed to load document");
+                stats.failed += 1;
+            }
+        }
+
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            info!(progress = i + 1, total = stats.total, "Recovery in progress");
+        }
+    }
+
+    info!(
+        loaded = stats.loaded,
+        expired = stats.expired,
+        failed = stats.failed,
+        "Recovery completed"
+    );
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+    use ussl_core::{Document, DocumentId, DocumentMeta, Strategy, Value};
+
+    #[tokio::test]
+    async fn test_recover_restores_documents() {
+        let storage = MemoryStorage::new();
+
+        let id = DocumentId::new("doc:1").unwrap();
+        let doc = Document::new(id.clone(), Strategy::CrdtText);
+        doc.set("content", Value::String("hello".into())).unwrap();
+        storage.store(&id, &doc.meta(), &doc.encode_snapshot()).await.unwrap();
+
+        let expired_id = DocumentId::new("doc:2").unwrap();
+        let mut meta = DocumentMeta::with_ttl(expired_id.clone(), Strategy::Lww, 10);
+        meta.created_at -= 1_000;
+        storage.store(&expired_id, &meta, &[]).await.unwrap();
+
+        let manager = DocumentManager::new();
+        let stats = recover(&manager, &storage).await.unwrap();
+
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.loaded, 1);
+        assert_eq!(stats.expired, 1);
+        assert_eq!(stats.failed, 0);
+
+        let restored = manager.get(&id).unwrap();
+        assert_eq!(restored.get(None).unwrap(), Value::String("hello".into()));
+        assert_eq!(restored.version(), doc.version());
+
+        assert!(manager.get(&expired_id).is_err());
+        assert!(!storage.exists(&expired_id).await.unwrap());
+    }
+}
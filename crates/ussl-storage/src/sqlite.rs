@@ -1,17 +1,20 @@
 //! SQLite storage backend
 
-use crate::{Storage, StorageError, StorageStats};
+use crate::{update_log, Storage, StorageError, StorageStats};
 use async_trait::async_trait;
 use rusqlite::{params, Connection, OptionalExtension};
 use std::path::Path;
 use std::sync::Mutex;
-use ussl_core::{DocumentId, DocumentMeta};
+use ussl_core::pattern::Token;
+use ussl_core::{Change, DocumentId, DocumentMeta, Glob};
 
 /// SQLite storage backend
 ///
 /// Embedded persistence suitable for edge deployments and single-node setups.
 pub struct SqliteStorage {
     conn: Mutex<Connection>,
+    /// Whether writes go to the update log
+    update_log: bool,
 }
 
 impl SqliteStorage {
@@ -22,6 +25,7 @@ impl SqliteStorage {
 
         let storage = Self {
             conn: Mutex::new(conn),
+            update_log: false,
         };
 
         storage.init_schema()?;
@@ -35,12 +39,19 @@ impl SqliteStorage {
 
         let storage = Self {
             conn: Mutex::new(conn),
+            update_log: false,
         };
 
         storage.init_schema()?;
         Ok(storage)
     }
 
+    /// Append writes to an update log instead of rewriting documents
+    pub fn with_update_log(mut self) -> Self {
+        self.update_log = true;
+        self
+    }
+
     fn init_schema(&self) -> Result<(), StorageError> {
         let conn = self.conn.lock().unwrap();
 
@@ -50,15 +61,38 @@ impl SqliteStorage {
                 id TEXT PRIMARY KEY,
                 meta BLOB NOT NULL,
                 data BLOB NOT NULL,
+                version INTEGER NOT NULL DEFAULT 0,
                 created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now') * 1000),
                 updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now') * 1000)
             );
 
             CREATE INDEX IF NOT EXISTS idx_documents_updated_at ON documents(updated_at);
+
+            CREATE TABLE IF NOT EXISTS document_log (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                id TEXT NOT NULL,
+                version INTEGER NOT NULL,
+                entry BLOB NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_document_log_id ON document_log(id, version, seq);
             "#,
         )
         .map_err(|e| StorageError::Database(e.to_string()))?;
 
+        // Databases created before documents carried their version
+        let has_version: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('documents') WHERE name = 'version'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        if !has_version {
+            conn.execute("ALTER TABLE documents ADD COLUMN version INTEGER NOT NULL DEFAULT 0", [])
+                .map_err(|e| StorageError::Database(e.to_string()))?;
+        }
+
         Ok(())
     }
 }
@@ -74,22 +108,34 @@ impl Storage for SqliteStorage {
         let meta_bytes = serde_json::to_vec(meta)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
 
-        let conn = self.conn.lock().unwrap();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
 
-        conn.execute(
+        tx.execute(
             r#"
-            INSERT INTO documents (id, meta, data, updated_at)
-            VALUES (?1, ?2, ?3, strftime('%s', 'now') * 1000)
+            INSERT INTO documents (id, meta, data, version, updated_at)
+            VALUES (?1, ?2, ?3, ?4, strftime('%s', 'now') * 1000)
             ON CONFLICT(id) DO UPDATE SET
                 meta = excluded.meta,
                 data = excluded.data,
+                version = excluded.version,
                 updated_at = excluded.updated_at
+            WHERE excluded.version >= documents.version
             "#,
-            params![id.as_str(), meta_bytes, data],
+            params![id.as_str(), meta_bytes, data, meta.version as i64],
         )
         .map_err(|e| StorageError::Database(e.to_string()))?;
 
-        Ok(())
+        // The snapshot contains everything logged up to its version
+        tx.execute(
+            "DELETE FROM document_log WHERE id = ?1 AND version <= ?2",
+            params![id.as_str(), meta.version as i64],
+        )
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        tx.commit().map_err(|e| StorageError::Database(e.to_string()))
     }
 
     async fn load(
@@ -107,19 +153,23 @@ impl Storage for SqliteStorage {
             .optional()
             .map_err(|e| StorageError::Database(e.to_string()))?;
 
-        match result {
-            Some((meta_bytes, data)) => {
-                let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
-                    .map_err(|e| StorageError::Serialization(e.to_string()))?;
-                Ok(Some((meta, data)))
-            }
-            None => Ok(None),
-        }
+        let Some((meta_bytes, data)) = result else {
+            return Ok(None);
+        };
+        let entries: Vec<Vec<u8>> = log_entries(&conn, id)?.into_iter().map(|(_, entry)| entry).collect();
+        drop(conn);
+
+        let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let data = update_log::replay(&meta, &data, &entries)?;
+        Ok(Some((meta, data)))
     }
 
     async fn delete(&self, id: &DocumentId) -> Result<bool, StorageError> {
         let conn = self.conn.lock().unwrap();
 
+        conn.execute("DELETE FROM document_log WHERE id = ?1", params![id.as_str()])
+            .map_err(|e| StorageError::Database(e.to_string()))?;
         let affected = conn
             .execute("DELETE FROM documents WHERE id = ?1", params![id.as_str()])
             .map_err(|e| StorageError::Database(e.to_string()))?;
@@ -127,37 +177,124 @@ impl Storage for SqliteStorage {
         Ok(affected > 0)
     }
 
+    async fn append(
+        &self,
+        id: &DocumentId,
+        meta: &DocumentMeta,
+        changes: &[Change],
+    ) -> Result<(), StorageError> {
+        let meta_bytes = serde_json::to_vec(meta)
+            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        tx.execute(
+            r#"
+            INSERT INTO documents (id, meta, data, version, updated_at)
+            VALUES (?1, ?2, X'', ?3, strftime('%s', 'now') * 1000)
+            ON CONFLICT(id) DO UPDATE SET
+                meta = excluded.meta,
+                version = excluded.version,
+                updated_at = excluded.updated_at
+            WHERE excluded.version >= documents.version
+            "#,
+            params![id.as_str(), meta_bytes, meta.version as i64],
+        )
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        for change in changes {
+            tx.execute(
+                "INSERT INTO document_log (id, version, entry) VALUES (?1, ?2, ?3)",
+                params![id.as_str(), meta.version as i64, change.encode()],
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        }
+
+        tx.commit().map_err(|e| StorageError::Database(e.to_string()))
+    }
+
+    async fn fold(&self, id: &DocumentId) -> Result<usize, StorageError> {
+        // Holding the connection keeps stores and appends out meanwhile
+        let mut conn = self.conn.lock().unwrap();
+
+        let entries = log_entries(&conn, id)?;
+        let Some(last) = entries.iter().map(|(seq, _)| *seq).max() else {
+            return Ok(0);
+        };
+        let row: Option<(Vec<u8>, Vec<u8>)> = conn
+            .query_row(
+                "SELECT meta, data FROM documents WHERE id = ?1",
+                params![id.as_str()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        let tx = conn
+            .transaction()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        if let Some((meta_bytes, data)) = row {
+            let meta: DocumentMeta = serde_json::from_slice(&meta_bytes)
+                .map_err(|e| StorageError::Serialization(e.to_string()))?;
+            let entries: Vec<Vec<u8>> = entries.into_iter().map(|(_, entry)| entry).collect();
+            let data = update_log::replay(&meta, &data, &entries)?;
+            tx.execute(
+                "UPDATE documents SET data = ?2 WHERE id = ?1",
+                params![id.as_str(), data],
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        }
+        let folded = tx
+            .execute(
+                "DELETE FROM document_log WHERE id = ?1 AND seq <= ?2",
+                params![id.as_str(), last],
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        tx.commit().map_err(|e| StorageError::Database(e.to_string()))?;
+
+        Ok(folded)
+    }
+
+    async fn logged(&self, min_entries: usize) -> Result<Vec<DocumentId>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT id FROM document_log GROUP BY id HAVING COUNT(*) >= ?1")
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        let ids: Vec<String> = stmt
+            .query_map(params![min_entries as i64], |row| row.get(0))
+            .map_err(|e| StorageError::Database(e.to_string()))?
+            .collect::<Result<_, _>>()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        Ok(ids.into_iter().filter_map(|id| DocumentId::new(id).ok()).collect())
+    }
+
+    fn logs_updates(&self) -> bool {
+        self.update_log
+    }
+
     async fn list(&self, pattern: Option<&str>) -> Result<Vec<DocumentId>, StorageError> {
         let conn = self.conn.lock().unwrap();
 
-        let sql = match pattern {
-            Some(p) if p.ends_with('*') => {
-                let prefix = p.trim_end_matches('*');
-                format!(
-                    "SELECT id FROM documents WHERE id LIKE '{}%' ORDER BY updated_at DESC",
-                    prefix.replace('\'', "''")
-                )
-            }
-            Some(p) if p.starts_with('*') => {
-                let suffix = p.trim_start_matches('*');
-                format!(
-                    "SELECT id FROM documents WHERE id LIKE '%{}' ORDER BY updated_at DESC",
-                    suffix.replace('\'', "''")
-                )
-            }
-            Some(p) => format!(
-                "SELECT id FROM documents WHERE id = '{}' ORDER BY updated_at DESC",
-                p.replace('\'', "''")
-            ),
-            None => "SELECT id FROM documents ORDER BY updated_at DESC".to_string(),
+        let (sql, arg) = match pattern.map(Glob::new) {
+            Some(glob) => match glob.as_literal() {
+                Some(id) => ("SELECT id FROM documents WHERE id = ?1 ORDER BY updated_at DESC", Some(id)),
+                None => ("SELECT id FROM documents WHERE id GLOB ?1 ORDER BY updated_at DESC", Some(to_sqlite_glob(&glob))),
+            },
+            None => ("SELECT id FROM documents ORDER BY updated_at DESC", None),
         };
 
         let mut stmt = conn
-            .prepare(&sql)
+            .prepare(sql)
             .map_err(|e| StorageError::Database(e.to_string()))?;
 
         let ids: Vec<DocumentId> = stmt
-            .query_map([], |row| {
+            .query_map(rusqlite::params_from_iter(arg), |row| {
                 let id: String = row.get(0)?;
                 Ok(id)
             })
@@ -169,6 +306,37 @@ impl Storage for SqliteStorage {
         Ok(ids)
     }
 
+    async fn scan(
+        &self,
+        after: Option<&str>,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<DocumentMeta>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+
+        // NULL bounds disable the id and pattern filters
+        let glob = pattern.map(|p| to_sqlite_glob(&Glob::new(p)));
+        let mut stmt = conn
+            .prepare(
+                "SELECT meta FROM documents
+                 WHERE (?1 IS NULL OR id > ?1) AND (?2 IS NULL OR id GLOB ?2)
+                 ORDER BY id LIMIT ?3",
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        let rows: Vec<Vec<u8>> = stmt
+            .query_map(params![after, glob, count as i64], |row| row.get(0))
+            .map_err(|e| StorageError::Database(e.to_string()))?
+            .collect::<Result<_, _>>()
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
+        rows.iter()
+            .map(|meta| {
+                serde_json::from_slice(meta).map_err(|e| StorageError::Serialization(e.to_string()))
+            })
+            .collect()
+    }
+
     async fn exists(&self, id: &DocumentId) -> Result<bool, StorageError> {
         let conn = self.conn.lock().unwrap();
 
@@ -198,13 +366,82 @@ impl Storage for SqliteStorage {
             )
             .map_err(|e| StorageError::Database(e.to_string()))?;
 
+        let (log_entries, log_size): (usize, usize) = conn
+            .query_row(
+                "SELECT COUNT(*), COALESCE(SUM(LENGTH(entry)), 0) FROM document_log",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+
         Ok(StorageStats {
             document_count,
-            total_size_bytes: total_size,
+            total_size_bytes: total_size + log_size,
+            log_entries,
         })
     }
 }
 
+/// A document's update log as `(seq, entry)`, in replay order
+fn log_entries(conn: &Connection, id: &DocumentId) -> Result<Vec<(i64, Vec<u8>)>, StorageError> {
+    let mut stmt = conn
+        .prepare("SELECT seq, entry FROM document_log WHERE id = ?1 ORDER BY version, seq")
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+
+    let entries = stmt
+        .query_map(params![id.as_str()], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| StorageError::Database(e.to_string()))?
+        .collect::<Result<_, _>>()
+        .map_err(|e| StorageError::Database(e.to_string()))?;
+    Ok(entries)
+}
+
+/// Translate a glob into SQLite `GLOB` syntax
+///
+/// `GLOB` already has `*`, `?` and `[...]`; it has no escape character, so
+/// literal wildcards are written as one-character classes.
+fn to_sqlite_glob(glob: &Glob) -> String {
+    let mut out = String::new();
+    for token in glob.tokens() {
+        match token {
+            Token::Literal(c @ ('*' | '?' | '[')) => {
+                out.push('[');
+                out.push(*c);
+                out.push(']');
+            }
+            Token::Literal(c) => out.push(*c),
+            Token::One => out.push('?'),
+            Token::Any => out.push('*'),
+            Token::Class { negated, ranges } => {
+                out.push('[');
+                if *negated {
+                    out.push('^');
+                }
+                // `]` is only a member first and `-` only last
+                if ranges.contains(&(']', ']')) {
+                    out.push(']');
+                }
+                for &(lo, hi) in ranges {
+                    match (lo, hi) {
+                        (']', ']') | ('-', '-') => {}
+                        (lo, hi) if lo == hi => out.push(lo),
+                        (lo, hi) => {
+                            out.push(lo);
+                            out.push('-');
+                            out.push(hi);
+                        }
+                    }
+                }
+                if ranges.contains(&('-', '-')) {
+                    out.push('-');
+                }
+                out.push(']');
+            }
+        }
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -257,10 +494,43 @@ mod tests {
         let carts = storage.list(Some("cart:*")).await.unwrap();
         assert_eq!(carts.len(), 3);
 
+        assert_eq!(storage.list(Some("*:[0-2]")).await.unwrap().len(), 6);
+        assert_eq!(storage.list(Some("?ser:?")).await.unwrap().len(), 5);
+        assert_eq!(storage.list(Some("user:[!0-3]")).await.unwrap().len(), 1);
+        assert_eq!(storage.list(Some("cart:1")).await.unwrap().len(), 1);
+        assert!(storage.list(Some("user:'*")).await.unwrap().is_empty());
+
         let all = storage.list(None).await.unwrap();
         assert_eq!(all.len(), 8);
     }
 
+    #[tokio::test]
+    async fn test_sqlite_scan() {
+        let storage = SqliteStorage::in_memory().unwrap();
+        for i in 0..25 {
+            let id = DocumentId::new(format!("doc:{:02}", i)).unwrap();
+            let meta = DocumentMeta::new(id.clone(), Strategy::Lww);
+            storage.store(&id, &meta, b"data").await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let page = storage.scan(after.as_deref(), Some("doc:?[05]"), 2).await.unwrap();
+            let Some(last) = page.last() else { break };
+            after = Some(last.id.as_str().to_string());
+            seen.extend(page.into_iter().map(|m| m.id.as_str().to_string()));
+        }
+        assert_eq!(seen, ["doc:00", "doc:05", "doc:10", "doc:15", "doc:20"]);
+    }
+
+    #[test]
+    fn test_sqlite_glob_translation() {
+        assert_eq!(to_sqlite_glob(&Glob::new("tenant:*:user:?")), "tenant:*:user:?");
+        assert_eq!(to_sqlite_glob(&Glob::new("a\\*[!x-z]")), "a[*][^x-z]");
+        assert_eq!(to_sqlite_glob(&Glob::new("[-a]]")), "[a-]]");
+    }
+
     #[tokio::test]
     async fn test_sqlite_upsert() {
         let storage = SqliteStorage::in_memory().unwrap();
@@ -281,4 +551,52 @@ mod tests {
         let stats = storage.stats().await.unwrap();
         assert_eq!(stats.document_count, 1);
     }
+
+    #[tokio::test]
+    async fn test_sqlite_update_log() {
+        let storage = SqliteStorage::in_memory().unwrap().with_update_log();
+        let id = DocumentId::new("test:log").unwrap();
+        let doc = ussl_core::Document::new(id.clone(), Strategy::CrdtMap);
+
+        for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+            doc.set(name, ussl_core::Value::String(i.to_string())).unwrap();
+            let delta = doc.take_delta(Some(name.to_string()));
+            storage.append(&id, &doc.meta(), &doc.changes(&delta).unwrap()).await.unwrap();
+        }
+        assert_eq!(storage.stats().await.unwrap().log_entries, 3);
+
+        // Loading replays the log onto the (empty) snapshot
+        let (meta, data) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(meta.version, doc.version());
+        let loaded = ussl_core::Document::from_snapshot(meta, &data).unwrap();
+        assert_eq!(loaded.get(None).unwrap(), doc.get(None).unwrap());
+
+        assert_eq!(storage.fold(&id).await.unwrap(), 3);
+        assert!(storage.logged(1).await.unwrap().is_empty());
+        let (_, folded) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(folded, data);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_stale_writes() {
+        let storage = SqliteStorage::in_memory().unwrap().with_update_log();
+        let id = DocumentId::new("test:stale").unwrap();
+        let doc = ussl_core::Document::new(id.clone(), Strategy::Lww);
+
+        doc.set("a", ussl_core::Value::from(1i64)).unwrap();
+        let (old_meta, old_data) = (doc.meta(), doc.encode_snapshot());
+        doc.set("a", ussl_core::Value::from(2i64)).unwrap();
+        let delta = doc.take_delta(Some("a".to_string()));
+        storage.append(&id, &doc.meta(), &doc.changes(&delta).unwrap()).await.unwrap();
+
+        // A snapshot older than the log neither wins nor drops newer entries
+        storage.store(&id, &old_meta, &old_data).await.unwrap();
+        storage.append(&id, &old_meta, &[]).await.unwrap();
+        assert_eq!(storage.stats().await.unwrap().log_entries, 1);
+
+        let (meta, data) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(meta.version, doc.version());
+        let loaded = ussl_core::Document::from_snapshot(meta, &data).unwrap();
+        assert_eq!(loaded.get(Some("a")).unwrap(), ussl_core::Value::from(2i64));
+    }
 }
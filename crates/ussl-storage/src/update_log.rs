@@ -0,0 +1,132 @@
+//! Update log persistence - append changes, fold them into snapshots later
+//!
+//! Rewriting a document's full state on every write costs as much as the
+//! document is large. In update-log mode each write instead appends the
+//! Y.js update or LWW value it produced (see `Document::changes`) to a
+//! per-document log. Loading replays the log on top of the last snapshot,
+//! and `fold_logs`, run periodically, writes the replayed state back as the
+//! new snapshot and truncates the log.
+
+use crate::{Storage, StorageError};
+use tracing::{debug, warn};
+use ussl_core::{Change, Document, DocumentMeta};
+
+/// Outcome of a fold run
+#[derive(Debug, Clone, Default)]
+pub struct FoldStats {
+    /// Documents whose log was folded
+    pub documents: usize,
+    /// Log entries folded into snapshots
+    pub entries: usize,
+    /// Documents that could not be folded
+    pub failed: usize,
+}
+
+/// Fold the update log of every document with at least `min_entries`
+/// entries into its snapshot
+///
+/// Failures on individual documents are logged and counted; their log is
+/// kept and retried on the next run. Storage errors on listing abort the run.
+pub async fn fold_logs(storage: &dyn Storage, min_entries: usize) -> Result<FoldStats, StorageError> {
+    let mut stats = FoldStats::default();
+
+    for id in storage.logged(min_entries.max(1)).await? {
+        match storage.fold(&id).await {
+            Ok(0) => {}
+            Ok(entries) => {
+                debug!(doc_id = %id, entries, "Folded update log");
+                stats.documents += 1;
+                stats.entries += entries;
+            }
+            Err(e) => {
+                warn!(doc_id = %id, error = %e, "Failed to fold update log");
+                stats.failed += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Replay encoded log entries onto a snapshot, returning the new snapshot
+///
+/// `entries` must be in log order: by version, then by arrival.
+pub(crate) fn replay(meta: &DocumentMeta, snapshot: &[u8], entries: &[Vec<u8>]) -> Result<Vec<u8>, StorageError> {
+    if entries.is_empty() {
+        return Ok(snapshot.to_vec());
+    }
+
+    let changes = entries
+        .iter()
+        .map(|entry| Change::decode(entry))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    let doc = Document::replay(meta.clone(), snapshot, &changes)
+        .map_err(|e| StorageError::Serialization(e.to_string()))?;
+    Ok(doc.encode_snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+    use ussl_core::{DocumentId, Strategy, Value};
+
+    /// Append the changes of `doc`'s latest write at `path`
+    async fn log(storage: &MemoryStorage, doc: &Document, path: &str) {
+        let delta = doc.take_delta(Some(path.to_string()));
+        let changes = doc.changes(&delta).unwrap();
+        storage.append(&doc.id(), &doc.meta(), &changes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_replays_and_fold_truncates() {
+        let storage = MemoryStorage::new().with_update_log();
+        let id = DocumentId::new("doc:log").unwrap();
+        let doc = Document::new(id.clone(), Strategy::Lww);
+
+        doc.set("title", Value::String("draft".into())).unwrap();
+        log(&storage, &doc, "title").await;
+        doc.push("tags", Value::String("a".into())).unwrap();
+        log(&storage, &doc, "tags").await;
+        doc.set("title", Value::String("final".into())).unwrap();
+        log(&storage, &doc, "title").await;
+
+        let (meta, snapshot) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(meta.version, doc.version());
+        let loaded = Document::from_snapshot(meta, &snapshot).unwrap();
+        assert_eq!(loaded.get(None).unwrap(), doc.get(None).unwrap());
+
+        assert_eq!(storage.logged(3).await.unwrap(), vec![id.clone()]);
+        assert!(storage.logged(4).await.unwrap().is_empty());
+
+        let stats = fold_logs(&storage, 1).await.unwrap();
+        assert_eq!((stats.documents, stats.entries, stats.failed), (1, 3, 0));
+        assert_eq!(storage.stats().await.unwrap().log_entries, 0);
+
+        let (meta, snapshot) = storage.load(&id).await.unwrap().unwrap();
+        let folded = Document::from_snapshot(meta, &snapshot).unwrap();
+        assert_eq!(folded.get(None).unwrap(), doc.get(None).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_store_clears_log() {
+        let storage = MemoryStorage::new().with_update_log();
+        let id = DocumentId::new("doc:store").unwrap();
+        let doc = Document::new(id.clone(), Strategy::CrdtCounter);
+
+        doc.increment("hits", 2).unwrap();
+        log(&storage, &doc, "hits").await;
+        storage.store(&id, &doc.meta(), &doc.encode_snapshot()).await.unwrap();
+        assert!(storage.logged(1).await.unwrap().is_empty());
+
+        doc.increment("hits", 3).unwrap();
+        log(&storage, &doc, "hits").await;
+        let (meta, snapshot) = storage.load(&id).await.unwrap().unwrap();
+        let loaded = Document::from_snapshot(meta, &snapshot).unwrap();
+        assert_eq!(loaded.get(Some("hits")).unwrap().as_i64(), Some(5));
+
+        assert!(storage.delete(&id).await.unwrap());
+        assert!(storage.logged(1).await.unwrap().is_empty());
+    }
+}
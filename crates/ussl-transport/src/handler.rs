@@ -1,13 +1,79 @@
 //! Connection handler - processes commands and manages subscriptions
 
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc};
 use tracing::{debug, info, warn};
-use ussl_core::{Backup, DocumentId, DocumentManager, Strategy, Value};
-use ussl_protocol::{Command, CommandKind, Parser, Response};
+use ussl_core::backup::{self, BackupHeader, RestoreStream};
+use ussl_core::crdt::is_within;
+use ussl_core::manager::Delta;
+use ussl_core::pattern;
+use ussl_core::{
+    Backup, DocumentId, DocumentManager, DocumentMeta, EventKind, Strategy, SubscriberId, Value,
+};
+use ussl_protocol::{
+    base64, BackupTarget, Command, CommandKind, GetFormat, Parser, Response, RestoreSource,
+};
 use ussl_storage::Storage;
+use crate::persist::Persister;
 use crate::rate_limit::{RateLimiter, RateLimitConfig};
 
+/// Slack subtracted from the last delivery time when looking for documents
+/// changed during a lag, since updates are not published in `updated_at` order
+const LAG_SLACK_MS: u64 = 1000;
+
+/// Documents examined per `SCAN` page when no `COUNT` is given
+const SCAN_DEFAULT_COUNT: usize = 10;
+
+/// Upper bound on `SCAN ... COUNT`, to keep each reply small
+const SCAN_MAX_COUNT: usize = 1000;
+
+/// Documents encoded per blocking task while streaming a backup
+const BACKUP_STREAM_CHUNK: usize = 64;
+
+/// Replies a background job can queue before it waits for the connection
+const JOB_BUFFER: usize = 16;
+
+/// Longest `RESTORE STREAM` record: a document at the size limit, base64
+/// encoded, plus its metadata
+const MAX_RESTORE_RECORD: usize = ussl_core::document::MAX_DOCUMENT_SIZE / 3 * 4 + 1024 * 1024;
+
+/// A command running in the background, such as `BACKUP TO`
+///
+/// Its replies arrive on `replies`, which closes when it is done.
+struct Job {
+    replies: mpsc::Receiver<Response>,
+    /// Whether the replies form one frame, so nothing may be sent in between
+    streaming: bool,
+}
+
+/// State of a `RESTORE STREAM` waiting for its closing `END`
+#[derive(Default)]
+struct StreamedRestore {
+    stream: RestoreStream,
+    /// First failure; later records are skipped and it is reported at `END`
+    error: Option<String>,
+}
+
+/// Documents matching `pattern`, optionally narrowed to the subtree at `path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Subscription {
+    pattern: String,
+    path: Option<String>,
+}
+
+impl std::fmt::Display for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.path {
+            Some(ref path) => write!(f, "{} PATH {}", self.pattern, path),
+            None => write!(f, "{}", self.pattern),
+        }
+    }
+}
+
 /// Handles a single client connection
 pub struct ConnectionHandler {
     /// Unique client ID
@@ -16,8 +82,16 @@ pub struct ConnectionHandler {
     manager: Arc<DocumentManager>,
     /// Protocol parser
     parser: Parser,
-    /// Active subscriptions (patterns)
-    subscriptions: Vec<String>,
+    /// Active subscriptions
+    subscriptions: Vec<Subscription>,
+    /// This connection's entry in the manager's subscription registry
+    subscriber: SubscriberId,
+    /// Frames to send after the response to the current command
+    replay: Vec<Response>,
+    /// Strategy and latest version of each document this client was sent
+    known: HashMap<String, (Strategy, u64)>,
+    /// When the last delta reached this connection (ms since epoch)
+    last_delivery: u64,
     /// Whether auth is required
     require_auth: bool,
     /// Whether client is authenticated
@@ -25,44 +99,71 @@ pub struct ConnectionHandler {
     /// Server password (if auth required)
     password: Option<String>,
     /// Optional persistent storage
-    storage: Option<Arc<dyn Storage>>,
+    persister: Option<Arc<Persister>>,
     /// Optional rate limiter
     rate_limiter: Option<RateLimiter>,
+    /// Directory `BACKUP TO` and `RESTORE FROM` are confined to
+    backup_dir: Option<PathBuf>,
+    /// Command running in the background; input waits until it is done
+    job: Option<Job>,
+    /// `RESTORE STREAM` in progress: input lines are backup records
+    restoring: Option<StreamedRestore>,
 }
 
 impl ConnectionHandler {
     pub fn new(client_id: String, manager: Arc<DocumentManager>) -> Self {
+        let subscriber = manager.subscriptions().register();
         Self {
             client_id,
             manager,
             parser: Parser::new(),
             subscriptions: Vec::new(),
+            subscriber,
+            replay: Vec::new(),
+            known: HashMap::new(),
+            last_delivery: now_ms(),
             require_auth: false,
             authenticated: true, // No auth required by default
             password: None,
-            storage: None,
+            persister: None,
             rate_limiter: None,
+            backup_dir: None,
+            job: None,
+            restoring: None,
         }
     }
 
     /// Create a new handler with authentication required
     pub fn with_auth(client_id: String, manager: Arc<DocumentManager>, password: String) -> Self {
+        let subscriber = manager.subscriptions().register();
         Self {
             client_id,
             manager,
             parser: Parser::new(),
             subscriptions: Vec::new(),
+            subscriber,
+            replay: Vec::new(),
+            known: HashMap::new(),
+            last_delivery: now_ms(),
             require_auth: true,
             authenticated: false,
             password: Some(password),
-            storage: None,
+            persister: None,
             rate_limiter: None,
+            backup_dir: None,
+            job: None,
+            restoring: None,
         }
     }
 
     /// Set the storage backend for persistence
-    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
-        self.storage = Some(storage);
+    pub fn with_storage(self, storage: Arc<dyn Storage>) -> Self {
+        self.with_persister(Arc::new(Persister::new(storage)))
+    }
+
+    /// Persist through `persister`, shared with other connections
+    pub fn with_persister(mut self, persister: Arc<Persister>) -> Self {
+        self.persister = Some(persister);
         self
     }
 
@@ -72,7 +173,16 @@ impl ConnectionHandler {
         self
     }
 
+    /// Allow `BACKUP TO` and `RESTORE FROM` on files inside `dir`
+    pub fn with_backup_dir(mut self, dir: PathBuf) -> Self {
+        self.backup_dir = Some(dir);
+        self
+    }
+
     /// Process incoming data and return responses
+    ///
+    /// Commands after one that starts a background job stay buffered until
+    /// the job is done; see [`Self::next_reply`].
     pub fn process(&mut self, data: &[u8]) -> Vec<Response> {
         let mut responses = Vec::new();
 
@@ -80,8 +190,19 @@ impl ConnectionHandler {
             responses.push(Response::error("PARSE_ERROR", e.to_string()));
             return responses;
         }
+        if self.job.is_some() {
+            return responses;
+        }
 
         loop {
+            if self.restoring.is_some() {
+                match self.parser.next_line() {
+                    Some(line) => responses.extend(self.restore_line(&line)),
+                    None => break,
+                }
+                continue;
+            }
+
             match self.parser.parse() {
                 Ok(Some(cmd)) => {
                     // Check rate limit (skip for PING/QUIT)
@@ -98,8 +219,11 @@ impl ConnectionHandler {
                         }
                     }
 
-                    let response = self.handle_command(cmd);
-                    responses.push(response);
+                    responses.extend(self.handle_command(cmd));
+                    responses.append(&mut self.replay);
+                    if self.job.is_some() {
+                        break;
+                    }
                 }
                 Ok(None) => break, // Need more data
                 Err(e) => {
@@ -112,32 +236,66 @@ impl ConnectionHandler {
         responses
     }
 
+    /// Whether a background job is running; input should not be read meanwhile
+    pub fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Whether the running job is sending a frame that deltas must not interrupt
+    pub fn is_streaming(&self) -> bool {
+        self.job.as_ref().is_some_and(|job| job.streaming)
+    }
+
+    /// Wait for the next replies of the running job
+    ///
+    /// Once the job is done, handles the commands buffered behind it and
+    /// returns their responses. Never completes when no job is running.
+    pub async fn next_reply(&mut self) -> Vec<Response> {
+        let Some(ref mut job) = self.job else {
+            return std::future::pending().await;
+        };
+        match job.replies.recv().await {
+            Some(response) => vec![response],
+            None => {
+                self.job = None;
+                self.process(&[])
+            }
+        }
+    }
+
     /// Handle a single command
-    fn handle_command(&mut self, cmd: Command) -> Response {
+    ///
+    /// Returns `None` when the command replies later, from a background job
+    /// or once the data following it has arrived.
+    fn handle_command(&mut self, cmd: Command) -> Option<Response> {
         debug!(client = %self.client_id, cmd = ?cmd.kind, "Processing command");
 
         // AUTH and PING are always allowed
         match &cmd.kind {
             CommandKind::Auth { password } => {
-                return self.handle_auth(password.clone());
+                return Some(self.handle_auth(password.clone()));
             }
-            CommandKind::Ping => return Response::pong(),
-            CommandKind::Quit => return Response::ok_with_message("Goodbye"),
+            CommandKind::Ping => return Some(Response::pong()),
+            CommandKind::Quit => return Some(Response::ok_with_message("Goodbye")),
             _ => {}
         }
 
         // Check authentication for all other commands
         if self.require_auth && !self.authenticated {
-            return Response::error("NOAUTH", "Authentication required. Use AUTH <password>");
+            return Some(Response::error("NOAUTH", "Authentication required. Use AUTH <password>"));
         }
 
-        match cmd.kind {
+        // Hold off BACKUP until this command's changes are complete
+        let manager = self.manager.clone();
+        let _barrier = changes_documents(&cmd.kind).then(|| manager.write_barrier());
+
+        let response = match cmd.kind {
             CommandKind::Auth { .. } => unreachable!(), // Handled above
             CommandKind::Create { strategy, ttl } => {
                 self.handle_create(cmd.document_id, strategy, ttl)
             }
-            CommandKind::Get { path } => {
-                self.handle_get(cmd.document_id, path)
+            CommandKind::Get { path, format } => {
+                self.handle_get(cmd.document_id, path, format)
             }
             CommandKind::Set { path, value } => {
                 self.handle_set(cmd.document_id, path, value)
@@ -145,8 +303,8 @@ impl ConnectionHandler {
             CommandKind::Delete { path } => {
                 self.handle_delete(cmd.document_id, path)
             }
-            CommandKind::Subscribe { pattern, path } => {
-                self.handle_subscribe(pattern, path)
+            CommandKind::Subscribe { pattern, path, from } => {
+                self.handle_subscribe(pattern, path, from)
             }
             CommandKind::Unsubscribe { pattern } => {
                 self.handle_unsubscribe(pattern)
@@ -157,6 +315,45 @@ impl ConnectionHandler {
             CommandKind::Increment { path, delta } => {
                 self.handle_increment(cmd.document_id, path, delta)
             }
+            CommandKind::ListInsert { path, index, value } => {
+                self.handle_linsert(cmd.document_id, path, index, value)
+            }
+            CommandKind::ListRemove { path, index, count } => {
+                self.handle_lremove(cmd.document_id, path, index, count)
+            }
+            CommandKind::ListMove { path, from, to } => {
+                self.handle_lmove(cmd.document_id, path, from, to)
+            }
+            CommandKind::ListRange { path, start, stop } => {
+                self.handle_lrange(cmd.document_id, path, start, stop)
+            }
+            CommandKind::SetAdd { path, value } => {
+                self.handle_sadd(cmd.document_id, path, value)
+            }
+            CommandKind::SetRemove { path, value } => {
+                self.handle_srem(cmd.document_id, path, value)
+            }
+            CommandKind::SetIsMember { path, value } => {
+                self.handle_sismember(cmd.document_id, path, value)
+            }
+            CommandKind::SetMembers { path } => {
+                self.handle_smembers(cmd.document_id, path)
+            }
+            CommandKind::TextInsert { index, text } => {
+                self.handle_text_insert(cmd.document_id, index, text)
+            }
+            CommandKind::TextDelete { index, len } => {
+                self.handle_text_delete(cmd.document_id, index, len)
+            }
+            CommandKind::TextFormat { index, len, attributes } => {
+                self.handle_text_format(cmd.document_id, index, len, attributes)
+            }
+            CommandKind::TextLen => self.handle_text_len(cmd.document_id),
+            CommandKind::Sync1 { state_vector } => self.handle_sync1(cmd.document_id, state_vector),
+            CommandKind::Sync2 { update } | CommandKind::Apply { update } => {
+                self.handle_apply(cmd.document_id, update)
+            }
+            CommandKind::State => self.handle_state(cmd.document_id),
             CommandKind::Presence { data } => {
                 self.handle_presence(cmd.document_id, data)
             }
@@ -164,12 +361,17 @@ impl ConnectionHandler {
             CommandKind::Quit => unreachable!(), // Handled above
             CommandKind::Info => self.handle_info(),
             CommandKind::Keys { pattern } => self.handle_keys(pattern),
+            CommandKind::Scan { cursor, pattern, count, strategy } => {
+                self.handle_scan(cursor, pattern, count, strategy)
+            }
             CommandKind::Compact => self.handle_compact(cmd.document_id),
             CommandKind::Expire { ttl_ms } => self.handle_expire(cmd.document_id, ttl_ms),
             CommandKind::Ttl => self.handle_ttl(cmd.document_id),
-            CommandKind::Backup => self.handle_backup(),
-            CommandKind::Restore { data } => self.handle_restore(data),
-        }
+            CommandKind::Backup { target } => return self.handle_backup(target),
+            CommandKind::Restore { source } => return self.handle_restore(source),
+            CommandKind::LastSave => self.handle_lastsave(),
+        };
+        Some(response)
     }
 
     fn handle_auth(&mut self, password: String) -> Response {
@@ -212,7 +414,7 @@ impl ConnectionHandler {
         }
     }
 
-    fn handle_get(&self, doc_id: Option<String>, path: Option<String>) -> Response {
+    fn handle_get(&self, doc_id: Option<String>, path: Option<String>, format: GetFormat) -> Response {
         let id_str = match doc_id {
             Some(id) => id,
             None => return Response::error("MISSING_ARG", "Document ID required"),
@@ -224,6 +426,12 @@ impl ConnectionHandler {
         };
 
         match self.manager.get(&id) {
+            Ok(doc) if format == GetFormat::Delta => {
+                match doc.text_delta() {
+                    Ok(delta) => Response::value(delta),
+                    Err(e) => Response::error("GET_ERROR", e.to_string()),
+                }
+            }
             Ok(doc) => {
                 match doc.get(path.as_deref()) {
                     Ok(value) => Response::value(value),
@@ -246,24 +454,18 @@ impl ConnectionHandler {
         };
 
         // Get or create document with default strategy
-        let doc = self.manager.get_or_create(id.clone(), Strategy::default());
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::default()) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
 
         match doc.set(&path, value) {
             Ok(_) => {
                 // Check if auto-compaction is needed
                 self.maybe_auto_compact(&id, &doc);
 
-                // Persist to storage if available
-                self.persist_document(&id, &doc);
-
-                // Publish update to subscribers
-                let delta = ussl_core::manager::Delta {
-                    document_id: id,
-                    version: doc.version(),
-                    path: Some(path),
-                    data: doc.encode_state(),
-                };
-                self.manager.publish_update(delta);
+                // Persist and publish to subscribers
+                self.commit(&id, &doc, Some(path));
                 Response::ok()
             }
             Err(e) => Response::error("SET_ERROR", e.to_string()),
@@ -287,7 +489,10 @@ impl ConnectionHandler {
                 match self.manager.get(&id) {
                     Ok(doc) => {
                         match doc.delete(Some(&p)) {
-                            Ok(_) => Response::ok(),
+                            Ok(_) => {
+                                self.commit(&id, &doc, Some(p));
+                                Response::ok()
+                            }
                             Err(e) => Response::error("DELETE_ERROR", e.to_string()),
                         }
                     }
@@ -297,22 +502,72 @@ impl ConnectionHandler {
             None => {
                 // Delete entire document
                 match self.manager.delete(&id) {
-                    Ok(_) => Response::ok(),
+                    Ok(_) => {
+                        self.unpersist_document(&id);
+                        Response::ok()
+                    }
                     Err(e) => Response::error("DELETE_ERROR", e.to_string()),
                 }
             }
         }
     }
 
-    fn handle_subscribe(&mut self, pattern: String, _path: Option<String>) -> Response {
-        if !self.subscriptions.contains(&pattern) {
-            self.subscriptions.push(pattern.clone());
+    fn handle_subscribe(&mut self, pattern: String, path: Option<String>, from: Option<u64>) -> Response {
+        let subscription = Subscription { pattern, path };
+        let message = format!("Subscribed to {}", subscription);
+        if !self.subscriptions.contains(&subscription) {
+            self.manager.subscriptions().add(self.subscriber, &subscription.pattern);
+            self.subscriptions.push(subscription.clone());
         }
-        Response::ok_with_message(format!("Subscribed to {}", pattern))
+        if let Some(version) = from {
+            self.replay = self.missed(&subscription, version);
+        }
+        Response::ok_with_message(message)
+    }
+
+    /// Frames catching `sub` up on changes made after `version`
+    ///
+    /// Whole-document subscriptions get every logged delta, or a `resync`
+    /// snapshot if the document's log no longer reaches back that far. Path
+    /// subscriptions get the current value once if any of them touched it.
+    fn missed(&mut self, sub: &Subscription, version: u64) -> Vec<Response> {
+        // Unchanged documents that are only on disk stay there
+        let ids = match self.manager.changed_since(&sub.pattern, version) {
+            Ok(ids) => ids,
+            Err(e) => {
+                warn!(client = %self.client_id, error = %e, "Failed to list documents for replay");
+                return Vec::new();
+            }
+        };
+
+        let mut frames = Vec::new();
+        for id in ids {
+            let Ok(doc) = self.manager.get(&id) else {
+                continue;
+            };
+            let deltas = doc.deltas_since(version);
+            let Some(last) = deltas.last().cloned() else {
+                continue;
+            };
+            for delta in &deltas {
+                self.remember(delta);
+            }
+            match sub.path.as_deref() {
+                None => frames.extend(deltas.into_iter().map(Response::delta)),
+                Some(path) if deltas.iter().any(|d| touches(d.path.as_deref(), path)) => {
+                    let value = doc.get(Some(path)).unwrap_or(Value::Null);
+                    frames.push(Response::path_value(&last, path, &value));
+                }
+                Some(_) => {}
+            }
+        }
+        frames
     }
 
+    /// Drop every subscription to `pattern`, whatever its path
     fn handle_unsubscribe(&mut self, pattern: String) -> Response {
-        self.subscriptions.retain(|p| p != &pattern);
+        self.subscriptions.retain(|s| s.pattern != pattern);
+        self.manager.subscriptions().remove(self.subscriber, &pattern);
         Response::ok_with_message(format!("Unsubscribed from {}", pattern))
     }
 
@@ -327,14 +582,17 @@ impl ConnectionHandler {
             Err(e) => return Response::error("INVALID_ID", e.to_string()),
         };
 
-        let doc = self.manager.get_or_create(id.clone(), Strategy::default());
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::default()) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
 
         match doc.push(&path, value) {
             Ok(_) => {
                 // Check if auto-compaction is needed
                 self.maybe_auto_compact(&id, &doc);
 
-                self.persist_document(&id, &doc);
+                self.commit(&id, &doc, Some(path));
                 Response::ok()
             }
             Err(e) => Response::error("PUSH_ERROR", e.to_string()),
@@ -352,20 +610,409 @@ impl ConnectionHandler {
             Err(e) => return Response::error("INVALID_ID", e.to_string()),
         };
 
-        let doc = self.manager.get_or_create(id.clone(), Strategy::CrdtCounter);
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::CrdtCounter) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
 
         match doc.increment(&path, delta) {
             Ok(new_value) => {
                 // Check if auto-compaction is needed
                 self.maybe_auto_compact(&id, &doc);
 
-                self.persist_document(&id, &doc);
+                self.commit(&id, &doc, Some(path));
                 Response::integer(new_value)
             }
             Err(e) => Response::error("INC_ERROR", e.to_string()),
         }
     }
 
+    fn handle_linsert(&self, doc_id: Option<String>, path: String, index: u32, value: Value) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::default()) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
+
+        match doc.list_insert(&path, index, value) {
+            Ok(len) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, Some(path));
+                Response::integer(len as i64)
+            }
+            Err(e) => Response::error("LIST_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_lremove(&self, doc_id: Option<String>, path: String, index: u32, count: u32) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.list_remove(&path, index, count) {
+            Ok(removed) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, Some(path));
+                Response::integer(removed as i64)
+            }
+            Err(e) => Response::error("LIST_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_lmove(&self, doc_id: Option<String>, path: String, from: u32, to: u32) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.list_move(&path, from, to) {
+            Ok(()) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, Some(path));
+                Response::ok()
+            }
+            Err(e) => Response::error("LIST_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_lrange(&self, doc_id: Option<String>, path: String, start: i64, stop: i64) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::array(Vec::new()),
+        };
+
+        match doc.list_range(&path, start, stop) {
+            Ok(items) => Response::array(items.into_iter().map(Response::value).collect()),
+            Err(e) => Response::error("LIST_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_sadd(&self, doc_id: Option<String>, path: String, value: Value) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::CrdtSet) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
+
+        match doc.set_add(&path, value) {
+            Ok(added) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, Some(path));
+                Response::integer(added as i64)
+            }
+            Err(e) => Response::error("SADD_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_srem(&self, doc_id: Option<String>, path: String, value: Value) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        // Removing from a missing set is a no-op
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::integer(0),
+        };
+
+        match doc.set_remove(&path, &value) {
+            Ok(true) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, Some(path));
+                Response::integer(1)
+            }
+            Ok(false) => Response::integer(0),
+            Err(e) => Response::error("SREM_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_sismember(&self, doc_id: Option<String>, path: String, value: Value) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::integer(0),
+        };
+
+        match doc.set_contains(&path, &value) {
+            Ok(found) => Response::integer(found as i64),
+            Err(e) => Response::error("SISMEMBER_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_smembers(&self, doc_id: Option<String>, path: String) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::array(Vec::new()),
+        };
+
+        match doc.set_members(&path) {
+            Ok(members) => Response::array(members.into_iter().map(Response::value).collect()),
+            Err(e) => Response::error("SMEMBERS_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_text_insert(&self, doc_id: Option<String>, index: u32, text: String) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get_or_create(id.clone(), Strategy::CrdtText) {
+            Ok(doc) => doc,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
+
+        match doc.text_insert(index, &text) {
+            Ok(()) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, None);
+                Response::ok()
+            }
+            Err(e) => Response::error("TEXT_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_text_delete(&self, doc_id: Option<String>, index: u32, len: u32) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.text_delete(index, len) {
+            Ok(()) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, None);
+                Response::ok()
+            }
+            Err(e) => Response::error("TEXT_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_text_format(
+        &self,
+        doc_id: Option<String>,
+        index: u32,
+        len: u32,
+        attributes: std::collections::HashMap<String, Value>,
+    ) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.text_format(index, len, attributes) {
+            Ok(()) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, None);
+                Response::ok()
+            }
+            Err(e) => Response::error("TEXT_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_text_len(&self, doc_id: Option<String>) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.text_len() {
+            Ok(len) => Response::integer(len as i64),
+            Err(e) => Response::error("TEXT_ERROR", e.to_string()),
+        }
+    }
+
+    /// First step of the Yjs sync handshake
+    ///
+    /// Replies with the updates the client's state vector is missing, plus
+    /// the server's own state vector so the client can answer with SYNC2.
+    fn handle_sync1(&self, doc_id: Option<String>, state_vector: Vec<u8>) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.encode_diff(&state_vector) {
+            Ok(update) => Response::array(vec![
+                Response::bulk(base64::encode(&update)),
+                Response::bulk(base64::encode(&doc.state_vector())),
+            ]),
+            Err(e) => Response::error("SYNC_ERROR", e.to_string()),
+        }
+    }
+
+    /// Apply a raw Y.js update (APPLY, and the second step of the sync handshake)
+    fn handle_apply(&self, doc_id: Option<String>, update: Vec<u8>) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        let doc = match self.manager.get(&id) {
+            Ok(doc) => doc,
+            Err(_) => return Response::not_found(&id_str),
+        };
+
+        match doc.apply_update(&update) {
+            Ok(()) => {
+                self.maybe_auto_compact(&id, &doc);
+
+                self.commit(&id, &doc, None);
+                Response::ok()
+            }
+            Err(e) => Response::error("CRDT_ERROR", e.to_string()),
+        }
+    }
+
+    fn handle_state(&self, doc_id: Option<String>) -> Response {
+        let id_str = match doc_id {
+            Some(id) => id,
+            None => return Response::error("MISSING_ARG", "Document ID required"),
+        };
+
+        let id = match DocumentId::new(&id_str) {
+            Ok(id) => id,
+            Err(e) => return Response::error("INVALID_ID", e.to_string()),
+        };
+
+        match self.manager.get(&id) {
+            Ok(doc) => Response::bulk(base64::encode(&doc.encode_state())),
+            Err(_) => Response::not_found(&id_str),
+        }
+    }
+
     fn handle_presence(&self, doc_id: Option<String>, data: Option<serde_json::Value>) -> Response {
         let id_str = match doc_id {
             Some(id) => id,
@@ -397,26 +1044,73 @@ impl ConnectionHandler {
     }
 
     fn handle_info(&self) -> Response {
-        let stats = self.manager.stats();
+        let stats = match self.manager.stats() {
+            Ok(stats) => stats,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
         let info = serde_json::json!({
             "version": env!("CARGO_PKG_VERSION"),
             "documents": stats.document_count,
+            "resident_documents": stats.resident_count,
             "subscribers": stats.subscriber_count,
             "client_id": self.client_id,
-            "subscriptions": self.subscriptions,
+            "subscriptions": self.subscriptions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "last_snapshot": self.manager.last_snapshot(),
         });
         Response::bulk(serde_json::to_vec(&info).unwrap_or_default())
     }
 
+    /// Unix time in seconds of the last successful snapshot, null if none yet
+    fn handle_lastsave(&self) -> Response {
+        match self.manager.last_snapshot() {
+            Some(info) => Response::integer((info.timestamp / 1000) as i64),
+            None => Response::null(),
+        }
+    }
+
     fn handle_keys(&self, pattern: Option<String>) -> Response {
-        let docs = self.manager.list(pattern.as_deref());
-        let keys: Vec<Response> = docs
+        let ids = match self.manager.keys(pattern.as_deref()) {
+            Ok(ids) => ids,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
+        let keys: Vec<Response> = ids
             .into_iter()
-            .map(|meta| Response::bulk(meta.id.as_str().as_bytes().to_vec()))
+            .map(|id| Response::bulk(id.as_str().as_bytes().to_vec()))
             .collect();
         Response::array(keys)
     }
 
+    /// One page of documents; the cursor is opaque to clients, `0` at both ends
+    fn handle_scan(
+        &self,
+        cursor: String,
+        pattern: Option<String>,
+        count: Option<usize>,
+        strategy: Option<Strategy>,
+    ) -> Response {
+        let after = if cursor == "0" {
+            None
+        } else {
+            match base64::decode(&cursor).and_then(|bytes| String::from_utf8(bytes).ok()) {
+                Some(after) => Some(after),
+                None => return Response::error("INVALID_CURSOR", format!("Invalid cursor: {}", cursor)),
+            }
+        };
+        let count = count.unwrap_or(SCAN_DEFAULT_COUNT).clamp(1, SCAN_MAX_COUNT);
+
+        let page = match self.manager.scan(after.as_deref(), pattern.as_deref(), strategy, count) {
+            Ok(page) => page,
+            Err(e) => return Response::error("STORAGE_ERROR", e.to_string()),
+        };
+        let next = page.cursor.map(|c| base64::encode(c.as_bytes())).unwrap_or_else(|| "0".into());
+        let keys: Vec<Response> = page
+            .ids
+            .into_iter()
+            .map(|id| Response::bulk(id.as_str().as_bytes().to_vec()))
+            .collect();
+        Response::array(vec![Response::bulk(next.into_bytes()), Response::array(keys)])
+    }
+
     fn handle_compact(&self, doc_id: Option<String>) -> Response {
         let id_str = match doc_id {
             Some(id) => id,
@@ -438,6 +1132,8 @@ impl ConnectionHandler {
                             compaction_count = doc.compaction_count(),
                             "Document compacted"
                         );
+                        // Stored in full: logged updates no longer apply to the new Y.Doc
+                        self.commit(&id, &doc, None);
                         Response::integer(bytes_saved as i64)
                     }
                     Err(e) => Response::error("COMPACT_ERROR", e.to_string()),
@@ -461,13 +1157,14 @@ impl ConnectionHandler {
         // TTL of 0 means remove TTL
         let ttl = if ttl_ms == 0 { None } else { Some(ttl_ms) };
 
-        match self.manager.set_expire(&id, ttl) {
-            Ok(_) => {
+        match self.manager.set_expire(&id, ttl).and_then(|_| self.manager.get(&id)) {
+            Ok(doc) => {
                 if ttl.is_some() {
                     info!(doc_id = %id, ttl_ms = ttl_ms, "TTL set");
                 } else {
                     info!(doc_id = %id, "TTL removed");
                 }
+                self.commit(&id, &doc, None);
                 Response::ok()
             }
             Err(e) => Response::error("EXPIRE_ERROR", e.to_string()),
@@ -502,8 +1199,44 @@ impl ConnectionHandler {
         }
     }
 
-    fn handle_backup(&self) -> Response {
-        let backup = self.manager.backup();
+    fn handle_backup(&mut self, target: BackupTarget) -> Option<Response> {
+        match target {
+            BackupTarget::Reply => Some(self.backup_reply()),
+            BackupTarget::File(name) => {
+                let path = match self.backup_path(&name, false) {
+                    Ok(path) => path,
+                    Err(response) => return Some(response),
+                };
+                let manager = self.manager.clone();
+                self.start_job(false, move |replies| async move {
+                    let written = tokio::task::spawn_blocking(move || {
+                        backup::write_file(&path, &manager.backup()?)
+                    })
+                    .await;
+                    let response = match written {
+                        Ok(Ok(count)) => {
+                            info!(documents = count, file = %name, "Backup saved");
+                            Response::ok_with_message(format!("Saved {} documents to {}", count, name))
+                        }
+                        Ok(Err(e)) => Response::error("BACKUP_ERROR", e.to_string()),
+                        Err(e) => Response::error("BACKUP_ERROR", e.to_string()),
+                    };
+                    let _ = replies.send(response).await;
+                });
+                None
+            }
+            BackupTarget::Stream => {
+                self.stream_backup();
+                None
+            }
+        }
+    }
+
+    fn backup_reply(&self) -> Response {
+        let backup = match self.manager.backup() {
+            Ok(backup) => backup,
+            Err(e) => return Response::error("BACKUP_ERROR", e.to_string()),
+        };
         match serde_json::to_vec(&backup) {
             Ok(json) => {
                 info!(
@@ -517,7 +1250,50 @@ impl ConnectionHandler {
         }
     }
 
-    fn handle_restore(&self, data: String) -> Response {
+    fn handle_restore(&mut self, source: RestoreSource) -> Option<Response> {
+        match source {
+            RestoreSource::Inline(data) => Some(self.restore_inline(data)),
+            RestoreSource::File(name) => {
+                let path = match self.backup_path(&name, true) {
+                    Ok(path) => path,
+                    Err(response) => return Some(response),
+                };
+                let manager = self.manager.clone();
+                let persister = self.persister.clone();
+                self.start_job(false, move |replies| async move {
+                    let restored = tokio::task::spawn_blocking({
+                        let manager = manager.clone();
+                        move || backup::read_file(&manager, &path)
+                    })
+                    .await;
+                    let response = match restored {
+                        Ok(Ok(ids)) => {
+                            info!(documents = ids.len(), file = %name, "Restore completed");
+                            if let Some(ref persister) = persister {
+                                for id in &ids {
+                                    if let Ok(doc) = manager.get(id) {
+                                        persister.store(id, &doc);
+                                    }
+                                }
+                            }
+                            Response::integer(ids.len() as i64)
+                        }
+                        Ok(Err(e)) => Response::error("RESTORE_ERROR", e.to_string()),
+                        Err(e) => Response::error("RESTORE_ERROR", e.to_string()),
+                    };
+                    let _ = replies.send(response).await;
+                });
+                None
+            }
+            RestoreSource::Stream => {
+                self.parser.set_max_size(MAX_RESTORE_RECORD);
+                self.restoring = Some(StreamedRestore::default());
+                None
+            }
+        }
+    }
+
+    fn restore_inline(&self, data: String) -> Response {
         let backup: Backup = match serde_json::from_str(&data) {
             Ok(b) => b,
             Err(e) => return Response::error("INVALID_JSON", e.to_string()),
@@ -533,7 +1309,7 @@ impl ConnectionHandler {
                 );
 
                 // Persist all restored documents to storage
-                if self.storage.is_some() {
+                if self.persister.is_some() {
                     for doc_backup in &backup.documents {
                         if let Ok(id) = DocumentId::new(&doc_backup.id) {
                             if let Ok(doc) = self.manager.get(&id) {
@@ -549,6 +1325,120 @@ impl ConnectionHandler {
         }
     }
 
+    /// Send every document as one NDJSON record each, after the header
+    ///
+    /// The reply is an array of bulk strings. The backup is taken and its
+    /// records encoded a chunk at a time off the async runtime, and sent as
+    /// they are ready.
+    fn stream_backup(&mut self) {
+        let manager = self.manager.clone();
+        self.start_job(true, move |replies| async move {
+            let backup = match tokio::task::spawn_blocking(move || manager.backup()).await {
+                Ok(Ok(backup)) => backup,
+                Ok(Err(e)) => {
+                    let _ = replies.send(Response::error("BACKUP_ERROR", e.to_string())).await;
+                    return;
+                }
+                Err(e) => {
+                    let _ = replies.send(Response::error("BACKUP_ERROR", e.to_string())).await;
+                    return;
+                }
+            };
+            let header = BackupHeader::new(&backup);
+            let mut frames = vec![
+                Response::ArrayHeader(header.count + 1),
+                Response::bulk(header.to_record()),
+            ];
+            let mut documents = backup.documents.into_iter();
+            loop {
+                for frame in frames.drain(..) {
+                    if replies.send(frame).await.is_err() {
+                        return; // Connection closed
+                    }
+                }
+                let chunk: Vec<_> = documents.by_ref().take(BACKUP_STREAM_CHUNK).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                let len = chunk.len();
+                let encoded = tokio::task::spawn_blocking(move || {
+                    chunk.iter().map(backup::document_record).collect::<Vec<_>>()
+                })
+                .await;
+                // One item per document even on failure, to keep the array intact
+                frames = match encoded {
+                    Ok(records) => records
+                        .into_iter()
+                        .map(|record| match record {
+                            Ok(record) => Response::bulk(record),
+                            Err(e) => Response::error("BACKUP_ERROR", e.to_string()),
+                        })
+                        .collect(),
+                    Err(e) => vec![Response::error("BACKUP_ERROR", e.to_string()); len],
+                };
+            }
+            info!(documents = header.count, "Backup streamed");
+        });
+    }
+
+    /// Take one line of a `RESTORE STREAM`; replies once `END` arrives
+    fn restore_line(&mut self, line: &str) -> Option<Response> {
+        if line.trim().eq_ignore_ascii_case("END") {
+            let restoring = self.restoring.take()?;
+            self.parser.set_max_size(ussl_protocol::parser::MAX_MESSAGE_SIZE);
+            let result = match restoring.error {
+                Some(e) => Err(e),
+                None => restoring.stream.finish().map_err(|e| e.to_string()),
+            };
+            return Some(match result {
+                Ok(count) => {
+                    info!(documents = count, "Streamed restore completed");
+                    Response::integer(count as i64)
+                }
+                Err(e) => Response::error("RESTORE_ERROR", e),
+            });
+        }
+
+        let restoring = self.restoring.as_mut()?;
+        if restoring.error.is_some() {
+            return None;
+        }
+        match restoring.stream.push_line(&self.manager, line) {
+            Ok(ids) => {
+                for id in ids {
+                    if let Ok(doc) = self.manager.get(&id) {
+                        self.persist_document(&id, &doc);
+                    }
+                }
+            }
+            Err(e) => restoring.error = Some(e.to_string()),
+        }
+        None
+    }
+
+    /// Run `task` in the background; it sends the command's replies and
+    /// drops the sender when done
+    fn start_job<F, Fut>(&mut self, streaming: bool, task: F)
+    where
+        F: FnOnce(mpsc::Sender<Response>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, replies) = mpsc::channel(JOB_BUFFER);
+        tokio::spawn(task(sender));
+        self.job = Some(Job { replies, streaming });
+    }
+
+    /// Resolve a file name given to `BACKUP TO` or `RESTORE FROM`
+    fn backup_path(&self, name: &str, existing: bool) -> Result<PathBuf, Response> {
+        let Some(ref dir) = self.backup_dir else {
+            return Err(Response::error(
+                "BACKUP_DISABLED",
+                "No backup directory configured. Start the server with --backup-dir",
+            ));
+        };
+        resolve_backup_path(dir, name, existing).map_err(|e| Response::error("INVALID_PATH", e))
+    }
+
     /// Check if a document should be compacted and do so automatically
     fn maybe_auto_compact(&self, id: &DocumentId, doc: &ussl_core::Document) {
         if doc.should_compact() {
@@ -569,20 +1459,26 @@ impl ConnectionHandler {
         }
     }
 
+    /// Persist the changes made to a document and publish them
+    fn commit(&self, id: &DocumentId, doc: &ussl_core::Document, path: Option<String>) {
+        let delta = match self.persister {
+            Some(ref persister) => persister.commit(id, doc, path),
+            None => doc.take_delta(path),
+        };
+        self.manager.publish_update(delta);
+    }
+
     /// Persist a document to storage (if available)
     fn persist_document(&self, id: &DocumentId, doc: &ussl_core::Document) {
-        if let Some(ref storage) = self.storage {
-            let meta = doc.meta();
-            let data = doc.encode_state();
-            let storage = storage.clone();
-            let id = id.clone();
-
-            // Spawn async task to persist
-            tokio::spawn(async move {
-                if let Err(e) = storage.store(&id, &meta, &data).await {
-                    warn!(doc_id = %id, error = %e, "Failed to persist document");
-                }
-            });
+        if let Some(ref persister) = self.persister {
+            persister.store(id, doc);
+        }
+    }
+
+    /// Remove a document from storage (if available)
+    fn unpersist_document(&self, id: &DocumentId) {
+        if let Some(ref persister) = self.persister {
+            persister.delete(id);
         }
     }
 
@@ -592,21 +1488,452 @@ impl ConnectionHandler {
     }
 
     /// Get subscription receiver for real-time updates
-    pub fn subscribe_updates(&self) -> broadcast::Receiver<ussl_core::manager::Delta> {
-        self.manager.subscribe()
+    ///
+    /// Only deltas of documents matching this connection's subscriptions
+    /// are delivered to it.
+    pub fn subscribe_updates(&self) -> broadcast::Receiver<Delta> {
+        self.manager
+            .subscriptions()
+            .receiver(self.subscriber)
+            .expect("subscriber is registered for the lifetime of the handler")
     }
 
-    /// Check if a delta matches any of this client's subscriptions
-    pub fn matches_subscription(&self, delta: &ussl_core::manager::Delta) -> bool {
+    /// Frames to send this client for a delta, one per matching subscription
+    ///
+    /// Whole-document subscriptions get the delta itself (once). Path
+    /// subscriptions get the value now at their path, but only if the delta
+    /// may have touched it; deltas without a path touch every path.
+    pub fn notifications(&mut self, delta: Delta) -> Vec<Response> {
+        self.last_delivery = now_ms();
+
         let doc_id = delta.document_id.as_str();
-        self.subscriptions.iter().any(|pattern| {
-            if pattern == "*" {
-                true
-            } else if let Some(prefix) = pattern.strip_suffix('*') {
-                doc_id.starts_with(prefix)
-            } else {
-                doc_id == pattern
+        let mut whole = false;
+        let mut paths: Vec<&str> = Vec::new();
+        let mut matched = false;
+
+        for sub in &self.subscriptions {
+            if !pattern::matches(&sub.pattern, doc_id) {
+                continue;
+            }
+            matched = true;
+            match sub.path.as_deref() {
+                None => whole = true,
+                Some(path) if touches(delta.path.as_deref(), path) && !paths.contains(&path) => {
+                    paths.push(path)
+                }
+                Some(_) => {}
+            }
+        }
+
+        let doc = if paths.is_empty() { None } else { self.manager.get(&delta.document_id).ok() };
+        let mut frames: Vec<Response> = paths
+            .into_iter()
+            .map(|path| {
+                let value = doc
+                    .as_ref()
+                    .and_then(|d| d.get(Some(path)).ok())
+                    .unwrap_or(Value::Null);
+                Response::path_value(&delta, path, &value)
+            })
+            .collect();
+
+        if matched {
+            self.remember(&delta);
+        }
+        if whole {
+            frames.insert(0, Response::delta(delta));
+        }
+        frames
+    }
+
+    /// Frames that bring a lagging client back in sync
+    ///
+    /// Called after the update channel dropped deltas. Documents the client
+    /// has heard of are resent in full if their version moved, or reported
+    /// deleted if they are gone. Other subscribed documents are resent if
+    /// they changed since shortly before the last delta that got through.
+    pub fn recover(&mut self) -> Vec<Response> {
+        let since = self.last_delivery.saturating_sub(LAG_SLACK_MS);
+        let mut deltas = Vec::new();
+
+        for (key, &(strategy, version)) in &self.known {
+            let Ok(id) = DocumentId::new(key) else {
+                continue;
+            };
+            match self.manager.get(&id) {
+                Ok(doc) if doc.version() != version => deltas.push(doc.full_delta(EventKind::Resync)),
+                Ok(_) => {}
+                Err(_) => {
+                    let mut meta = DocumentMeta::new(id, strategy);
+                    meta.version = version;
+                    deltas.push(Delta::removed(&meta, EventKind::Deleted));
+                }
+            }
+        }
+
+        let mut patterns: Vec<&str> = self.subscriptions.iter().map(|s| s.pattern.as_str()).collect();
+        patterns.sort_unstable();
+        patterns.dedup();
+        let mut changed: Vec<DocumentMeta> = patterns
+            .into_iter()
+            .flat_map(|pattern| self.manager.list(Some(pattern)))
+            .filter(|meta| meta.updated_at >= since && !self.known.contains_key(meta.id.as_str()))
+            .collect();
+        changed.sort_by(|a, b| a.id.as_str().cmp(b.id.as_str()));
+        changed.dedup_by(|a, b| a.id == b.id);
+        for meta in changed {
+            if let Ok(doc) = self.manager.get(&meta.id) {
+                deltas.push(doc.full_delta(EventKind::Resync));
+            }
+        }
+
+        deltas.into_iter().flat_map(|delta| self.notifications(delta)).collect()
+    }
+
+    /// Track the latest version of a document this client was told about
+    fn remember(&mut self, delta: &Delta) {
+        let key = delta.document_id.as_str();
+        if matches!(delta.event, EventKind::Deleted | EventKind::Expired) {
+            self.known.remove(key);
+            return;
+        }
+        let entry = self.known.entry(key.to_string()).or_insert((delta.strategy, 0));
+        *entry = (delta.strategy, entry.1.max(delta.version));
+    }
+}
+
+impl Drop for ConnectionHandler {
+    fn drop(&mut self) {
+        self.manager.subscriptions().unregister(self.subscriber);
+    }
+}
+
+/// Whether `kind` modifies documents and must wait for a running `BACKUP`
+fn changes_documents(kind: &CommandKind) -> bool {
+    // RESTORE is left out: the manager holds the barrier while it restores
+    matches!(
+        kind,
+        CommandKind::Create { .. }
+            | CommandKind::Set { .. }
+            | CommandKind::Delete { .. }
+            | CommandKind::Push { .. }
+            | CommandKind::Increment { .. }
+            | CommandKind::ListInsert { .. }
+            | CommandKind::ListRemove { .. }
+            | CommandKind::ListMove { .. }
+            | CommandKind::SetAdd { .. }
+            | CommandKind::SetRemove { .. }
+            | CommandKind::TextInsert { .. }
+            | CommandKind::TextDelete { .. }
+            | CommandKind::TextFormat { .. }
+            | CommandKind::Sync2 { .. }
+            | CommandKind::Apply { .. }
+            | CommandKind::Compact
+            | CommandKind::Expire { .. }
+    )
+}
+
+/// `name` inside `dir`, refusing anything that would leave it
+///
+/// `name` must be relative without `..`, and symlinks are resolved, so one
+/// pointing out of `dir` is refused too. With `existing` the file itself must
+/// exist, otherwise only the directory it goes in.
+fn resolve_backup_path(dir: &Path, name: &str, existing: bool) -> Result<PathBuf, String> {
+    let relative = Path::new(name);
+    if name.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(format!("Path must be inside the backup directory: {}", name));
+    }
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| format!("Backup directory unavailable: {}", e))?;
+
+    let path = dir.join(relative);
+    let resolved = if existing {
+        path.canonicalize()
+    } else {
+        let (parent, file) = (path.parent().unwrap_or(&dir), path.file_name().unwrap_or_default());
+        parent.canonicalize().map(|parent| parent.join(file))
+    };
+    match resolved {
+        Ok(resolved) if resolved.starts_with(&dir) => Ok(resolved),
+        Ok(_) => Err(format!("Path must be inside the backup directory: {}", name)),
+        Err(e) => Err(format!("{}: {}", name, e)),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Whether a change at `changed` (None: anywhere) can affect the value at `path`
+fn touches(changed: Option<&str>, path: &str) -> bool {
+    changed.is_none_or(|c| is_within(c, path) || is_within(path, c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(responses: &[Response]) -> Vec<String> {
+        responses
+            .iter()
+            .map(|r| String::from_utf8(r.encode().to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_recover_after_lag() {
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone());
+        let mut rx = handler.subscribe_updates();
+
+        handler.process(b"SUB doc:*\r\nSET doc:1 a 1\r\nSET doc:2 a 1\r\n");
+        while let Ok(delta) = rx.try_recv() {
+            handler.notifications(delta);
+        }
+
+        // Changes whose deltas the client never receives
+        let doc1 = DocumentId::new("doc:1").unwrap();
+        manager.get(&doc1).unwrap().set("a", Value::from(2i64)).unwrap();
+        manager.delete(&DocumentId::new("doc:2").unwrap()).unwrap();
+        manager.create(DocumentId::new("doc:3").unwrap(), Strategy::Lww, None).unwrap();
+        manager.create(DocumentId::new("other:1").unwrap(), Strategy::Lww, None).unwrap();
+
+        let frames = frames(&handler.recover());
+        assert_eq!(frames.len(), 3, "{:?}", frames);
+        for expected in [
+            " resync doc:1 - lww snapshot ",
+            " deleted doc:2 - lww update ",
+            " resync doc:3 - lww snapshot ",
+        ] {
+            assert!(frames.iter().any(|f| f.contains(expected)), "{:?}", frames);
+        }
+
+        // Nothing changed since: nothing to resend
+        assert!(handler.recover().is_empty());
+    }
+
+    #[test]
+    fn test_subscribe_from_version() {
+        let manager = Arc::new(DocumentManager::new());
+        let mut writer = ConnectionHandler::new("writer".into(), manager.clone());
+        writer.process(b"SET doc:1 a 1\r\nSET doc:1 b 2\r\nSET doc:1 c 3\r\n");
+        let version = manager.get(&DocumentId::new("doc:1").unwrap()).unwrap().version();
+
+        let mut reader = ConnectionHandler::new("reader".into(), manager.clone());
+        let command = format!("SUB doc:* FROM {}\r\nSUB doc:1 PATH c FROM {}\r\n", version - 2, version - 1);
+        let replayed = frames(&reader.process(command.as_bytes()));
+        assert_eq!(replayed.len(), 5, "{:?}", replayed);
+        assert!(replayed[1].starts_with(&format!("#{} updated doc:1 b lww update ", version - 1)));
+        assert!(replayed[2].starts_with(&format!("#{} updated doc:1 c lww update ", version)));
+        assert!(replayed[4].starts_with(&format!("#{} updated doc:1 c lww json 3", version)));
+
+        // Up to date, or unknown to this server: nothing missed, or everything
+        assert_eq!(reader.process(format!("SUB doc:1 FROM {}\r\n", version).as_bytes()).len(), 1);
+        let resynced = frames(&reader.process(format!("SUB doc:1 FROM {}\r\n", version + 10).as_bytes()));
+        assert!(resynced[1].starts_with(&format!("#{} resync doc:1 - lww snapshot ", version)));
+    }
+
+    #[test]
+    fn test_scan_walks_all_keys() {
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager);
+        for i in 0..5 {
+            handler.process(format!("SET doc:{} a 1\r\n", i).as_bytes());
+        }
+
+        let mut keys = Vec::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let responses = handler.process(format!("SCAN {} MATCH doc:* COUNT 2\r\n", cursor).as_bytes());
+            let Response::Array(page) = &responses[0] else {
+                panic!("Expected array, got {:?}", responses);
+            };
+            let (Response::Bulk(next), Response::Array(ids)) = (&page[0], &page[1]) else {
+                panic!("Unexpected page {:?}", page);
+            };
+            keys.extend(ids.iter().map(|id| match id {
+                Response::Bulk(id) => String::from_utf8(id.to_vec()).unwrap(),
+                other => panic!("Unexpected key {:?}", other),
+            }));
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(keys, ["doc:0", "doc:1", "doc:2", "doc:3", "doc:4"]);
+
+        let responses = frames(&handler.process(b"SCAN not-a-cursor\r\n"));
+        assert!(responses[0].starts_with("-ERR INVALID_CURSOR"), "{:?}", responses);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ussl-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_backup_paths_stay_in_backup_dir() {
+        let dir = temp_dir("backup-paths");
+        assert!(resolve_backup_path(&dir, "nightly.ndjson", false).is_ok());
+        assert!(resolve_backup_path(&dir, "../nightly.ndjson", false).is_err());
+        assert!(resolve_backup_path(&dir, "/etc/passwd", true).is_err());
+        assert!(resolve_backup_path(&dir, "", false).is_err());
+        assert!(resolve_backup_path(&dir, "missing/nightly.ndjson", false).is_err());
+        assert!(resolve_backup_path(&dir, "nightly.ndjson", true).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut handler = ConnectionHandler::new("test".into(), Arc::new(DocumentManager::new()));
+        let responses = frames(&handler.process(b"BACKUP TO nightly.ndjson\r\n"));
+        assert!(responses[0].starts_with("-ERR BACKUP_DISABLED"), "{:?}", responses);
+    }
+
+    #[tokio::test]
+    async fn test_backup_to_file_and_restore_from_it() {
+        let dir = temp_dir("backup-file");
+        let mut handler = ConnectionHandler::new("test".into(), Arc::new(DocumentManager::new()))
+            .with_backup_dir(dir.clone());
+
+        // Commands after the backup wait for it to finish
+        let responses = frames(&handler.process(b"SET doc:1 a 1\r\nBACKUP TO nightly.ndjson\r\nPING\r\n"));
+        assert_eq!(responses, ["+OK\r\n"]);
+        assert!(handler.is_busy() && !handler.is_streaming());
+        assert_eq!(frames(&handler.next_reply().await), ["+OK Saved 1 documents to nightly.ndjson\r\n"]);
+        assert_eq!(frames(&handler.next_reply().await), ["+PONG\r\n"]);
+        assert!(!handler.is_busy());
+
+        let manager = Arc::new(DocumentManager::new());
+        let mut restorer = ConnectionHandler::new("test".into(), manager.clone()).with_backup_dir(dir.clone());
+        assert!(restorer.process(b"RESTORE FROM nightly.ndjson\r\n").is_empty());
+        assert_eq!(frames(&restorer.next_reply().await), [":1\r\n"]);
+        assert!(restorer.next_reply().await.is_empty());
+        assert!(manager.get(&DocumentId::new("doc:1").unwrap()).is_ok());
+
+        let responses = frames(&restorer.process(b"RESTORE FROM ../nightly.ndjson\r\n"));
+        assert!(responses[0].starts_with("-ERR INVALID_PATH"), "{:?}", responses);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_stream() {
+        let source = DocumentManager::new();
+        for i in 0..3 {
+            source.create(DocumentId::new(format!("doc:{}", i)).unwrap(), Strategy::CrdtMap, None).unwrap();
+        }
+        let mut ndjson = Vec::new();
+        backup::write_ndjson(&source.backup().unwrap(), &mut ndjson).unwrap();
+        let ndjson = String::from_utf8(ndjson).unwrap().replace('\n', "\r\n");
+
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone());
+        let (first, rest) = ndjson.split_at(ndjson.len() / 2);
+        assert!(handler.process(format!("RESTORE STREAM\r\n{}", first).as_bytes()).is_empty());
+        let responses = frames(&handler.process(format!("{}END\r\nPING\r\n", rest).as_bytes()));
+        assert_eq!(responses, [":3\r\n", "+PONG\r\n"]);
+        assert_eq!(manager.stats().unwrap().document_count, 3);
+
+        // A bad record is reported at the end, and no later line is run as a command
+        let responses = frames(&handler.process(b"RESTORE STREAM\r\nnot json\r\nPING\r\nEND\r\n"));
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].starts_with("-ERR RESTORE_ERROR"), "{:?}", responses);
+    }
+
+    #[test]
+    fn test_restore_stream_large_document() {
+        let source = DocumentManager::new();
+        let id = DocumentId::new("doc:big").unwrap();
+        let doc = source.create(id.clone(), Strategy::Lww, None).unwrap();
+        doc.set("blob", Value::String("x".repeat(4 * 1024 * 1024))).unwrap();
+        let mut ndjson = Vec::new();
+        backup::write_ndjson(&source.backup().unwrap(), &mut ndjson).unwrap();
+        let ndjson = String::from_utf8(ndjson).unwrap().replace('\n', "\r\n");
+
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone());
+        assert!(handler.process(b"RESTORE STREAM\r\n").is_empty());
+        let mut responses = Vec::new();
+        for chunk in ndjson.as_bytes().chunks(64 * 1024) {
+            responses.extend(handler.process(chunk));
+        }
+        responses.extend(handler.process(b"END\r\n"));
+        assert_eq!(frames(&responses), [":1\r\n"]);
+        assert_eq!(manager.get(&id).unwrap().get(None).unwrap(), doc.get(None).unwrap());
+
+        // The usual limit is back once the stream ends
+        let oversized = vec![b'x'; ussl_protocol::parser::MAX_MESSAGE_SIZE + 1];
+        assert!(frames(&handler.process(&oversized))[0].starts_with("-ERR PARSE_ERROR"));
+    }
+
+    #[tokio::test]
+    async fn test_update_log_storage() {
+        let storage = Arc::new(ussl_storage::MemoryStorage::new().with_update_log());
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone()).with_storage(storage.clone());
+
+        let responses = frames(&handler.process(b"SET doc:1 name \"a\"\r\nPUSH doc:1 tags 1\r\nSET doc:1 name \"b\"\r\n"));
+        assert_eq!(responses, ["+OK\r\n", "+OK\r\n", "+OK\r\n"]);
+        // Writes are appended by the persister's workers
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while storage.stats().await.unwrap().log_entries < 3 {
+                tokio::task::yield_now().await;
             }
         })
+        .await
+        .expect("the 3 writes were not appended to the update log within 5s");
+
+        let id = DocumentId::new("doc:1").unwrap();
+        let (meta, data) = storage.load(&id).await.unwrap().unwrap();
+        let loaded = ussl_core::Document::from_snapshot(meta, &data).unwrap();
+        assert_eq!(loaded.get(None).unwrap(), manager.get(&id).unwrap().get(None).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_edits_after_compact_survive_reload() {
+        let storage = Arc::new(ussl_storage::MemoryStorage::new().with_update_log());
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone()).with_storage(storage.clone());
+
+        let responses = frames(&handler.process(
+            b"TEXT.INSERT doc:t 0 \"hello\"\r\nCOMPACT doc:t\r\nTEXT.INSERT doc:t 5 \" world\"\r\n",
+        ));
+        assert_eq!(responses.len(), 3);
+
+        let id = DocumentId::new("doc:t").unwrap();
+        let version = manager.get(&id).unwrap().version();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let stored = storage.load(&id).await.unwrap().map(|(meta, _)| meta.version);
+                if stored == Some(version) {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the edit after the compaction did not reach storage within 5s");
+
+        // The compaction was stored in full, so only the later edit is logged
+        assert_eq!(storage.stats().await.unwrap().log_entries, 1);
+        let (meta, data) = storage.load(&id).await.unwrap().unwrap();
+        let loaded = ussl_core::Document::from_snapshot(meta, &data).unwrap();
+        assert_eq!(loaded.get(None).unwrap(), Value::from("hello world"));
+    }
+
+    #[test]
+    fn test_lastsave_and_info_report_snapshots() {
+        let dir = temp_dir("lastsave");
+        let manager = Arc::new(DocumentManager::new());
+        let mut handler = ConnectionHandler::new("test".into(), manager.clone());
+        assert_eq!(frames(&handler.process(b"LASTSAVE\r\n")), ["$-1\r\n"]);
+
+        let info = backup::write_snapshot(&manager, &dir).unwrap();
+        let responses = frames(&handler.process(b"LASTSAVE\r\nINFO\r\n"));
+        assert_eq!(responses[0], format!(":{}\r\n", info.timestamp / 1000));
+        assert!(responses[1].contains(&format!("\"timestamp\":{}", info.timestamp)), "{:?}", responses);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
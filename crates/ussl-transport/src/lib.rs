@@ -10,6 +10,7 @@ pub mod tcp;
 #[cfg(feature = "websocket")]
 pub mod websocket;
 pub mod handler;
+pub mod persist;
 #[cfg(feature = "tls")]
 pub mod tls;
 pub mod rate_limit;
@@ -20,6 +21,7 @@ pub use tcp::TcpServer;
 #[cfg(feature = "websocket")]
 pub use websocket::WebSocketServer;
 pub use handler::ConnectionHandler;
+pub use persist::Persister;
 #[cfg(feature = "tls")]
 pub use tls::{TlsConfig, TlsError};
 pub use rate_limit::{RateLimiter, RateLimitConfig};
@@ -4,14 +4,14 @@
 //! Metrics are exposed in Prometheus text format via HTTP.
 
 use prometheus::{
-    Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec,
-    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder, Encoder,
+    Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder, Encoder,
 };
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
 use tracing::{error, info};
+use ussl_core::backup::SnapshotInfo;
 
 /// USSL metrics collector
 #[derive(Clone)]
@@ -50,6 +50,14 @@ pub struct Metrics {
     // Backup/Restore metrics
     pub backups_total: IntCounter,
     pub restores_total: IntCounter,
+
+    // Snapshot metrics
+    pub snapshots_total: IntCounter,
+    pub snapshot_errors: IntCounter,
+    pub snapshot_duration_seconds: Histogram,
+    pub last_snapshot_timestamp: IntGauge,
+    pub last_snapshot_documents: IntGauge,
+    pub last_snapshot_bytes: IntGauge,
 }
 
 impl Metrics {
@@ -139,6 +147,32 @@ impl Metrics {
             "ussl_restores_total", "Total restores performed"
         ).unwrap();
 
+        // Snapshot metrics
+        let snapshots_total = IntCounter::new(
+            "ussl_snapshots_total", "Total scheduled snapshots written"
+        ).unwrap();
+
+        let snapshot_errors = IntCounter::new(
+            "ussl_snapshot_errors_total", "Total scheduled snapshots that failed"
+        ).unwrap();
+
+        let snapshot_duration_seconds = Histogram::with_opts(
+            HistogramOpts::new("ussl_snapshot_duration_seconds", "Time to take and write a snapshot")
+                .buckets(vec![0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0]),
+        ).unwrap();
+
+        let last_snapshot_timestamp = IntGauge::new(
+            "ussl_last_snapshot_timestamp_seconds", "Unix time of the last successful snapshot"
+        ).unwrap();
+
+        let last_snapshot_documents = IntGauge::new(
+            "ussl_last_snapshot_documents", "Documents in the last successful snapshot"
+        ).unwrap();
+
+        let last_snapshot_bytes = IntGauge::new(
+            "ussl_last_snapshot_bytes", "Size of the last successful snapshot file"
+        ).unwrap();
+
         // Register all metrics
         registry.register(Box::new(connections_total.clone())).unwrap();
         registry.register(Box::new(connections_active.clone())).unwrap();
@@ -157,6 +191,12 @@ impl Metrics {
         registry.register(Box::new(compaction_bytes_saved.clone())).unwrap();
         registry.register(Box::new(backups_total.clone())).unwrap();
         registry.register(Box::new(restores_total.clone())).unwrap();
+        registry.register(Box::new(snapshots_total.clone())).unwrap();
+        registry.register(Box::new(snapshot_errors.clone())).unwrap();
+        registry.register(Box::new(snapshot_duration_seconds.clone())).unwrap();
+        registry.register(Box::new(last_snapshot_timestamp.clone())).unwrap();
+        registry.register(Box::new(last_snapshot_documents.clone())).unwrap();
+        registry.register(Box::new(last_snapshot_bytes.clone())).unwrap();
 
         Self {
             registry,
@@ -177,6 +217,12 @@ impl Metrics {
             compaction_bytes_saved,
             backups_total,
             restores_total,
+            snapshots_total,
+            snapshot_errors,
+            snapshot_duration_seconds,
+            last_snapshot_timestamp,
+            last_snapshot_documents,
+            last_snapshot_bytes,
         }
     }
 
@@ -213,6 +259,20 @@ impl Metrics {
         self.bytes_sent.inc_by(sent);
     }
 
+    /// Record a successful snapshot
+    pub fn record_snapshot(&self, info: &SnapshotInfo) {
+        self.snapshots_total.inc();
+        self.snapshot_duration_seconds.observe(info.duration_ms as f64 / 1000.0);
+        self.last_snapshot_timestamp.set((info.timestamp / 1000) as i64);
+        self.last_snapshot_documents.set(info.documents as i64);
+        self.last_snapshot_bytes.set(info.bytes as i64);
+    }
+
+    /// Record a failed snapshot
+    pub fn record_snapshot_error(&self) {
+        self.snapshot_errors.inc();
+    }
+
     /// Export metrics in Prometheus text format
     pub fn export(&self) -> String {
         let encoder = TextEncoder::new();
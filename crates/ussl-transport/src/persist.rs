@@ -0,0 +1,240 @@
+//! Ordered background persistence
+//!
+//! Writes are queued instead of spawned one task each, so they reach the
+//! storage in the order the changes were made. Each document always goes
+//! through the same worker; writes for different documents still run in
+//! parallel across workers.
+//!
+//! A lazy `DocumentManager` can use the persister as its backing store, so
+//! that evictions are written in order with everything queued before them.
+
+use parking_lot::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+use ussl_core::manager::Delta;
+use ussl_core::{Backing, Change, Document, DocumentId, DocumentMeta};
+use ussl_storage::{Storage, StorageBacking, StorageError};
+
+/// Number of workers writing to storage
+const WORKERS: usize = 8;
+
+/// A write waiting for its worker
+enum Write {
+    Store { meta: DocumentMeta, data: Vec<u8> },
+    Append { meta: DocumentMeta, changes: Vec<Change> },
+    Delete,
+}
+
+/// A queued write, and who waits for its outcome if anyone
+struct Queued {
+    id: DocumentId,
+    write: Write,
+    done: Option<oneshot::Sender<Result<(), StorageError>>>,
+}
+
+/// Persists documents in the background, in order per document
+///
+/// Shared by every connection writing to the same storage.
+pub struct Persister {
+    storage: Arc<dyn Storage>,
+    /// Reads for the `Backing` implementation, which bypass the queues
+    reads: StorageBacking,
+    queues: Vec<Mutex<mpsc::UnboundedSender<Queued>>>,
+}
+
+impl Persister {
+    /// Start the workers for `storage` (must be called within a Tokio runtime)
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        let queues = (0..WORKERS)
+            .map(|_| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                tokio::spawn(run(storage.clone(), rx));
+                Mutex::new(tx)
+            })
+            .collect();
+        let reads = StorageBacking::new(storage.clone());
+        Self { storage, reads, queues }
+    }
+
+    /// The storage written to
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    /// Take the changes made to a document since its last delta and queue them
+    ///
+    /// With an update-log storage only the changes are appended; otherwise,
+    /// or if the delta carries the full state, the whole document is stored.
+    /// The delta is taken while holding the queue, so concurrent commits of
+    /// a document are queued in the order their changes were taken.
+    pub fn commit(&self, id: &DocumentId, doc: &Document, path: Option<String>) -> Delta {
+        let queue = self.queue(id).lock();
+        let delta = doc.take_delta(path);
+        let write = match doc.changes(&delta) {
+            Some(changes) if self.storage.logs_updates() => Write::Append { meta: doc.meta(), changes },
+            _ => snapshot(doc),
+        };
+        let _ = queue.send(Queued { id: id.clone(), write, done: None });
+        delta
+    }
+
+    /// Queue a write of the whole document
+    pub fn store(&self, id: &DocumentId, doc: &Document) {
+        let queue = self.queue(id).lock();
+        let _ = queue.send(Queued { id: id.clone(), write: snapshot(doc), done: None });
+    }
+
+    /// Queue the removal of a document
+    pub fn delete(&self, id: &DocumentId) {
+        let queue = self.queue(id).lock();
+        let _ = queue.send(Queued { id: id.clone(), write: Write::Delete, done: None });
+    }
+
+    /// Queue a write and block until it, and so every write queued before
+    /// it for the document, has reached the storage
+    fn write_through(&self, id: &DocumentId, write: Write) -> ussl_core::Result<()> {
+        let (done, outcome) = oneshot::channel();
+        self.queue(id)
+            .lock()
+            .send(Queued { id: id.clone(), write, done: Some(done) })
+            .map_err(|_| stopped())?;
+        match tokio::task::block_in_place(|| outcome.blocking_recv()) {
+            Ok(result) => result.map_err(|e| ussl_core::Error::Storage(e.to_string())),
+            Err(_) => Err(stopped()),
+        }
+    }
+
+    fn queue(&self, id: &DocumentId) -> &Mutex<mpsc::UnboundedSender<Queued>> {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        &self.queues[hasher.finish() as usize % self.queues.len()]
+    }
+}
+
+/// Backing store for a lazy `DocumentManager`
+///
+/// Flushes and removals go through the document's queue and return once
+/// written, so an eviction never lands before an older queued write. Like
+/// [`StorageBacking`], this must be used within a multi-threaded runtime.
+impl Backing for Persister {
+    fn load(&self, id: &DocumentId) -> ussl_core::Result<Option<(DocumentMeta, Vec<u8>)>> {
+        self.reads.load(id)
+    }
+
+    fn flush(&self, meta: &DocumentMeta, snapshot: &[u8]) -> ussl_core::Result<()> {
+        let write = Write::Store { meta: meta.clone(), data: snapshot.to_vec() };
+        self.write_through(&meta.id, write)
+    }
+
+    fn delete(&self, id: &DocumentId) -> ussl_core::Result<()> {
+        self.write_through(id, Write::Delete)
+    }
+
+    fn list(&self, pattern: Option<&str>) -> ussl_core::Result<Vec<DocumentId>> {
+        self.reads.list(pattern)
+    }
+
+    fn scan(&self, after: Option<&str>, pattern: Option<&str>, count: usize) -> ussl_core::Result<Vec<DocumentMeta>> {
+        self.reads.scan(after, pattern, count)
+    }
+}
+
+fn stopped() -> ussl_core::Error {
+    ussl_core::Error::Storage("Persister has stopped".into())
+}
+
+fn snapshot(doc: &Document) -> Write {
+    // Metadata first: the snapshot then holds at least what its version says
+    let meta = doc.meta();
+    Write::Store { meta, data: doc.encode_snapshot() }
+}
+
+async fn run(storage: Arc<dyn Storage>, mut writes: mpsc::UnboundedReceiver<Queued>) {
+    while let Some(Queued { id, write, done }) = writes.recv().await {
+        let (result, failure) = match write {
+            Write::Store { meta, data } => {
+                (storage.store(&id, &meta, &data).await, "Failed to persist document")
+            }
+            Write::Append { meta, changes } => {
+                (storage.append(&id, &meta, &changes).await, "Failed to append document changes")
+            }
+            Write::Delete => {
+                (storage.delete(&id).await.map(|_| ()), "Failed to remove persisted document")
+            }
+        };
+        // A waiting caller reports the error itself
+        match done {
+            Some(done) => {
+                let _ = done.send(result);
+            }
+            None => {
+                if let Err(e) = result {
+                    warn!(doc_id = %id, error = %e, "{}", failure);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ussl_core::{CacheConfig, DocumentManager, EvictionPolicy, Strategy, Value};
+    use ussl_storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn test_writes_keep_their_order() {
+        let storage = Arc::new(MemoryStorage::new().with_update_log());
+        let persister = Persister::new(storage.clone());
+        let id = DocumentId::new("test:order").unwrap();
+        let doc = Document::new(id.clone(), Strategy::Lww);
+
+        for i in 0..50i64 {
+            doc.set("n", Value::from(i)).unwrap();
+            persister.commit(&id, &doc, Some("n".into()));
+            if i % 10 == 0 {
+                persister.store(&id, &doc);
+            }
+        }
+        drop(persister);
+
+        let loaded = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                match storage.load(&id).await.unwrap() {
+                    Some((meta, data)) if meta.version == doc.version() => break (meta, data),
+                    _ => tokio::task::yield_now().await,
+                }
+            }
+        })
+        .await
+        .expect("queued writes did not reach storage");
+        let loaded = Document::from_snapshot(loaded.0, &loaded.1).unwrap();
+        assert_eq!(loaded.get(Some("n")).unwrap(), Value::from(49i64));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_eviction_lands_after_queued_writes() {
+        let storage = Arc::new(MemoryStorage::new().with_update_log());
+        let persister = Arc::new(Persister::new(storage.clone()));
+        let manager = DocumentManager::new()
+            .with_backing(persister.clone(), CacheConfig::new(0, EvictionPolicy::Lru));
+        let id = DocumentId::new("test:evict").unwrap();
+
+        let doc = manager.create(id.clone(), Strategy::Lww, None).unwrap();
+        for i in 0..50i64 {
+            doc.set("n", Value::from(i)).unwrap();
+            persister.commit(&id, &doc, Some("n".into()));
+        }
+        let version = doc.version();
+        drop(doc);
+
+        // The flush waits for the queued writes, so storage is current at once
+        assert_eq!(manager.evict().unwrap(), 1);
+        let (meta, _) = storage.load(&id).await.unwrap().unwrap();
+        assert_eq!(meta.version, version);
+        assert_eq!(manager.get(&id).unwrap().get(Some("n")).unwrap(), Value::from(49i64));
+    }
+}
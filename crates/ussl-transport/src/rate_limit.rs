@@ -1,7 +1,7 @@
 //! Rate limiting using Token Bucket algorithm
 
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{Duration, Instant};
+use std::time::Instant;
 use parking_lot::Mutex;
 
 /// Rate limiter configuration
@@ -142,6 +142,7 @@ impl RateLimiter {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
     use std::thread;
 
     #[test]
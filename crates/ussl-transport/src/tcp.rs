@@ -1,15 +1,17 @@
 //! TCP transport for USSL
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tracing::{error, info, warn};
 use ussl_core::DocumentManager;
 use ussl_protocol::Response;
 use ussl_storage::Storage;
+use crate::persist::Persister;
 
 use crate::handler::ConnectionHandler;
 use crate::rate_limit::RateLimitConfig;
@@ -23,8 +25,9 @@ pub struct TcpServer {
     addr: SocketAddr,
     client_counter: AtomicU64,
     password: Option<String>,
-    storage: Option<Arc<dyn Storage>>,
+    persister: Option<Arc<Persister>>,
     rate_limit: Option<RateLimitConfig>,
+    backup_dir: Option<PathBuf>,
     #[cfg(feature = "tls")]
     tls_config: Option<TlsConfig>,
 }
@@ -36,8 +39,9 @@ impl TcpServer {
             addr,
             client_counter: AtomicU64::new(0),
             password: None,
-            storage: None,
+            persister: None,
             rate_limit: None,
+            backup_dir: None,
             #[cfg(feature = "tls")]
             tls_config: None,
         }
@@ -50,16 +54,22 @@ impl TcpServer {
             addr,
             client_counter: AtomicU64::new(0),
             password: Some(password),
-            storage: None,
+            persister: None,
             rate_limit: None,
+            backup_dir: None,
             #[cfg(feature = "tls")]
             tls_config: None,
         }
     }
 
     /// Set the storage backend for persistence
-    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
-        self.storage = Some(storage);
+    pub fn with_storage(self, storage: Arc<dyn Storage>) -> Self {
+        self.with_persister(Arc::new(Persister::new(storage)))
+    }
+
+    /// Persist through `persister`, shared with other servers
+    pub fn with_persister(mut self, persister: Arc<Persister>) -> Self {
+        self.persister = Some(persister);
         self
     }
 
@@ -69,6 +79,12 @@ impl TcpServer {
         self
     }
 
+    /// Allow `BACKUP TO` and `RESTORE FROM` on files inside `dir`
+    pub fn with_backup_dir(mut self, dir: PathBuf) -> Self {
+        self.backup_dir = Some(dir);
+        self
+    }
+
     /// Enable TLS with the given configuration
     #[cfg(feature = "tls")]
     pub fn with_tls(mut self, tls_config: TlsConfig) -> Self {
@@ -103,8 +119,9 @@ impl TcpServer {
                     );
                     let manager = self.manager.clone();
                     let password = self.password.clone();
-                    let storage = self.storage.clone();
+                    let persister = self.persister.clone();
                     let rate_limit = self.rate_limit.clone();
+                    let backup_dir = self.backup_dir.clone();
 
                     #[cfg(feature = "tls")]
                     let tls_config = self.tls_config.clone();
@@ -115,7 +132,7 @@ impl TcpServer {
                             if let Some(tls) = tls_config {
                                 match tls.acceptor().accept(stream).await {
                                     Ok(tls_stream) => {
-                                        if let Err(e) = handle_connection(tls_stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                                        if let Err(e) = handle_connection(tls_stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                             error!(client = %client_id, error = %e, "TLS connection error");
                                         }
                                     }
@@ -124,7 +141,7 @@ impl TcpServer {
                                     }
                                 }
                             } else {
-                                if let Err(e) = handle_connection(stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                                if let Err(e) = handle_connection(stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                     error!(client = %client_id, error = %e, "Connection error");
                                 }
                             }
@@ -132,7 +149,7 @@ impl TcpServer {
 
                         #[cfg(not(feature = "tls"))]
                         {
-                            if let Err(e) = handle_connection(stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                            if let Err(e) = handle_connection(stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                 error!(client = %client_id, error = %e, "Connection error");
                             }
                         }
@@ -152,8 +169,9 @@ async fn handle_connection<S>(
     client_id: String,
     manager: Arc<DocumentManager>,
     password: Option<String>,
-    storage: Option<Arc<dyn Storage>>,
+    persister: Option<Arc<Persister>>,
     rate_limit: Option<RateLimitConfig>,
+    backup_dir: Option<PathBuf>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
 where
     S: AsyncRead + AsyncWrite + Unpin,
@@ -166,21 +184,25 @@ where
         Some(pwd) => ConnectionHandler::with_auth(client_id.clone(), manager, pwd),
         None => ConnectionHandler::new(client_id.clone(), manager),
     };
-    let handler = match storage {
-        Some(s) => handler.with_storage(s),
+    let handler = match persister {
+        Some(p) => handler.with_persister(p),
         None => handler,
     };
-    let mut handler = match rate_limit {
+    let handler = match rate_limit {
         Some(config) => handler.with_rate_limit(config),
         None => handler,
     };
+    let mut handler = match backup_dir {
+        Some(dir) => handler.with_backup_dir(dir),
+        None => handler,
+    };
     let mut buf = vec![0u8; 4096];
     let mut update_rx = handler.subscribe_updates();
 
     loop {
         tokio::select! {
-            // Handle incoming data from client
-            result = read_half.read(&mut buf) => {
+            // Handle incoming data from client, unless a command is still running
+            result = read_half.read(&mut buf), if !handler.is_busy() => {
                 match result {
                     Ok(0) => {
                         info!(client = %client_id, "Client disconnected");
@@ -206,21 +228,46 @@ where
                 }
             }
 
-            // Handle updates for subscriptions
-            result = update_rx.recv() => {
+            // Send the replies of a command running in the background
+            responses = handler.next_reply(), if handler.is_busy() => {
+                for response in responses {
+                    write_half.write_all(&response.encode()).await?;
+
+                    if matches!(response, Response::Ok(Some(ref msg)) if msg == "Goodbye") {
+                        handler.cleanup();
+                        return Ok(());
+                    }
+                }
+            }
+
+            // Handle updates for subscriptions, unless a reply is being streamed
+            result = update_rx.recv(), if !handler.is_streaming() => {
                 match result {
                     Ok(delta) => {
-                        if handler.matches_subscription(&delta) {
-                            let response = Response::delta(delta.version, delta.data);
-                            let data = response.encode();
-                            if let Err(e) = write_half.write_all(&data).await {
-                                error!(client = %client_id, error = %e, "Write error");
-                                break;
-                            }
+                        let data: Vec<u8> = handler
+                            .notifications(delta)
+                            .iter()
+                            .flat_map(|response| response.encode())
+                            .collect();
+                        if data.is_empty() {
+                            continue;
+                        }
+                        if let Err(e) = write_half.write_all(&data).await {
+                            error!(client = %client_id, error = %e, "Write error");
+                            break;
                         }
                     }
                     Err(broadcast::error::RecvError::Lagged(n)) => {
-                        warn!(client = %client_id, missed = n, "Client lagged behind updates");
+                        warn!(client = %client_id, missed = n, "Client lagged behind updates, resyncing");
+                        let data: Vec<u8> = handler
+                            .recover()
+                            .iter()
+                            .flat_map(|response| response.encode())
+                            .collect();
+                        if let Err(e) = write_half.write_all(&data).await {
+                            error!(client = %client_id, error = %e, "Write error");
+                            break;
+                        }
                     }
                     Err(broadcast::error::RecvError::Closed) => {
                         break;
@@ -238,6 +285,7 @@ where
 mod tests {
     use super::*;
     use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpStream;
 
     #[tokio::test]
     async fn test_tcp_ping_pong() {
@@ -251,7 +299,7 @@ mod tests {
         let manager_clone = manager.clone();
         let server = tokio::spawn(async move {
             let (stream, _) = listener.accept().await.unwrap();
-            handle_connection(stream, "test".into(), manager_clone, None, None, None).await.unwrap();
+            handle_connection(stream, "test".into(), manager_clone, None, None, None, None).await.unwrap();
         });
 
         // Connect client
@@ -273,4 +321,93 @@ mod tests {
 
         server.await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_tcp_path_subscription() {
+        let manager = Arc::new(DocumentManager::new());
+        let id = ussl_core::DocumentId::new("device:1").unwrap();
+        manager.create(id, ussl_core::Strategy::Lww, None).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bound_addr = listener.local_addr().unwrap();
+
+        let manager_clone = manager.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, "test".into(), manager_clone, None, None, None, None).await.unwrap();
+        });
+
+        let client = TcpStream::connect(bound_addr).await.unwrap();
+        let (read, mut write) = tokio::io::split(client);
+        let mut lines = BufReader::new(read).lines();
+
+        write.write_all(b"SUB device:* PATH battery\r\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "+OK Subscribed to device:* PATH battery");
+
+        // A change outside the subtree is not sent
+        write.write_all(b"SET device:1 firmware 2\r\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "+OK");
+
+        write.write_all(b"SET device:1 battery 80\r\n").await.unwrap();
+        let mut frame = None;
+        let mut ok = false;
+        while frame.is_none() || !ok {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line.starts_with('#') {
+                frame = Some(line);
+            } else {
+                assert_eq!(line, "+OK");
+                ok = true;
+            }
+        }
+        let frame = frame.unwrap();
+        assert!(frame.ends_with(" updated device:1 battery lww json 80"), "{}", frame);
+
+        write.write_all(b"QUIT\r\n").await.unwrap();
+        drop(write);
+        drop(lines);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tcp_backup_stream() {
+        let manager = Arc::new(DocumentManager::new());
+        for i in 0..3 {
+            let id = ussl_core::DocumentId::new(format!("doc:{}", i)).unwrap();
+            manager.create(id, ussl_core::Strategy::Lww, None).unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bound_addr = listener.local_addr().unwrap();
+
+        let manager_clone = manager.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, "test".into(), manager_clone, None, None, None, None).await.unwrap();
+        });
+
+        let client = TcpStream::connect(bound_addr).await.unwrap();
+        let (read, mut write) = tokio::io::split(client);
+        let mut lines = BufReader::new(read).lines();
+
+        // The array arrives whole, and the PING reply only after it
+        write.write_all(b"BACKUP STREAM\r\nPING\r\n").await.unwrap();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "*4");
+        let mut records = Vec::new();
+        for _ in 0..4 {
+            let len = lines.next_line().await.unwrap().unwrap();
+            let record = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(len, format!("${}", record.len()));
+            records.push(record);
+        }
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "+PONG");
+
+        let ndjson = records.join("\n");
+        let restored = DocumentManager::new();
+        let ids = ussl_core::backup::read_ndjson(&restored, ndjson.as_bytes()).unwrap();
+        assert_eq!(ids.len(), 3);
+
+        write.write_all(b"QUIT\r\n").await.unwrap();
+        server.await.unwrap();
+    }
 }
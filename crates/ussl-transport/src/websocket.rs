@@ -1,17 +1,19 @@
 //! WebSocket transport for USSL
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use futures_util::{SinkExt, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::broadcast;
 use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
 use tracing::{error, info, warn};
 use ussl_core::DocumentManager;
 use ussl_protocol::Response;
 use ussl_storage::Storage;
+use crate::persist::Persister;
 
 use crate::handler::ConnectionHandler;
 use crate::rate_limit::RateLimitConfig;
@@ -25,8 +27,9 @@ pub struct WebSocketServer {
     addr: SocketAddr,
     client_counter: AtomicU64,
     password: Option<String>,
-    storage: Option<Arc<dyn Storage>>,
+    persister: Option<Arc<Persister>>,
     rate_limit: Option<RateLimitConfig>,
+    backup_dir: Option<PathBuf>,
     #[cfg(feature = "tls")]
     tls_config: Option<TlsConfig>,
 }
@@ -38,8 +41,9 @@ impl WebSocketServer {
             addr,
             client_counter: AtomicU64::new(0),
             password: None,
-            storage: None,
+            persister: None,
             rate_limit: None,
+            backup_dir: None,
             #[cfg(feature = "tls")]
             tls_config: None,
         }
@@ -52,16 +56,22 @@ impl WebSocketServer {
             addr,
             client_counter: AtomicU64::new(0),
             password: Some(password),
-            storage: None,
+            persister: None,
             rate_limit: None,
+            backup_dir: None,
             #[cfg(feature = "tls")]
             tls_config: None,
         }
     }
 
     /// Set the storage backend for persistence
-    pub fn with_storage(mut self, storage: Arc<dyn Storage>) -> Self {
-        self.storage = Some(storage);
+    pub fn with_storage(self, storage: Arc<dyn Storage>) -> Self {
+        self.with_persister(Arc::new(Persister::new(storage)))
+    }
+
+    /// Persist through `persister`, shared with other servers
+    pub fn with_persister(mut self, persister: Arc<Persister>) -> Self {
+        self.persister = Some(persister);
         self
     }
 
@@ -71,6 +81,12 @@ impl WebSocketServer {
         self
     }
 
+    /// Allow `BACKUP TO` and `RESTORE FROM` on files inside `dir`
+    pub fn with_backup_dir(mut self, dir: PathBuf) -> Self {
+        self.backup_dir = Some(dir);
+        self
+    }
+
     /// Enable TLS with the given configuration (wss://)
     #[cfg(feature = "tls")]
     pub fn with_tls(mut self, tls_config: TlsConfig) -> Self {
@@ -106,8 +122,9 @@ impl WebSocketServer {
                     );
                     let manager = self.manager.clone();
                     let password = self.password.clone();
-                    let storage = self.storage.clone();
+                    let persister = self.persister.clone();
                     let rate_limit = self.rate_limit.clone();
+                    let backup_dir = self.backup_dir.clone();
 
                     #[cfg(feature = "tls")]
                     let tls_config = self.tls_config.clone();
@@ -121,7 +138,7 @@ impl WebSocketServer {
                                     Ok(tls_stream) => {
                                         match accept_async(tls_stream).await {
                                             Ok(ws_stream) => {
-                                                if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                                                if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                                     error!(client = %client_id, error = %e, "WSS connection error");
                                                 }
                                             }
@@ -137,7 +154,7 @@ impl WebSocketServer {
                             } else {
                                 match accept_async(stream).await {
                                     Ok(ws_stream) => {
-                                        if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                                        if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                             error!(client = %client_id, error = %e, "WebSocket connection error");
                                         }
                                     }
@@ -152,7 +169,7 @@ impl WebSocketServer {
                         {
                             match accept_async(stream).await {
                                 Ok(ws_stream) => {
-                                    if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, storage, rate_limit).await {
+                                    if let Err(e) = handle_ws_connection(ws_stream, client_id.clone(), manager, password, persister, rate_limit, backup_dir).await {
                                         error!(client = %client_id, error = %e, "WebSocket connection error");
                                     }
                                 }
@@ -177,8 +194,9 @@ async fn handle_ws_connection<S>(
     client_id: String,
     manager: Arc<DocumentManager>,
     password: Option<String>,
-    storage: Option<Arc<dyn Storage>>,
+    persister: Option<Arc<Persister>>,
     rate_limit: Option<RateLimitConfig>,
+    backup_dir: Option<PathBuf>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
 where
     S: AsyncRead + AsyncWrite + Unpin,
@@ -191,20 +209,24 @@ where
         Some(pwd) => ConnectionHandler::with_auth(client_id.clone(), manager, pwd),
         None => ConnectionHandler::new(client_id.clone(), manager),
     };
-    let handler = match storage {
-        Some(s) => handler.with_storage(s),
+    let handler = match persister {
+        Some(p) => handler.with_persister(p),
         None => handler,
     };
-    let mut handler = match rate_limit {
+    let handler = match rate_limit {
         Some(config) => handler.with_rate_limit(config),
         None => handler,
     };
+    let mut handler = match backup_dir {
+        Some(dir) => handler.with_backup_dir(dir),
+        None => handler,
+    };
     let mut update_rx = handler.subscribe_updates();
 
-    loop {
+    'conn: loop {
         tokio::select! {
-            // Handle incoming WebSocket messages
-            msg = read.next() => {
+            // Handle incoming WebSocket messages, unless a command is still running
+            msg = read.next(), if !handler.is_busy() => {
                 match msg {
                     Some(Ok(Message::Text(text))) => {
                         let mut data = text.into_bytes();
@@ -217,7 +239,7 @@ where
                         for response in responses {
                             let encoded = response.encode();
                             let text = String::from_utf8_lossy(&encoded).to_string();
-                            write.send(Message::Text(text.into())).await?;
+                            write.send(Message::Text(text)).await?;
 
                             // Check for QUIT
                             if matches!(response, Response::Ok(Some(ref msg)) if msg == "Goodbye") {
@@ -231,7 +253,7 @@ where
                         let responses = handler.process(&data);
                         for response in responses {
                             let encoded = response.encode();
-                            write.send(Message::Binary(encoded.to_vec().into())).await?;
+                            write.send(Message::Binary(encoded.to_vec())).await?;
                         }
                     }
                     Some(Ok(Message::Ping(data))) => {
@@ -251,22 +273,43 @@ where
                 }
             }
 
-            // Handle updates for subscriptions
-            result = update_rx.recv() => {
+            // Send the replies of a command running in the background
+            responses = handler.next_reply(), if handler.is_busy() => {
+                for response in responses {
+                    let encoded = response.encode();
+                    let text = String::from_utf8_lossy(&encoded).to_string();
+                    write.send(Message::Text(text)).await?;
+
+                    if matches!(response, Response::Ok(Some(ref msg)) if msg == "Goodbye") {
+                        handler.cleanup();
+                        return Ok(());
+                    }
+                }
+            }
+
+            // Handle updates for subscriptions, unless a reply is being streamed
+            result = update_rx.recv(), if !handler.is_streaming() => {
                 match result {
                     Ok(delta) => {
-                        if handler.matches_subscription(&delta) {
-                            let response = Response::delta(delta.version, delta.data);
+                        for response in handler.notifications(delta) {
                             let encoded = response.encode();
                             let text = String::from_utf8_lossy(&encoded).to_string();
-                            if let Err(e) = write.send(Message::Text(text.into())).await {
+                            if let Err(e) = write.send(Message::Text(text)).await {
                                 error!(client = %client_id, error = %e, "WebSocket write error");
-                                break;
+                                break 'conn;
                             }
                         }
                     }
                     Err(broadcast::error::RecvError::Lagged(n)) => {
-                        warn!(client = %client_id, missed = n, "WebSocket client lagged behind updates");
+                        warn!(client = %client_id, missed = n, "WebSocket client lagged behind updates, resyncing");
+                        for response in handler.recover() {
+                            let encoded = response.encode();
+                            let text = String::from_utf8_lossy(&encoded).to_string();
+                            if let Err(e) = write.send(Message::Text(text)).await {
+                                error!(client = %client_id, error = %e, "WebSocket write error");
+                                break 'conn;
+                            }
+                        }
                     }
                     Err(broadcast::error::RecvError::Closed) => {
                         break;
@@ -69,6 +69,7 @@ async fn embedded_example() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[allow(dead_code)]
 async fn tcp_client_example() -> Result<(), Box<dyn std::error::Error>> {
     let addr: SocketAddr = "127.0.0.1:6380".parse()?;
     let mut stream = TcpStream::connect(addr).await?;
@@ -80,7 +81,7 @@ async fn tcp_client_example() -> Result<(), Box<dyn std::error::Error>> {
         writer: &mut tokio::net::tcp::WriteHalf<'_>,
         reader: &mut BufReader<tokio::net::tcp::ReadHalf<'_>>,
         cmd: &str,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<String, Box<dyn std::error::Error>> {
         println!("> {}", cmd);
         writer.write_all(format!("{}\r\n", cmd).as_bytes()).await?;
 
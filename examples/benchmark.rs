@@ -428,7 +428,6 @@ async fn bench_connections(addr: SocketAddr, max_clients: usize, password: Optio
     let failed = Arc::new(AtomicU64::new(0));
 
     for _ in 0..max_clients {
-        let addr = addr;
         let successful = successful.clone();
         let failed = failed.clone();
         let password = password.clone();
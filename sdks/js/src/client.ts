@@ -8,8 +8,10 @@ import type {
   USSLOptions,
   ConnectionState,
   DocumentOptions,
+  DocumentEvent,
   Strategy,
   Delta,
+  Value,
 } from './types';
 
 const DEFAULT_OPTIONS: Required<USSLOptions> = {
@@ -220,17 +222,28 @@ export class USSLClient {
   }
 
   private handleDelta(line: string): void {
-    // Format: #<version> <base64-data>
-    const match = line.match(/^#(\d+)\s+(.+)$/);
+    // Format: #<version> <event> <document-id> <path> <strategy> <encoding> <base64-data>
+    // JSON payloads (path subscriptions) may contain spaces, so take the rest of the line.
+    // A path with spaces, an empty path or a literal "-" arrives in double quotes.
+    const match = line.match(/^#(\d+)\s+(\S+)\s+(\S+)\s+("[^"]*"|\S+)\s+(\S+)\s+(\S+)\s+(.+)$/);
     if (!match) return;
 
-    const version = parseInt(match[1], 10);
-    const data = this.base64ToBytes(match[2]);
-
-    // Notify all subscribed documents
-    for (const doc of this.documents.values()) {
-      doc._handleDelta({ documentId: doc.id, version, data });
-    }
+    const [, versionStr, event, documentId, rawPath, strategy, encoding, payload] = match;
+    const path = rawPath.startsWith('"') ? rawPath.slice(1, -1) : rawPath === '-' ? undefined : rawPath;
+    const doc = this.documents.get(documentId);
+    if (!doc) return;
+
+    doc._handleDelta({
+      event: event as DocumentEvent,
+      documentId,
+      version: parseInt(versionStr, 10),
+      path,
+      strategy: strategy as Strategy,
+      ...(encoding === 'json'
+        ? { data: new Uint8Array(), value: JSON.parse(payload) as Value }
+        : { data: this.base64ToBytes(payload) }),
+      snapshot: encoding === 'snapshot',
+    });
   }
 
   private handleDisconnect(): void {
@@ -21,6 +21,8 @@ export class Document {
   private subscribers = new Set<SubscribeCallback>();
   private localCache: Value = null;
   private subscribed = false;
+  /** Version of the last delta received, to resume from after reconnecting */
+  private lastVersion?: number;
 
   /** Document ID */
   public readonly id: string;
@@ -121,6 +123,7 @@ export class Document {
    */
   _handleDelta(delta: Delta): void {
     if (delta.documentId !== this.id) return;
+    this.lastVersion = delta.version;
 
     // Refresh from server and notify
     this.get().then((value) => {
@@ -134,7 +137,8 @@ export class Document {
    */
   _resubscribe(): void {
     if (this.subscribed) {
-      this.client.send(`SUB ${this.id}`).catch(console.error);
+      const from = this.lastVersion !== undefined ? ` FROM ${this.lastVersion}` : '';
+      this.client.send(`SUB ${this.id}${from}`).catch(console.error);
     }
   }
 
@@ -25,5 +25,6 @@ export type {
   DocumentOptions,
   Value,
   Delta,
+  DocumentEvent,
   PresenceData,
 } from './types';
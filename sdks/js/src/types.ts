@@ -19,12 +19,22 @@ export type Value =
   | Value[]
   | { [key: string]: Value };
 
+/** What happened to a document */
+export type DocumentEvent = 'created' | 'updated' | 'deleted' | 'expired' | 'restored' | 'resync';
+
 /** Delta update from server */
 export interface Delta {
+  event: DocumentEvent;
   documentId: string;
   version: number;
   path?: string;
+  strategy: Strategy;
+  /** Yjs update (v1 encoding), or the full state if `snapshot` is set */
   data: Uint8Array;
+  /** `data` is the full document state rather than an incremental update */
+  snapshot: boolean;
+  /** Value now at `path`, for path subscriptions (`data` is then empty) */
+  value?: Value;
 }
 
 /** Presence data for a client */